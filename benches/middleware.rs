@@ -0,0 +1,121 @@
+//! Per-request overhead of the middleware
+//!
+//! Run with `cargo bench`. Requests are driven through an in-memory service, so every
+//! measurement includes actix-web's routing and the construction of the test request;
+//! compare against `request/baseline` for the cost of the middleware alone.
+//!
+//! Run with `cargo bench --features fast-rng` to compare the operating system RNG
+//! with the per-thread CSPRNG (the `*_fast_rng` benchmarks).
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::test::{self, TestRequest};
+use actix_web::{rt::SystemRunner, web, App, HttpResponse};
+use actix_web_request_uuid::{
+    get_current_request_id, set_current_request_id, Alphabet, RequestID, RequestIDMessage,
+    RequestIDMiddleware, REQUEST_ID_HEADER,
+};
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion};
+
+fn bench_service<S, R, B>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    rt: &SystemRunner,
+    name: &str,
+    app: &S,
+    make_request: impl Fn() -> R,
+) where
+    S: Service<R, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    group.bench_function(name, |b| {
+        b.iter(|| rt.block_on(app.call(make_request())).unwrap())
+    });
+}
+
+fn request() -> TestRequest {
+    TestRequest::get().uri("/")
+}
+
+fn bench_requests(c: &mut Criterion) {
+    let rt = actix_web::rt::System::new();
+    let mut group = c.benchmark_group("request");
+
+    let baseline = rt.block_on(test::init_service(
+        App::new().route("/", web::get().to(HttpResponse::Ok)),
+    ));
+    bench_service(&mut group, &rt, "baseline", &baseline, || {
+        request().to_request()
+    });
+
+    #[allow(unused_mut)]
+    let mut middlewares = vec![
+        ("uuid", RequestIDMiddleware::new()),
+        ("simple_uuid", RequestIDMiddleware::new().with_simple_uuid()),
+        ("uuid_v7", RequestIDMiddleware::new().with_uuid_v7()),
+        (
+            "base62",
+            RequestIDMiddleware::new().with_compact_id(Alphabet::Base62),
+        ),
+        ("nanoid", RequestIDMiddleware::new().with_nanoid()),
+        ("ksuid", RequestIDMiddleware::new().with_ksuid()),
+        (
+            "namespaced_length",
+            RequestIDMiddleware::new()
+                .with_prefix("api")
+                .with_id_length(16),
+        ),
+    ];
+    #[cfg(feature = "fast-rng")]
+    middlewares.extend([
+        ("uuid_fast_rng", RequestIDMiddleware::new().with_fast_rng()),
+        (
+            "uuid_v7_fast_rng",
+            RequestIDMiddleware::new().with_uuid_v7().with_fast_rng(),
+        ),
+        (
+            "base62_fast_rng",
+            RequestIDMiddleware::new()
+                .with_compact_id(Alphabet::Base62)
+                .with_fast_rng(),
+        ),
+    ]);
+    for (name, middleware) in middlewares {
+        let app = rt.block_on(test::init_service(
+            App::new()
+                .wrap(middleware)
+                .route("/", web::get().to(HttpResponse::Ok)),
+        ));
+        bench_service(&mut group, &rt, name, &app, || request().to_request());
+    }
+
+    let app = rt.block_on(test::init_service(
+        App::new()
+            .wrap(RequestIDMiddleware::new().with_propagation())
+            .route("/", web::get().to(HttpResponse::Ok)),
+    ));
+    bench_service(&mut group, &rt, "propagated", &app, || {
+        request()
+            .insert_header((REQUEST_ID_HEADER, "7d3f6c1e-8a2b-4c5d-9e0f-1a2b3c4d5e6f"))
+            .to_request()
+    });
+
+    group.finish();
+}
+
+fn bench_request_id(c: &mut Criterion) {
+    let mut group = c.benchmark_group("request_id");
+    let id = TestRequest::default().to_http_request().request_id();
+
+    group.bench_function("clone", |b| b.iter(|| id.clone()));
+    group.bench_function("to_bytes", |b| b.iter(|| id.to_bytes()));
+
+    set_current_request_id(id.as_str());
+    group.bench_function("get_current", |b| b.iter(get_current_request_id));
+    group.bench_function("current", |b| b.iter(RequestID::current));
+    group.bench_function("with_current", |b| {
+        b.iter(|| RequestID::with_current(|id| id.map(|id| id.as_str().len())))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_requests, bench_request_id);
+criterion_main!(benches);
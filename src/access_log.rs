@@ -0,0 +1,714 @@
+//! Structured access log keyed by request ID
+//!
+//! The middleware writes one line per request, using the same [`RequestID`] it sends
+//! in the response header, so access logs and application logs can be joined without
+//! coordinating with actix's `Logger`.
+//!
+//! [`RequestID`]: crate::RequestID
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::USER_AGENT;
+use actix_web::http::{Method, StatusCode};
+
+use crate::RequestID;
+
+/// Output format of access log lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line, e.g. `{"id":"...","status":200}`
+    Json,
+    /// Space separated `key=value` pairs, e.g. `id=... status=200`
+    Logfmt,
+}
+
+/// Field of an access log line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogField {
+    /// Request ID, as sent in the response header (`id`)
+    Id,
+    /// HTTP method (`method`)
+    Method,
+    /// Request path without the query string (`path`)
+    Path,
+    /// Response status code (`status`)
+    Status,
+    /// Time until the response body was sent, in milliseconds (`latency_ms`)
+    Latency,
+    /// Number of response body bytes sent (`bytes`)
+    Bytes,
+    /// IP address of the peer (`peer`)
+    Peer,
+    /// `User-Agent` request header (`user_agent`)
+    UserAgent,
+}
+
+impl LogField {
+    /// All fields, in the default order
+    pub const ALL: [LogField; 8] = [
+        LogField::Id,
+        LogField::Method,
+        LogField::Path,
+        LogField::Status,
+        LogField::Latency,
+        LogField::Bytes,
+        LogField::Peer,
+        LogField::UserAgent,
+    ];
+
+    /// Get the key of this field in log lines
+    pub fn key(&self) -> &'static str {
+        match self {
+            LogField::Id => "id",
+            LogField::Method => "method",
+            LogField::Path => "path",
+            LogField::Status => "status",
+            LogField::Latency => "latency_ms",
+            LogField::Bytes => "bytes",
+            LogField::Peer => "peer",
+            LogField::UserAgent => "user_agent",
+        }
+    }
+}
+
+/// Destination of access log lines
+///
+/// Implemented for closures, so any logger or channel can be used as a sink:
+///
+/// ```rust
+/// use actix_web_request_uuid::{AccessLog, LogFormat};
+///
+/// let log = AccessLog::new(LogFormat::Logfmt, |line: &str| eprintln!("{}", line));
+/// ```
+pub trait LogSink: Send + Sync {
+    /// Write one line, without a trailing newline
+    ///
+    /// Called on the request path; implementations must not block for long and
+    /// cannot fail the request, so write errors are theirs to handle.
+    fn write_line(&self, line: &str);
+}
+
+impl<F: Fn(&str) + Send + Sync> LogSink for F {
+    fn write_line(&self, line: &str) {
+        self(line)
+    }
+}
+
+/// Sink writing to standard output
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn write_line(&self, line: &str) {
+        let _ = writeln!(io::stdout().lock(), "{}", line);
+    }
+}
+
+/// Sink sending lines to a channel, e.g. for a background writer thread or tests
+#[derive(Debug)]
+pub struct ChannelSink {
+    sender: Mutex<Sender<String>>,
+}
+
+impl ChannelSink {
+    /// Create a sink sending to `sender`
+    ///
+    /// Lines are dropped once the receiver has been dropped.
+    pub fn new(sender: Sender<String>) -> Self {
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+}
+
+impl LogSink for ChannelSink {
+    fn write_line(&self, line: &str) {
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send(line.to_string());
+        }
+    }
+}
+
+/// Sink appending to a file, with optional size-based rotation
+///
+/// Lines are queued to a dedicated writer thread, which buffers them and flushes the
+/// file whenever the queue runs empty, so requests never wait for file I/O. Up to
+/// 8192 lines are queued; further lines are dropped until the writer catches up.
+/// Dropping the sink writes the remaining lines and stops the thread.
+///
+/// When rotation is enabled and a line would grow the file beyond the limit, the
+/// file is renamed to `<path>.1`, existing rotated files move up by one, files
+/// beyond the configured count are deleted, and a new file is started.
+///
+/// Write errors and dropped lines are reported to the handler registered with
+/// [`FileSink::on_error`] and otherwise ignored.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// use actix_web_request_uuid::{AccessLog, FileSink, LogFormat};
+///
+/// let sink = FileSink::new("access.log")?
+///     .with_rotation(10 * 1024 * 1024, 5)
+///     .on_error(|e| log::warn!("access log: {}", e));
+/// let log = AccessLog::new(LogFormat::Json, sink);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct FileSink {
+    sender: Option<SyncSender<Command>>,
+    writer: Option<JoinHandle<()>>,
+    on_error: Option<ErrorHandler>,
+}
+
+type ErrorHandler = Arc<dyn Fn(&io::Error) + Send + Sync>;
+
+/// Number of lines queued for the writer thread
+const FILE_SINK_QUEUE_LENGTH: usize = 8192;
+
+/// Message to the writer thread of a [`FileSink`]
+enum Command {
+    Line(String),
+    Rotation(u64, usize),
+    OnError(ErrorHandler),
+}
+
+impl FileSink {
+    /// Open `path` for appending, creating it if needed, and start the writer thread
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let writer = FileWriter::open(path.as_ref().to_path_buf())?;
+        let (sender, receiver) = sync_channel(FILE_SINK_QUEUE_LENGTH);
+        let writer = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || writer.run(receiver))?;
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+            on_error: None,
+        })
+    }
+
+    /// Rotate the file once it would exceed `max_bytes`
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes` - Maximum size of the current file
+    /// * `max_files` - Number of rotated files to keep; 0 discards old lines
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_bytes` is 0.
+    pub fn with_rotation(self, max_bytes: u64, max_files: usize) -> Self {
+        if max_bytes == 0 {
+            panic!("Rotation size must be greater than 0");
+        }
+        self.send(Command::Rotation(max_bytes, max_files));
+        self
+    }
+
+    /// Report write errors and dropped lines to `handler`
+    ///
+    /// I/O errors are reported on the writer thread. Lines dropped because the queue
+    /// is full are reported on the thread handling the request, as an error of kind
+    /// `WouldBlock`, so the handler should be cheap, e.g. increment a counter.
+    pub fn on_error<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        let handler: ErrorHandler = Arc::new(handler);
+        self.on_error = Some(handler.clone());
+        self.send(Command::OnError(handler));
+        self
+    }
+
+    /// Send a configuration change, which the writer applies before any later line
+    fn send(&self, command: Command) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(command);
+        }
+    }
+}
+
+impl LogSink for FileSink {
+    fn write_line(&self, line: &str) {
+        let Some(sender) = &self.sender else { return };
+        let error = match sender.try_send(Command::Line(line.to_string())) {
+            Ok(()) => return,
+            Err(TrySendError::Full(_)) => io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "access log queue is full, line dropped",
+            ),
+            Err(TrySendError::Disconnected(_)) => io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "access log writer has stopped, line dropped",
+            ),
+        };
+        if let Some(handler) = &self.on_error {
+            handler(&error);
+        }
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer finish the remaining lines and exit
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl fmt::Debug for FileSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileSink").finish_non_exhaustive()
+    }
+}
+
+/// File and rotation state, owned by the writer thread of a [`FileSink`]
+struct FileWriter {
+    path: PathBuf,
+    rotation: Option<(u64, usize)>,
+    on_error: Option<ErrorHandler>,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl FileWriter {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let (file, size) = open_append(&path)?;
+        Ok(Self {
+            path,
+            rotation: None,
+            on_error: None,
+            file,
+            size,
+        })
+    }
+
+    /// Write queued lines until the sink is dropped
+    fn run(mut self, commands: Receiver<Command>) {
+        while let Ok(command) = commands.recv() {
+            self.handle(command);
+            while let Ok(command) = commands.try_recv() {
+                self.handle(command);
+            }
+            // Flush once the queue is drained, so lines are not held back while idle
+            let flushed = self.file.flush();
+            self.report(flushed);
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Line(line) => {
+                let written = self.write(&line);
+                self.report(written);
+            }
+            Command::Rotation(max_bytes, max_files) => {
+                self.rotation = Some((max_bytes, max_files));
+            }
+            Command::OnError(handler) => self.on_error = Some(handler),
+        }
+    }
+
+    fn report(&self, result: io::Result<()>) {
+        if let (Err(e), Some(handler)) = (result, &self.on_error) {
+            handler(&e);
+        }
+    }
+
+    fn rotate(&mut self, max_files: usize) -> io::Result<()> {
+        self.file.flush()?;
+        if max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, max_files));
+            for n in (1..max_files).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        (self.file, self.size) = open_append(&self.path)?;
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some((max_bytes, max_files)) = self.rotation {
+            if self.size > 0 && self.size + len > max_bytes {
+                if let Err(e) = self.rotate(max_files) {
+                    self.report(Err(e));
+                    // Keep appending to the current file, and retry after another `max_bytes`
+                    self.size = 0;
+                }
+            }
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Open `path` for appending, returning the file and its current size
+fn open_append(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+/// Path of the `n`th rotated file
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", n));
+    path.into()
+}
+
+/// Access log configuration
+///
+/// Register with [`RequestIDMiddleware::with_access_log`]. All fields are written by
+/// default; use [`AccessLog::with_fields`] to select and order them.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{AccessLog, LogField, LogFormat, RequestIDMiddleware, StdoutSink};
+///
+/// let middleware = RequestIDMiddleware::new().with_access_log(
+///     AccessLog::new(LogFormat::Logfmt, StdoutSink)
+///         .with_fields(&[LogField::Id, LogField::Status, LogField::Latency]),
+/// );
+/// // id=550e8400-e29b-41d4-a716-446655440000 status=200 latency_ms=0.412
+/// ```
+///
+/// [`RequestIDMiddleware::with_access_log`]: crate::RequestIDMiddleware::with_access_log
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    fields: Vec<LogField>,
+    sink: Arc<dyn LogSink>,
+}
+
+impl AccessLog {
+    /// Create an access log writing every field to `sink`
+    pub fn new<S: LogSink + 'static>(format: LogFormat, sink: S) -> Self {
+        Self {
+            format,
+            fields: LogField::ALL.to_vec(),
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Select the fields to write, in order
+    pub fn with_fields(mut self, fields: &[LogField]) -> Self {
+        self.fields = fields.to_vec();
+        self
+    }
+
+    /// Capture the request side of an entry
+    pub(crate) fn start(&self, req: &ServiceRequest) -> PendingEntry {
+        let wants = |field| self.fields.contains(&field);
+        PendingEntry {
+            method: req.method().clone(),
+            path: wants(LogField::Path).then(|| req.path().to_string()),
+            peer: req.peer_addr().map(|addr| addr.ip()),
+            user_agent: wants(LogField::UserAgent)
+                .then(|| {
+                    req.headers()
+                        .get(USER_AGENT)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string)
+                })
+                .flatten(),
+        }
+    }
+
+    /// Complete an entry and write it to the sink
+    pub(crate) fn finish(
+        &self,
+        entry: PendingEntry,
+        id: &RequestID,
+        status: StatusCode,
+        bytes: Option<u64>,
+        latency: Duration,
+    ) {
+        let mut line = LineWriter::new(self.format);
+        for field in &self.fields {
+            match field {
+                LogField::Id => line.string(field.key(), Some(id.as_str())),
+                LogField::Method => line.string(field.key(), Some(entry.method.as_str())),
+                LogField::Path => line.string(field.key(), entry.path.as_deref()),
+                LogField::Status => line.raw(field.key(), Some(status.as_u16().to_string())),
+                LogField::Latency => line.raw(
+                    field.key(),
+                    Some(format!("{:.3}", latency.as_secs_f64() * 1000.0)),
+                ),
+                LogField::Bytes => line.raw(field.key(), bytes.map(|b| b.to_string())),
+                LogField::Peer => {
+                    line.string(field.key(), entry.peer.map(|ip| ip.to_string()).as_deref())
+                }
+                LogField::UserAgent => line.string(field.key(), entry.user_agent.as_deref()),
+            }
+        }
+        self.sink.write_line(&line.finish());
+    }
+}
+
+/// Request data captured when the request arrives
+pub(crate) struct PendingEntry {
+    method: Method,
+    path: Option<String>,
+    peer: Option<IpAddr>,
+    user_agent: Option<String>,
+}
+
+/// Builds one line in the configured format
+pub(crate) struct LineWriter {
+    format: LogFormat,
+    line: String,
+}
+
+impl LineWriter {
+    pub(crate) fn new(format: LogFormat) -> Self {
+        let line = match format {
+            LogFormat::Json => String::from("{"),
+            LogFormat::Logfmt => String::new(),
+        };
+        Self { format, line }
+    }
+
+    fn key(&mut self, key: &str) {
+        match self.format {
+            LogFormat::Json => {
+                if self.line.len() > 1 {
+                    self.line.push(',');
+                }
+                self.line.push('"');
+                self.line.push_str(key);
+                self.line.push_str("\":");
+            }
+            LogFormat::Logfmt => {
+                if !self.line.is_empty() {
+                    self.line.push(' ');
+                }
+                self.line.push_str(key);
+                self.line.push('=');
+            }
+        }
+    }
+
+    /// Write a string value, quoted as needed
+    pub(crate) fn string(&mut self, key: &str, value: Option<&str>) {
+        self.key(key);
+        match (self.format, value) {
+            (LogFormat::Json, None) => self.line.push_str("null"),
+            (LogFormat::Json, Some(value)) => {
+                self.line.push('"');
+                escape_into(&mut self.line, value);
+                self.line.push('"');
+            }
+            (LogFormat::Logfmt, None) => {}
+            (LogFormat::Logfmt, Some(value)) => {
+                let quote = value.is_empty()
+                    || value
+                        .chars()
+                        .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+                if quote {
+                    self.line.push('"');
+                    escape_into(&mut self.line, value);
+                    self.line.push('"');
+                } else {
+                    self.line.push_str(value);
+                }
+            }
+        }
+    }
+
+    /// Write a numeric value, which needs no quoting
+    pub(crate) fn raw(&mut self, key: &str, value: Option<String>) {
+        self.key(key);
+        match (self.format, value) {
+            (_, Some(value)) => self.line.push_str(&value),
+            (LogFormat::Json, None) => self.line.push_str("null"),
+            (LogFormat::Logfmt, None) => {}
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        if self.format == LogFormat::Json {
+            self.line.push('}');
+        }
+        self.line
+    }
+}
+
+/// Escape quotes, backslashes and control characters, JSON style
+fn escape_into(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod access_log_tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn entry(path: &str, user_agent: Option<&str>) -> PendingEntry {
+        PendingEntry {
+            method: Method::POST,
+            path: Some(path.to_string()),
+            peer: Some("10.0.0.1".parse().unwrap()),
+            user_agent: user_agent.map(str::to_string),
+        }
+    }
+
+    /// Test JSON lines, including escaping and unknown values
+    #[test]
+    fn test_json_format() {
+        let (tx, rx) = mpsc::channel();
+        let log = AccessLog::new(LogFormat::Json, ChannelSink::new(tx));
+        log.finish(
+            entry("/a\"b", Some("curl/8.0")),
+            &RequestID::from_core("id-1".to_string()),
+            StatusCode::CREATED,
+            None,
+            Duration::from_micros(1500),
+        );
+
+        let line = rx.recv().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "id": "id-1",
+                "method": "POST",
+                "path": "/a\"b",
+                "status": 201,
+                "latency_ms": 1.5,
+                "bytes": null,
+                "peer": "10.0.0.1",
+                "user_agent": "curl/8.0",
+            })
+        );
+    }
+
+    /// Test logfmt lines with selected fields and quoting
+    #[test]
+    fn test_logfmt_format() {
+        let (tx, rx) = mpsc::channel();
+        let log = AccessLog::new(LogFormat::Logfmt, ChannelSink::new(tx)).with_fields(&[
+            LogField::Id,
+            LogField::Status,
+            LogField::Bytes,
+            LogField::UserAgent,
+        ]);
+        log.finish(
+            entry("/", Some("Mozilla/5.0 (X11)")),
+            &RequestID::from_core("id-2".to_string()),
+            StatusCode::OK,
+            Some(42),
+            Duration::ZERO,
+        );
+
+        assert_eq!(
+            rx.recv().unwrap(),
+            r#"id=id-2 status=200 bytes=42 user_agent="Mozilla/5.0 (X11)""#
+        );
+    }
+
+    /// Test size-based file rotation
+    #[test]
+    fn test_file_rotation() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let sink = FileSink::new(&path).unwrap().with_rotation(10, 2);
+        for line in ["line-1", "line-2", "line-3", "line-4"] {
+            sink.write_line(line);
+        }
+        // Waits for the writer thread
+        drop(sink);
+
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(path.clone()), "line-4\n");
+        assert_eq!(read(rotated_path(&path, 1)), "line-3\n");
+        assert_eq!(read(rotated_path(&path, 2)), "line-2\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Test that write failures are reported to the error handler
+    #[test]
+    fn test_file_errors() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let reported = errors.clone();
+        let sink = FileSink::new(&path)
+            .unwrap()
+            .with_rotation(10, 1)
+            .on_error(move |e| reported.lock().unwrap().push(e.kind()));
+        sink.write_line("line-1");
+        // Rotation fails once the directory is gone
+        fs::remove_dir_all(&dir).unwrap();
+        sink.write_line("line-2");
+        drop(sink);
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.as_slice(), [io::ErrorKind::NotFound]);
+    }
+
+    /// Test that lines are still written after rotation failed
+    #[test]
+    fn test_rotation_failure() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        // The current file cannot be renamed onto a non-empty directory
+        fs::create_dir_all(rotated_path(&path, 1).join("blocked")).unwrap();
+
+        let errors = Arc::new(Mutex::new(0));
+        let reported = errors.clone();
+        let sink = FileSink::new(&path)
+            .unwrap()
+            .with_rotation(10, 1)
+            .on_error(move |_| *reported.lock().unwrap() += 1);
+        for line in ["line-1", "line-2", "line-3", "line-4"] {
+            sink.write_line(line);
+        }
+        drop(sink);
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "line-1\nline-2\nline-3\nline-4\n"
+        );
+        assert_eq!(*errors.lock().unwrap(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Request IDs in messages to `actix` actors
+//!
+//! Actors handle their messages on their own arbiter, outside of the request that sent
+//! them, so the thread-local request ID is not available there. [`WithRequestID`] carries
+//! the sender's ID along with the message and installs it while the message is handled.
+use actix::Message;
+
+use crate::{context, RequestID};
+
+/// Message envelope carrying the request ID of its sender
+///
+/// Available with the `actix` feature. The envelope has the same result type as the
+/// wrapped message. Actors accept it by implementing `Handler<WithRequestID<M>>` and
+/// delegating to their handler for `M` through [`WithRequestID::handle`]:
+///
+/// ```rust
+/// use actix::{Actor, Context, Handler, Message};
+/// use actix_web_request_uuid::{get_current_request_id, RequestID, WithRequestID};
+///
+/// struct Billing;
+///
+/// impl Actor for Billing {
+///     type Context = Context<Self>;
+/// }
+///
+/// struct Charge(u64);
+///
+/// impl Message for Charge {
+///     type Result = ();
+/// }
+///
+/// impl Handler<Charge> for Billing {
+///     type Result = ();
+///
+///     fn handle(&mut self, msg: Charge, _ctx: &mut Context<Self>) {
+///         // Logged with the ID of the request that sent the message
+///         println!("[{:?}] charging {}", get_current_request_id(), msg.0);
+///     }
+/// }
+///
+/// impl Handler<WithRequestID<Charge>> for Billing {
+///     type Result = ();
+///
+///     fn handle(&mut self, msg: WithRequestID<Charge>, ctx: &mut Context<Self>) {
+///         msg.handle(|msg| self.handle(msg, ctx))
+///     }
+/// }
+///
+/// # actix::System::new().block_on(async {
+/// let billing = Billing.start();
+/// let charge = RequestID::from("request-1").scope(async {
+///     // In a handler: capture the current request ID
+///     billing.send(WithRequestID::new(Charge(42))).await
+/// });
+/// charge.await.unwrap();
+/// # });
+/// ```
+///
+/// The ID is installed while `handle` runs. If the handler returns a future, e.g. a
+/// `ResponseFuture`, wrap that future with
+/// [`FutureExt::with_current_request_id`](crate::FutureExt::with_current_request_id)
+/// inside `handle` to keep the ID while it is polled.
+#[derive(Debug, Clone)]
+pub struct WithRequestID<M> {
+    id: Option<RequestID>,
+    message: M,
+}
+
+impl<M> WithRequestID<M> {
+    /// Wrap a message with the current request ID
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send
+    ///
+    /// # Returns
+    ///
+    /// An envelope carrying the ID returned by [`RequestID::current`]. Outside of a
+    /// request, the message is handled without a request ID.
+    pub fn new(message: M) -> Self {
+        Self {
+            id: RequestID::current(),
+            message,
+        }
+    }
+
+    /// Wrap a message with a given request ID
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The request ID to install while the message is handled
+    /// * `message` - The message to send
+    pub fn with_id(id: RequestID, message: M) -> Self {
+        Self {
+            id: Some(id),
+            message,
+        }
+    }
+
+    /// Get the request ID carried by the envelope
+    pub fn id(&self) -> Option<&RequestID> {
+        self.id.as_ref()
+    }
+
+    /// Get the wrapped message
+    pub fn message(&self) -> &M {
+        &self.message
+    }
+
+    /// Unwrap the message, discarding the request ID
+    pub fn into_inner(self) -> M {
+        self.message
+    }
+
+    /// Handle the wrapped message with the carried request ID as the current ID
+    ///
+    /// The actor thread's previous request ID is restored when `f` returns.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Closure handling the unwrapped message, usually the actor's handler for `M`
+    ///
+    /// # Returns
+    ///
+    /// The closure's return value.
+    pub fn handle<R>(self, f: impl FnOnce(M) -> R) -> R {
+        let Self { id, message } = self;
+        context::enter(id.as_ref(), || f(message))
+    }
+}
+
+impl<M: Message> Message for WithRequestID<M> {
+    type Result = M::Result;
+}
+
+#[cfg(test)]
+mod actor_tests {
+    use super::*;
+    use crate::get_current_request_id;
+    use actix::{Actor, Context, Handler};
+
+    struct Recorder;
+
+    impl Actor for Recorder {
+        type Context = Context<Self>;
+    }
+
+    struct WhoAmI;
+
+    impl Message for WhoAmI {
+        type Result = Option<String>;
+    }
+
+    impl Handler<WhoAmI> for Recorder {
+        type Result = Option<String>;
+
+        fn handle(&mut self, _msg: WhoAmI, _ctx: &mut Context<Self>) -> Self::Result {
+            get_current_request_id()
+        }
+    }
+
+    impl Handler<WithRequestID<WhoAmI>> for Recorder {
+        type Result = Option<String>;
+
+        fn handle(&mut self, msg: WithRequestID<WhoAmI>, ctx: &mut Context<Self>) -> Self::Result {
+            msg.handle(|msg| self.handle(msg, ctx))
+        }
+    }
+
+    /// Test that the sender's ID is installed while the actor handles the message
+    #[actix_rt::test]
+    async fn test_envelope_carries_id() {
+        let addr = Recorder.start();
+
+        let send = RequestID::from("request-1").scope(async {
+            let envelope = WithRequestID::new(WhoAmI);
+            assert_eq!(envelope.id().map(RequestID::as_str), Some("request-1"));
+            addr.send(envelope).await.unwrap()
+        });
+        assert_eq!(send.await.as_deref(), Some("request-1"));
+
+        // The ID does not stay installed on the actor's thread
+        assert_eq!(addr.send(WhoAmI).await.unwrap(), None);
+
+        let explicit = WithRequestID::with_id(RequestID::from("job-7"), WhoAmI);
+        assert_eq!(addr.send(explicit).await.unwrap().as_deref(), Some("job-7"));
+        assert_eq!(addr.send(WithRequestID::new(WhoAmI)).await.unwrap(), None);
+    }
+}
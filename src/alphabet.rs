@@ -0,0 +1,97 @@
+//! Alphabets for compact request IDs
+//!
+//! Unlike a truncated UUID, a compact ID fills every character with entropy
+//! drawn uniformly from the selected alphabet.
+
+/// Alphabet used by the compact ID generator
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{Alphabet, RequestIDMiddleware};
+///
+/// // 22 base62 characters, ~131 bits of entropy
+/// let middleware = RequestIDMiddleware::new().with_compact_id(Alphabet::Base62);
+/// assert_eq!(middleware.get_id_length(), 22);
+///
+/// // 12 Crockford base32 characters, 60 bits of entropy
+/// let middleware = RequestIDMiddleware::new()
+///     .with_compact_id(Alphabet::Crockford32)
+///     .with_id_length(12);
+/// assert_eq!(middleware.entropy_bits(), Some(60.0));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    /// `0-9`, `A-Z` and `a-z` (62 characters)
+    Base62,
+    /// Bitcoin-style base58, without the look-alike characters `0`, `O`, `I` and `l`
+    Base58,
+    /// Crockford's base32, upper case without `I`, `L`, `O` and `U`
+    Crockford32,
+    /// URL-safe base64 (`A-Z`, `a-z`, `0-9`, `-` and `_`)
+    UrlSafeBase64,
+}
+
+impl Alphabet {
+    /// Get the characters of this alphabet
+    pub fn chars(&self) -> &'static str {
+        match self {
+            Alphabet::Base62 => "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+            Alphabet::Base58 => "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz",
+            Alphabet::Crockford32 => "0123456789ABCDEFGHJKMNPQRSTVWXYZ",
+            Alphabet::UrlSafeBase64 => {
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+            }
+        }
+    }
+
+    /// Get the number of bits of entropy carried by each character
+    pub fn bits_per_char(&self) -> f64 {
+        (self.chars().len() as f64).log2()
+    }
+
+    /// Get the default ID length, the smallest length carrying at least 128 bits of entropy
+    pub fn default_length(&self) -> usize {
+        (128.0 / self.bits_per_char()).ceil() as usize
+    }
+}
+
+#[cfg(test)]
+mod alphabet_tests {
+    use super::*;
+
+    const ALPHABETS: [Alphabet; 4] = [
+        Alphabet::Base62,
+        Alphabet::Base58,
+        Alphabet::Crockford32,
+        Alphabet::UrlSafeBase64,
+    ];
+
+    /// Test alphabet sizes and that characters are unique
+    #[test]
+    fn test_alphabet_chars() {
+        let sizes = [62, 58, 32, 64];
+        for (alphabet, size) in ALPHABETS.iter().zip(sizes) {
+            let chars = alphabet.chars();
+            assert_eq!(chars.len(), size);
+
+            let mut sorted: Vec<u8> = chars.bytes().collect();
+            sorted.sort_unstable();
+            sorted.dedup();
+            assert_eq!(sorted.len(), size);
+        }
+    }
+
+    /// Test default lengths reach 128 bits of entropy
+    #[test]
+    fn test_default_length() {
+        assert_eq!(Alphabet::Base62.default_length(), 22);
+        assert_eq!(Alphabet::Base58.default_length(), 22);
+        assert_eq!(Alphabet::Crockford32.default_length(), 26);
+        assert_eq!(Alphabet::UrlSafeBase64.default_length(), 22);
+
+        for alphabet in ALPHABETS {
+            assert!(alphabet.default_length() as f64 * alphabet.bits_per_char() >= 128.0);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Decode request IDs generated with `RequestIDMiddleware::with_encrypted_ids`
+//!
+//! The key is read from the `REQUEST_ID_KEY` environment variable as 64 hex
+//! characters. IDs are taken from the command line, or one per line from stdin.
+//!
+//! ```text
+//! $ REQUEST_ID_KEY=... request-id-decode api_Jq3...
+//! api_Jq3...
+//!   timestamp: 2025-06-01T12:34:56.789Z (1748781296789)
+//!   node_id:   3
+//!   tenant_id: 42
+//! ```
+use std::io::{BufRead, Write};
+use std::process::ExitCode;
+
+use actix_web_request_uuid::EncryptedIds;
+
+/// Environment variable holding the hex-encoded key
+const KEY_VARIABLE: &str = "REQUEST_ID_KEY";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let key = std::env::var(KEY_VARIABLE).ok();
+    run(
+        &args,
+        key.as_deref(),
+        std::io::stdin().lock(),
+        &mut std::io::stdout().lock(),
+        &mut std::io::stderr().lock(),
+    )
+}
+
+/// Decode the IDs given as arguments, or read from `input` if there are none
+///
+/// # Arguments
+///
+/// * `args` - Command line arguments without the program name
+/// * `key` - Value of the key variable, if set
+/// * `input` - Source of IDs, one per line
+/// * `out` - Receives the decoded IDs
+/// * `err` - Receives usage errors and IDs that fail to decode
+fn run(
+    args: &[String],
+    key: Option<&str>,
+    input: impl BufRead,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> ExitCode {
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        let _ = writeln!(
+            out,
+            "Usage: {}=<hex key> request-id-decode [ID...]",
+            KEY_VARIABLE
+        );
+        let _ = writeln!(
+            out,
+            "Reads IDs from stdin, one per line, if none are given."
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let _ = writeln!(err, "error: {} is not set", KEY_VARIABLE);
+            return ExitCode::from(2);
+        }
+    };
+    let ids = match EncryptedIds::from_hex_key(key) {
+        Ok(ids) => ids,
+        Err(e) => {
+            let _ = writeln!(err, "error: invalid {}: {}", KEY_VARIABLE, e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let inputs: Box<dyn Iterator<Item = String>> = if args.is_empty() {
+        Box::new(input.lines().map_while(Result::ok))
+    } else {
+        Box::new(args.iter().cloned())
+    };
+
+    let mut failed = false;
+    for input in inputs {
+        let id = input.trim();
+        if id.is_empty() {
+            continue;
+        }
+        match ids.find_and_decode(id) {
+            Ok(metadata) => {
+                let _ = writeln!(out, "{}", id);
+                for line in metadata.to_string().lines() {
+                    let _ = writeln!(out, "  {}", line);
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(err, "{}: {}", id, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod request_id_decode_tests {
+    use super::*;
+    use actix_web_request_uuid::IdMetadata;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// Run with the given arguments and stdin, returning the exit code, stdout and stderr
+    fn decode(args: &[&str], key: Option<&str>, input: &str) -> (ExitCode, String, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let code = run(&args, key, input.as_bytes(), &mut out, &mut err);
+        (
+            code,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    /// Test that a missing or invalid key is a usage error
+    #[test]
+    fn test_key_handling() {
+        let (code, out, err) = decode(&["abc"], None, "");
+        assert_eq!(code, ExitCode::from(2));
+        assert_eq!(out, "");
+        assert_eq!(err, "error: REQUEST_ID_KEY is not set\n");
+
+        let (code, _, err) = decode(&["abc"], Some("+0"), "");
+        assert_eq!(code, ExitCode::from(2));
+        assert!(err.starts_with("error: invalid REQUEST_ID_KEY: "));
+
+        // Help needs no key
+        let (code, out, _) = decode(&["--help"], None, "");
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert!(out.starts_with("Usage: REQUEST_ID_KEY=<hex key>"));
+    }
+
+    /// Test decoding namespaced IDs from arguments and from stdin
+    #[test]
+    fn test_decode_output() {
+        let ids = EncryptedIds::from_hex_key(KEY).unwrap();
+        let token = ids.encode(&IdMetadata {
+            timestamp: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            node_id: Some(3),
+            tenant_id: None,
+        });
+        let id = format!("api_{}", token);
+        let expected = format!(
+            "{}\n  timestamp: 2023-11-14T22:13:20.123Z (1700000000123)\n  node_id:   3\n  tenant_id: -\n",
+            id
+        );
+
+        let (code, out, err) = decode(&[&id], Some(KEY), "");
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(out, expected);
+        assert_eq!(err, "");
+
+        // Blank lines are skipped, and a bad ID fails the run without hiding the others
+        let (code, out, err) = decode(&[], Some(KEY), &format!("\n{}\napi_bogus\n", id));
+        assert_eq!(code, ExitCode::FAILURE);
+        assert_eq!(out, expected);
+        assert!(err.starts_with("api_bogus: "));
+    }
+}
@@ -0,0 +1,404 @@
+//! Response bodies that keep the request ID in context
+//!
+//! Streaming bodies, such as Server-Sent Events, are polled after the middleware returned
+//! the response. [`RequestIDBody`] installs the request ID for every poll of the body and
+//! runs the end-of-stream hooks once the body finished or was dropped.
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use pin_project_lite::pin_project;
+
+use crate::access_log::PendingEntry;
+use crate::context;
+use crate::hooks::Hooks;
+use crate::{AccessLog, RecentRequests, RequestID, RequestSummary};
+
+/// How the response body of a request ended
+///
+/// Passed to [`RequestIDMiddleware::on_stream_end`](crate::RequestIDMiddleware::on_stream_end)
+/// hooks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamEnd {
+    /// Time from the arrival of the request until the body ended
+    pub latency: Duration,
+    /// Number of body bytes produced
+    pub bytes: u64,
+    /// Whether the body was sent completely, rather than failing or being dropped early,
+    /// e.g. because the client disconnected
+    pub completed: bool,
+}
+
+pin_project! {
+    /// Response body installing the request ID while it is polled
+    ///
+    /// Wraps the body of every response passing through the middleware. Logging inside a
+    /// streaming body therefore sees the request's own ID, and the ID is removed from the
+    /// thread again when a poll returns.
+    pub struct RequestIDBody<B> {
+        #[pin]
+        body: B,
+        end: EndOfStream,
+    }
+}
+
+/// Runs the end-of-stream hooks exactly once, at the latest when the body is dropped
+struct EndOfStream {
+    id: RequestID,
+    started: Instant,
+    bytes: u64,
+    /// Size of the body, unless it is a stream of unknown length
+    expected: Option<u64>,
+    hooks: Rc<Hooks>,
+    records: Option<Records>,
+    done: bool,
+}
+
+/// Access log entry and recent-requests summary of a response, written once its body ended
+pub(crate) struct Records {
+    pub(crate) status: StatusCode,
+    /// Message of the error the response was created from, if any
+    pub(crate) error: Option<String>,
+    pub(crate) access_log: Option<(Rc<AccessLog>, PendingEntry)>,
+    pub(crate) recent: Option<(RecentRequests, RequestSummary)>,
+}
+
+impl Records {
+    fn finish(self, id: &RequestID, bytes: u64, latency: Duration) {
+        if let Some((log, entry)) = self.access_log {
+            log.finish(entry, id, self.status, Some(bytes), latency);
+        }
+        if let Some((recent, summary)) = self.recent {
+            recent.finish(summary, id, self.status, self.error, latency);
+        }
+    }
+}
+
+impl EndOfStream {
+    fn finish(&mut self, completed: bool) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        let end = StreamEnd {
+            latency: self.started.elapsed(),
+            bytes: self.bytes,
+            completed,
+        };
+        context::enter(Some(&self.id), || {
+            self.hooks.run_stream_end(&self.id, &end);
+            if let Some(records) = self.records.take() {
+                records.finish(&self.id, end.bytes, end.latency);
+            }
+        });
+    }
+}
+
+impl Drop for EndOfStream {
+    fn drop(&mut self) {
+        // Empty bodies, e.g. of `204 No Content` responses, are never polled
+        let completed = self.expected == Some(self.bytes);
+        self.finish(completed);
+    }
+}
+
+impl<B: MessageBody> RequestIDBody<B> {
+    /// Wrap the body of the response to the request with the given ID
+    pub(crate) fn new(body: B, id: RequestID, started: Instant, hooks: Rc<Hooks>) -> Self {
+        let expected = match body.size() {
+            BodySize::None => Some(0),
+            BodySize::Sized(bytes) => Some(bytes),
+            BodySize::Stream => None,
+        };
+        Self {
+            body,
+            end: EndOfStream {
+                id,
+                started,
+                bytes: 0,
+                expected,
+                hooks,
+                records: None,
+                done: false,
+            },
+        }
+    }
+
+    /// Write the access log entry and recent-requests summary once the body ended
+    pub(crate) fn with_records(mut self, records: Option<Records>) -> Self {
+        self.end.records = records;
+        self
+    }
+}
+
+impl<B> RequestIDBody<B> {
+    /// Get the ID of the request this body responds to
+    pub fn request_id(&self) -> &RequestID {
+        &self.end.id
+    }
+
+    /// Get the wrapped body
+    pub fn get_ref(&self) -> &B {
+        &self.body
+    }
+}
+
+impl<B: MessageBody> MessageBody for RequestIDBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.project();
+        let end = this.end;
+        let body = this.body;
+        let poll = context::enter(Some(&end.id), || body.poll_next(cx));
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => end.bytes += chunk.len() as u64,
+            Poll::Ready(Some(Err(_))) => end.finish(false),
+            Poll::Ready(None) => end.finish(true),
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn try_into_bytes(self) -> Result<Bytes, Self> {
+        // Lets `BoxBody` and the HTTP dispatcher treat wrapped `Bytes` bodies as bytes
+        let Self { body, mut end } = self;
+        match body.try_into_bytes() {
+            Ok(bytes) => {
+                end.bytes = bytes.len() as u64;
+                end.finish(true);
+                Ok(bytes)
+            }
+            Err(body) => Err(Self { body, end }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod body_tests {
+    use super::*;
+    use crate::{
+        get_current_request_id, ChannelSink, LogFormat, RequestIDMiddleware, REQUEST_ID_HEADER,
+    };
+    use actix_web::{body, test, web, App, HttpResponse};
+    use std::cell::RefCell;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    /// Streaming body yielding the chunks returned by a closure until it returns `None`
+    struct Chunks<F>(F, usize);
+
+    impl<F: FnMut(usize) -> Option<Bytes> + Unpin> MessageBody for Chunks<F> {
+        type Error = Infallible;
+
+        fn size(&self) -> BodySize {
+            BodySize::Stream
+        }
+
+        fn poll_next(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Bytes, Infallible>>> {
+            let this = self.get_mut();
+            this.1 += 1;
+            Poll::Ready((this.0)(this.1 - 1).map(Ok))
+        }
+    }
+
+    type Ends = Arc<Mutex<Vec<(String, StreamEnd)>>>;
+
+    fn hooks(ends: &Ends) -> Rc<Hooks> {
+        let ends = ends.clone();
+        let mut hooks = Hooks::default();
+        hooks.stream_end.push(Arc::new(move |id, end| {
+            assert_eq!(get_current_request_id().as_deref(), Some(id.as_str()));
+            ends.lock().unwrap().push((id.to_string(), *end));
+        }));
+        Rc::new(hooks)
+    }
+
+    /// Test that the ID is installed while the body is polled and hooks run at the end
+    #[actix_rt::test]
+    async fn test_body_installs_id() {
+        let ends = Ends::default();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let chunks = {
+            let seen = seen.clone();
+            Chunks(
+                move |i| {
+                    seen.borrow_mut().push(get_current_request_id());
+                    (i < 2).then(|| Bytes::from_static(b"data"))
+                },
+                0,
+            )
+        };
+        let body = RequestIDBody::new(
+            chunks,
+            RequestID::from("streaming"),
+            Instant::now(),
+            hooks(&ends),
+        );
+
+        let bytes = body::to_bytes(body).await.unwrap();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(seen.borrow().len(), 3);
+        assert!(seen
+            .borrow()
+            .iter()
+            .all(|id| id.as_deref() == Some("streaming")));
+        assert_eq!(get_current_request_id(), None);
+
+        let ends = ends.lock().unwrap();
+        assert_eq!(ends.len(), 1);
+        assert_eq!(ends[0].0, "streaming");
+        assert_eq!(ends[0].1.bytes, 8);
+        assert!(ends[0].1.completed);
+    }
+
+    /// Test that boxing keeps bytes bodies as bytes, and reports them as sent
+    #[actix_rt::test]
+    async fn test_try_into_bytes() {
+        let ends = Ends::default();
+        let body = RequestIDBody::new(
+            Bytes::from_static(b"hello"),
+            RequestID::from("bytes"),
+            Instant::now(),
+            hooks(&ends),
+        );
+        let bytes = body::BoxBody::new(body).try_into_bytes().unwrap();
+        assert_eq!(bytes, "hello");
+        assert_eq!(ends.lock().unwrap()[0].1.bytes, 5);
+        assert!(ends.lock().unwrap()[0].1.completed);
+
+        // Streams stay wrapped, and end when they are dropped
+        let ends = Ends::default();
+        let body = RequestIDBody::new(
+            Chunks(|_| Some(Bytes::from_static(b"tick")), 0),
+            RequestID::from("stream"),
+            Instant::now(),
+            hooks(&ends),
+        );
+        let body = body::BoxBody::new(body).try_into_bytes().unwrap_err();
+        assert!(ends.lock().unwrap().is_empty());
+        drop(body);
+        assert_eq!(ends.lock().unwrap().len(), 1);
+    }
+
+    /// Test that dropping an unfinished body runs the hooks once
+    #[actix_rt::test]
+    async fn test_dropped_body() {
+        let ends = Ends::default();
+        let body = RequestIDBody::new(
+            Chunks(|_| Some(Bytes::from_static(b"tick")), 0),
+            RequestID::from("disconnected"),
+            Instant::now(),
+            hooks(&ends),
+        );
+        drop(body);
+
+        let ends = ends.lock().unwrap();
+        assert_eq!(ends.len(), 1);
+        assert!(!ends[0].1.completed);
+        assert_eq!(ends[0].1.bytes, 0);
+    }
+
+    /// Test that a streamed response body sees the request's ID and ends with a hook
+    #[actix_rt::test]
+    async fn test_streaming_response() {
+        let ends = Ends::default();
+        let on_end = ends.clone();
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().on_stream_end(move |id, end| {
+                    on_end.lock().unwrap().push((id.to_string(), *end));
+                }))
+                .route(
+                    "/events",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().body(Chunks(
+                            |i| {
+                                (i < 3).then(|| {
+                                    format!("data: {:?}\n\n", get_current_request_id()).into()
+                                })
+                            },
+                            0,
+                        ))
+                    }),
+                )
+                .route("/empty", web::get().to(HttpResponse::NoContent)),
+        )
+        .await;
+
+        let resp =
+            test::call_service(&app, test::TestRequest::with_uri("/events").to_request()).await;
+        let id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(get_current_request_id().is_none());
+        let events = test::read_body(resp).await;
+        let expected = format!("data: Some({:?})\n\n", id).repeat(3);
+        assert_eq!(events, expected.as_bytes());
+
+        let resp =
+            test::call_service(&app, test::TestRequest::with_uri("/empty").to_request()).await;
+        drop(resp);
+
+        let ends = ends.lock().unwrap();
+        assert_eq!(ends.len(), 2);
+        assert_eq!(ends[0].0, id);
+        assert_eq!(ends[0].1.bytes, expected.len() as u64);
+        assert!(ends[0].1.completed);
+        assert!(ends[1].1.completed);
+    }
+
+    /// Test that access logs and summaries of streams count the bytes that were sent
+    #[actix_rt::test]
+    async fn test_streaming_records() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let recent = RecentRequests::new(10);
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_access_log(AccessLog::new(LogFormat::Json, ChannelSink::new(tx)))
+                        .with_recent_requests(&recent),
+                )
+                .route(
+                    "/events",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .body(Chunks(|i| (i < 4).then(|| Bytes::from_static(b"tick")), 0))
+                    }),
+                ),
+        )
+        .await;
+
+        let resp =
+            test::call_service(&app, test::TestRequest::with_uri("/events").to_request()).await;
+        assert!(rx.try_recv().is_err());
+        assert!(recent.is_empty());
+        assert_eq!(test::read_body(resp).await.len(), 16);
+
+        let line: serde_json::Value = serde_json::from_str(&rx.recv().unwrap()).unwrap();
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["bytes"], 16);
+        let summary = recent.get(line["id"].as_str().unwrap()).unwrap();
+        assert_eq!(summary.status, 200);
+        assert_eq!(summary.path, "/events");
+    }
+}
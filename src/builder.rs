@@ -0,0 +1,422 @@
+//! Fallible builder for the middleware
+//!
+//! The chaining methods on [`RequestIDMiddleware`] panic on invalid arguments and
+//! silently let later calls override earlier ones. The builder instead collects the
+//! settings and validates them together in [`RequestIDMiddlewareBuilder::build`].
+use std::sync::Arc;
+
+use actix_web::http::header::HeaderName;
+use uuid::Uuid;
+
+use crate::config::validate_namespace;
+use crate::deterministic::{SharedCounter, SharedSeeded};
+use crate::generator::{validate_alphabet, Generator};
+#[cfg(feature = "fast-rng")]
+use crate::rng::Entropy;
+#[cfg(feature = "encrypted-ids")]
+use crate::EncryptedIds;
+#[cfg(feature = "signing")]
+use crate::Signer;
+use crate::{
+    Alphabet, ConfigError, IpCidr, Namespace, RequestIDMiddleware, DEFAULT_NANOID_SIZE,
+    NANOID_ALPHABET,
+};
+
+/// Builder for [`RequestIDMiddleware`] reporting invalid settings as errors
+///
+/// Created with [`RequestIDMiddleware::builder`]. The methods have the same names
+/// and meaning as on the middleware, but nothing is checked until
+/// [`RequestIDMiddlewareBuilder::build`], which returns the first problem found.
+/// Settings that cannot fail, such as hooks, metrics or the access log, are chained
+/// on the built middleware.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{Alphabet, ConfigError, RequestIDCounters, RequestIDMiddleware};
+///
+/// let middleware = RequestIDMiddleware::builder()
+///     .header_name("x-request-id")
+///     .with_compact_id(Alphabet::Base62)
+///     .with_prefix("billing")
+///     .build()
+///     .unwrap()
+///     .with_metrics(RequestIDCounters::new());
+///
+/// let result = RequestIDMiddleware::builder().with_id_length(0).build();
+/// assert_eq!(result.err(), Some(ConfigError::ZeroLength));
+/// ```
+#[derive(Default)]
+pub struct RequestIDMiddlewareBuilder {
+    generator: Option<(&'static str, Generator)>,
+    header_name: Option<String>,
+    id_length: Option<usize>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    separator: Option<String>,
+    propagate: bool,
+    trusted_networks: Vec<IpCidr>,
+    #[cfg(feature = "fast-rng")]
+    fast_rng: bool,
+    #[cfg(feature = "signing")]
+    signer: Option<Signer>,
+    error: Option<ConfigError>,
+}
+
+impl RequestIDMiddlewareBuilder {
+    /// Create a builder with the defaults of [`RequestIDMiddleware::new`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the header name, see [`RequestIDMiddleware::header_name`]
+    pub fn header_name<T: Into<String>>(mut self, header_name: T) -> Self {
+        self.header_name = Some(header_name.into());
+        self
+    }
+
+    /// Set the ID length, see [`RequestIDMiddleware::with_id_length`]
+    ///
+    /// A length of 0 is reported as [`ConfigError::ZeroLength`].
+    pub fn with_id_length(mut self, length: usize) -> Self {
+        self.id_length = Some(length);
+        self
+    }
+
+    /// Use a custom ID generation function, see [`RequestIDMiddleware::generator`]
+    pub fn generator<F>(self, f: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.set_generator("generator", Generator::Custom(Arc::new(f)))
+    }
+
+    /// Use hyphenated UUIDs, see [`RequestIDMiddleware::with_full_uuid`]
+    pub fn with_full_uuid(self) -> Self {
+        self.set_generator("with_full_uuid", Generator::Uuid)
+    }
+
+    /// Use UUIDs without hyphens, see [`RequestIDMiddleware::with_simple_uuid`]
+    pub fn with_simple_uuid(self) -> Self {
+        self.set_generator("with_simple_uuid", Generator::SimpleUuid)
+    }
+
+    /// Use time-ordered UUID v7, see [`RequestIDMiddleware::with_uuid_v7`]
+    pub fn with_uuid_v7(self) -> Self {
+        self.set_generator("with_uuid_v7", Generator::UuidV7)
+    }
+
+    /// Use a custom UUID format, see [`RequestIDMiddleware::with_custom_uuid_format`]
+    pub fn with_custom_uuid_format<F>(self, formatter: F) -> Self
+    where
+        F: Fn(Uuid) -> String + Send + Sync + 'static,
+    {
+        self.set_generator(
+            "with_custom_uuid_format",
+            Generator::Custom(Arc::new(move || formatter(Uuid::new_v4()))),
+        )
+    }
+
+    /// Use compact random IDs, see [`RequestIDMiddleware::with_compact_id`]
+    pub fn with_compact_id(self, alphabet: Alphabet) -> Self {
+        self.set_generator("with_compact_id", Generator::Compact(alphabet))
+    }
+
+    /// Use a per-thread CSPRNG, see [`RequestIDMiddleware::with_fast_rng`]
+    #[cfg(feature = "fast-rng")]
+    pub fn with_fast_rng(mut self) -> Self {
+        self.fast_rng = true;
+        self
+    }
+
+    /// Use nanoid-style IDs, see [`RequestIDMiddleware::with_nanoid`]
+    pub fn with_nanoid(self) -> Self {
+        self.with_custom_nanoid(NANOID_ALPHABET, DEFAULT_NANOID_SIZE)
+    }
+
+    /// Use nanoid-style IDs with a custom alphabet and size, see
+    /// [`RequestIDMiddleware::with_custom_nanoid`]
+    ///
+    /// Invalid alphabets are reported as [`ConfigError::InvalidAlphabet`] and a size
+    /// of 0 as [`ConfigError::ZeroLength`].
+    pub fn with_custom_nanoid(mut self, alphabet: &str, size: usize) -> Self {
+        if size == 0 {
+            self.fail(ConfigError::ZeroLength);
+        }
+        if let Err(message) = validate_alphabet(alphabet) {
+            self.fail(ConfigError::InvalidAlphabet(message.to_string()));
+        }
+
+        self.set_generator(
+            "with_custom_nanoid",
+            Generator::Nanoid {
+                alphabet: Arc::from(alphabet),
+                size,
+            },
+        )
+    }
+
+    /// Use KSUIDs, see [`RequestIDMiddleware::with_ksuid`]
+    ///
+    /// Combining this with [`RequestIDMiddlewareBuilder::with_id_length`] is
+    /// reported as [`ConfigError::ConflictingOptions`].
+    pub fn with_ksuid(self) -> Self {
+        self.set_generator("with_ksuid", Generator::Ksuid)
+    }
+
+    /// Use IDs carrying encrypted metadata, see
+    /// [`RequestIDMiddleware::with_encrypted_ids`]
+    ///
+    /// Combining this with [`RequestIDMiddlewareBuilder::with_id_length`] is
+    /// reported as [`ConfigError::ConflictingOptions`].
+    #[cfg(feature = "encrypted-ids")]
+    pub fn with_encrypted_ids(self, ids: EncryptedIds) -> Self {
+        self.set_generator("with_encrypted_ids", Generator::Encrypted(Arc::new(ids)))
+    }
+
+    /// Use reproducible IDs, see [`RequestIDMiddleware::with_seeded_ids`]
+    pub fn with_seeded_ids(self, seed: u64) -> Self {
+        self.set_generator(
+            "with_seeded_ids",
+            Generator::Seeded(SharedSeeded::new(seed)),
+        )
+    }
+
+    /// Use sequential IDs, see [`RequestIDMiddleware::with_sequential_ids`]
+    pub fn with_sequential_ids(self) -> Self {
+        self.set_generator(
+            "with_sequential_ids",
+            Generator::Sequential(SharedCounter::default()),
+        )
+    }
+
+    /// Set the namespace prefix, see [`RequestIDMiddleware::with_prefix`]
+    pub fn with_prefix<T: Into<String>>(mut self, prefix: T) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Set the namespace suffix, see [`RequestIDMiddleware::with_suffix`]
+    pub fn with_suffix<T: Into<String>>(mut self, suffix: T) -> Self {
+        self.suffix = Some(suffix.into());
+        self
+    }
+
+    /// Set the namespace separator, see [`RequestIDMiddleware::with_separator`]
+    pub fn with_separator<T: Into<String>>(mut self, separator: T) -> Self {
+        self.separator = Some(separator.into());
+        self
+    }
+
+    /// Honor inbound request IDs, see [`RequestIDMiddleware::with_propagation`]
+    pub fn with_propagation(mut self) -> Self {
+        self.propagate = true;
+        self
+    }
+
+    /// Trust a network given in CIDR notation, e.g. `10.0.0.0/8`
+    ///
+    /// See [`RequestIDMiddleware::with_trusted_networks`]. Networks that cannot be
+    /// parsed are reported as [`ConfigError::InvalidCidr`].
+    pub fn with_trusted_network(mut self, network: &str) -> Self {
+        match network.parse() {
+            Ok(network) => self.trusted_networks.push(network),
+            Err(error) => self.fail(error),
+        }
+        self
+    }
+
+    /// Trust the given networks, see [`RequestIDMiddleware::with_trusted_networks`]
+    pub fn with_trusted_networks<I>(mut self, networks: I) -> Self
+    where
+        I: IntoIterator<Item = IpCidr>,
+    {
+        self.trusted_networks.extend(networks);
+        self
+    }
+
+    /// Sign generated IDs, see [`RequestIDMiddleware::with_signing`]
+    #[cfg(feature = "signing")]
+    pub fn with_signing(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Validate the settings and build the middleware
+    ///
+    /// # Returns
+    ///
+    /// The middleware, or the first invalid setting:
+    ///
+    /// * [`ConfigError::InvalidHeaderName`] if the header name is not a valid HTTP
+    ///   header name
+    /// * [`ConfigError::ZeroLength`] if the ID length or nanoid size is 0
+    /// * [`ConfigError::InvalidAlphabet`] if a nanoid alphabet is invalid
+    /// * [`ConfigError::InvalidNamespace`] if the prefix, suffix or separator
+    ///   cannot be sent in a header
+    /// * [`ConfigError::InvalidCidr`] if a trusted network cannot be parsed
+    /// * [`ConfigError::ConflictingOptions`] if several generators are selected,
+    ///   trusted networks are set without propagation, or the ID length is set for
+    ///   IDs that must not be truncated
+    pub fn build(self) -> Result<RequestIDMiddleware, ConfigError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let mut middleware = RequestIDMiddleware::new();
+
+        if let Some(header_name) = self.header_name {
+            if HeaderName::try_from(header_name.as_str()).is_err() {
+                return Err(ConfigError::InvalidHeaderName(header_name));
+            }
+            middleware.header_name = header_name;
+        }
+
+        if let Some((name, generator)) = self.generator {
+            if self.id_length.is_some() && matches!(name, "with_ksuid" | "with_encrypted_ids") {
+                return Err(ConfigError::ConflictingOptions(format!(
+                    "with_id_length makes the IDs of {} unparsable",
+                    name
+                )));
+            }
+            middleware.generator = generator;
+        }
+
+        match self.id_length {
+            Some(0) => return Err(ConfigError::ZeroLength),
+            length => middleware.id_length = length,
+        }
+
+        let mut namespace = Namespace::default();
+        for part in [&self.prefix, &self.suffix, &self.separator]
+            .into_iter()
+            .flatten()
+        {
+            validate_namespace(part)?;
+        }
+        namespace.prefix = self.prefix;
+        namespace.suffix = self.suffix;
+        if let Some(separator) = self.separator {
+            namespace.separator = separator;
+        }
+        middleware.namespace = namespace;
+
+        if !self.trusted_networks.is_empty() && !self.propagate {
+            return Err(ConfigError::ConflictingOptions(
+                "with_trusted_networks has no effect without with_propagation".to_string(),
+            ));
+        }
+        middleware.propagate = self.propagate;
+        middleware.trusted_networks = self.trusted_networks;
+
+        #[cfg(feature = "fast-rng")]
+        if self.fast_rng {
+            middleware.entropy = Entropy::ThreadLocal;
+        }
+
+        #[cfg(feature = "signing")]
+        if let Some(signer) = self.signer {
+            middleware = middleware.with_signing(signer);
+        }
+
+        Ok(middleware)
+    }
+
+    /// Select the generator, reporting a conflict if one was already selected
+    fn set_generator(mut self, name: &'static str, generator: Generator) -> Self {
+        if let Some((previous, _)) = &self.generator {
+            self.fail(ConfigError::ConflictingOptions(format!(
+                "{} and {} both select the ID generator",
+                previous, name
+            )));
+        }
+        self.generator = Some((name, generator));
+        self
+    }
+
+    /// Remember the first error, reported by `build`
+    fn fail(&mut self, error: ConfigError) {
+        self.error.get_or_insert(error);
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use super::*;
+
+    fn error(builder: RequestIDMiddlewareBuilder) -> ConfigError {
+        builder.build().err().expect("the builder should fail")
+    }
+
+    /// Test that valid settings are applied
+    #[test]
+    fn test_build() {
+        let middleware = RequestIDMiddleware::builder()
+            .header_name("x-request-id")
+            .with_simple_uuid()
+            .with_id_length(16)
+            .with_prefix("api")
+            .with_separator("-")
+            .with_propagation()
+            .with_trusted_network("10.0.0.0/8")
+            .build()
+            .unwrap();
+
+        assert_eq!(middleware.header_name, "x-request-id");
+        assert_eq!(middleware.get_total_id_length(), 20);
+        assert!(middleware.propagate);
+        assert_eq!(middleware.trusted_networks.len(), 1);
+    }
+
+    /// Test that invalid values are reported
+    #[test]
+    fn test_invalid_values() {
+        assert_eq!(
+            error(RequestIDMiddleware::builder().header_name("request id")),
+            ConfigError::InvalidHeaderName("request id".to_string())
+        );
+        assert_eq!(
+            error(RequestIDMiddleware::builder().with_id_length(0)),
+            ConfigError::ZeroLength
+        );
+        assert_eq!(
+            error(RequestIDMiddleware::builder().with_custom_nanoid("abca", 10)),
+            ConfigError::InvalidAlphabet(
+                "Alphabet must not contain duplicate characters".to_string()
+            )
+        );
+        assert_eq!(
+            error(RequestIDMiddleware::builder().with_suffix("eu west")),
+            ConfigError::InvalidNamespace("eu west".to_string())
+        );
+        assert_eq!(
+            error(
+                RequestIDMiddleware::builder()
+                    .with_propagation()
+                    .with_trusted_network("10.0.0.0/40")
+            ),
+            ConfigError::InvalidCidr("10.0.0.0/40".to_string())
+        );
+    }
+
+    /// Test that conflicting options are reported
+    #[test]
+    fn test_conflicting_options() {
+        assert!(matches!(
+            error(RequestIDMiddleware::builder().with_ksuid().with_nanoid()),
+            ConfigError::ConflictingOptions(message) if message.contains("with_ksuid")
+        ));
+        assert!(matches!(
+            error(
+                RequestIDMiddleware::builder()
+                    .with_ksuid()
+                    .with_id_length(20)
+            ),
+            ConfigError::ConflictingOptions(_)
+        ));
+        assert!(matches!(
+            error(RequestIDMiddleware::builder().with_trusted_network("10.0.0.0/8")),
+            ConfigError::ConflictingOptions(_)
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+//! Middleware configuration loaded from files or environment variables
+//!
+//! Lets the same binary pick its request ID settings per environment. Values are
+//! validated when the middleware is built, so a bad setting fails at startup with a
+//! [`ConfigError`] instead of panicking in a builder method.
+use std::collections::HashMap;
+use std::env::{self, VarError};
+use std::fmt;
+use std::str::FromStr;
+
+/// Error in the middleware configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The header name is not a valid HTTP header name
+    InvalidHeaderName(String),
+    /// The ID length is 0
+    ZeroLength,
+    /// The ID format is not one of the built-in formats
+    UnknownFormat(String),
+    /// A namespace prefix, suffix or separator contains characters other than
+    /// visible ASCII
+    InvalidNamespace(String),
+    /// A trusted network is not an IP address or CIDR network
+    InvalidCidr(String),
+    /// A nanoid alphabet is empty, too long, contains duplicates or characters
+    /// other than visible ASCII
+    InvalidAlphabet(String),
+    /// Options were combined that contradict each other
+    ConflictingOptions(String),
+    /// An environment variable could not be parsed
+    InvalidValue {
+        /// Name of the variable
+        key: String,
+        /// Value of the variable
+        value: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidHeaderName(name) => write!(f, "invalid header name `{}`", name),
+            ConfigError::ZeroLength => f.write_str("request ID length must be greater than 0"),
+            ConfigError::UnknownFormat(format) => write!(
+                f,
+                "unknown request ID format `{}`, expected one of: {}",
+                format,
+                RequestIDFormat::ALL.map(|f| f.as_str()).join(", ")
+            ),
+            ConfigError::InvalidNamespace(value) => write!(
+                f,
+                "namespace `{}` must only contain visible ASCII characters",
+                value
+            ),
+            ConfigError::InvalidCidr(network) => {
+                write!(f, "`{}` is not an IP address or CIDR network", network)
+            }
+            ConfigError::InvalidAlphabet(message) => f.write_str(message),
+            ConfigError::ConflictingOptions(message) => {
+                write!(f, "conflicting options: {}", message)
+            }
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value `{}` for {}", value, key)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Built-in ID format selectable from configuration
+///
+/// Names are kebab-case: `uuid`, `simple-uuid`, `uuid-v7`, `base62`, `base58`, `crockford32`,
+/// `url-safe-base64`, `nanoid` and `ksuid`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum RequestIDFormat {
+    /// Hyphenated UUID v4, see `with_full_uuid`
+    #[default]
+    Uuid,
+    /// UUID v4 without hyphens, see `with_simple_uuid`
+    SimpleUuid,
+    /// Time-ordered UUID v7, see `with_uuid_v7`
+    UuidV7,
+    /// Compact base62 ID, see `with_compact_id`
+    Base62,
+    /// Compact base58 ID, see `with_compact_id`
+    Base58,
+    /// Compact Crockford base32 ID, see `with_compact_id`
+    Crockford32,
+    /// Compact URL-safe base64 ID, see `with_compact_id`
+    UrlSafeBase64,
+    /// Nanoid-style ID, see `with_nanoid`
+    Nanoid,
+    /// KSUID, see `with_ksuid`
+    Ksuid,
+}
+
+impl RequestIDFormat {
+    /// All formats, in declaration order
+    pub const ALL: [RequestIDFormat; 9] = [
+        RequestIDFormat::Uuid,
+        RequestIDFormat::SimpleUuid,
+        RequestIDFormat::UuidV7,
+        RequestIDFormat::Base62,
+        RequestIDFormat::Base58,
+        RequestIDFormat::Crockford32,
+        RequestIDFormat::UrlSafeBase64,
+        RequestIDFormat::Nanoid,
+        RequestIDFormat::Ksuid,
+    ];
+
+    /// Get the name of this format
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestIDFormat::Uuid => "uuid",
+            RequestIDFormat::SimpleUuid => "simple-uuid",
+            RequestIDFormat::UuidV7 => "uuid-v7",
+            RequestIDFormat::Base62 => "base62",
+            RequestIDFormat::Base58 => "base58",
+            RequestIDFormat::Crockford32 => "crockford32",
+            RequestIDFormat::UrlSafeBase64 => "url-safe-base64",
+            RequestIDFormat::Nanoid => "nanoid",
+            RequestIDFormat::Ksuid => "ksuid",
+        }
+    }
+}
+
+impl FromStr for RequestIDFormat {
+    type Err = ConfigError;
+
+    /// Parse a format name, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RequestIDFormat::ALL
+            .into_iter()
+            .find(|format| format.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| ConfigError::UnknownFormat(s.to_string()))
+    }
+}
+
+impl fmt::Display for RequestIDFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Settings for [`RequestIDMiddleware::from_config`]
+///
+/// Every field is optional and defaults to the behavior of
+/// [`RequestIDMiddleware::new`]. With the `serde` feature the struct implements
+/// `Deserialize`, so it can be embedded in an application's configuration file:
+///
+/// ```toml
+/// [request_id]
+/// header_name = "x-request-id"
+/// format = "base62"
+/// prefix = "billing"
+/// propagate = true
+/// trusted_networks = ["10.0.0.0/8"]
+/// ```
+///
+/// Use [`RequestIDConfig::from_env`] to read the settings from environment
+/// variables instead.
+///
+/// [`RequestIDMiddleware::from_config`]: crate::RequestIDMiddleware::from_config
+/// [`RequestIDMiddleware::new`]: crate::RequestIDMiddleware::new
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct RequestIDConfig {
+    /// Name of the request ID header, `request-id` by default
+    pub header_name: Option<String>,
+    /// ID format
+    pub format: RequestIDFormat,
+    /// ID length, see `with_id_length`
+    pub length: Option<usize>,
+    /// Namespace prefix, see `with_prefix`
+    pub prefix: Option<String>,
+    /// Namespace suffix, see `with_suffix`
+    pub suffix: Option<String>,
+    /// Namespace separator, see `with_separator`
+    pub separator: Option<String>,
+    /// Honor inbound request IDs, see `with_propagation`
+    pub propagate: bool,
+    /// IP addresses or CIDR networks allowed to send request IDs, see
+    /// `with_trusted_networks`
+    pub trusted_networks: Vec<String>,
+}
+
+impl RequestIDConfig {
+    /// Read the configuration from environment variables
+    ///
+    /// Variable names are the upper-case field names joined to `prefix` with an
+    /// underscore, e.g. `REQUEST_ID_HEADER_NAME` and `REQUEST_ID_TRUSTED_NETWORKS`
+    /// for the prefix `REQUEST_ID`. Unset and empty variables keep their default.
+    ///
+    /// * `_PROPAGATE` accepts `true`/`false`, `1`/`0` and `yes`/`no`
+    /// * `_TRUSTED_NETWORKS` is a comma-separated list
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Prefix of the variable names, may be empty
+    ///
+    /// # Returns
+    ///
+    /// The configuration, or an error naming the variable that could not be parsed.
+    /// Values are only checked for their syntax here; the rest of the validation
+    /// happens in [`RequestIDMiddleware::from_config`].
+    ///
+    /// [`RequestIDMiddleware::from_config`]: crate::RequestIDMiddleware::from_config
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_lookup(prefix, |key| match env::var(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(VarError::NotPresent) => Ok(None),
+            Err(VarError::NotUnicode(value)) => Err(ConfigError::InvalidValue {
+                key: key.to_string(),
+                value: value.to_string_lossy().into_owned(),
+            }),
+        })
+    }
+
+    /// Read the configuration from key/value pairs, e.g. a parsed `.env` file
+    ///
+    /// Keys are interpreted as in [`RequestIDConfig::from_env`]; keys not
+    /// starting with `prefix` are ignored.
+    pub fn from_vars<I, K, V>(prefix: &str, vars: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars: HashMap<String, String> = vars
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        Self::from_lookup(prefix, |key| Ok(vars.get(key).cloned()))
+    }
+
+    fn from_lookup<L>(prefix: &str, lookup: L) -> Result<Self, ConfigError>
+    where
+        L: Fn(&str) -> Result<Option<String>, ConfigError>,
+    {
+        let prefix = prefix.trim_end_matches('_');
+        let var = |name: &str| {
+            let key = if prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}_{}", prefix, name)
+            };
+            let value = lookup(&key)?.filter(|value| !value.trim().is_empty());
+            Ok::<_, ConfigError>(value.map(|value| (key, value)))
+        };
+        let invalid = |key: String, value: String| ConfigError::InvalidValue { key, value };
+
+        let mut config = RequestIDConfig {
+            header_name: var("HEADER_NAME")?.map(|(_, value)| value),
+            prefix: var("PREFIX")?.map(|(_, value)| value),
+            suffix: var("SUFFIX")?.map(|(_, value)| value),
+            separator: var("SEPARATOR")?.map(|(_, value)| value),
+            ..Default::default()
+        };
+
+        if let Some((_, value)) = var("FORMAT")? {
+            config.format = value.parse()?;
+        }
+        if let Some((key, value)) = var("LENGTH")? {
+            config.length = Some(value.trim().parse().map_err(|_| invalid(key, value))?);
+        }
+        if let Some((key, value)) = var("PROPAGATE")? {
+            config.propagate = match value.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" => true,
+                "false" | "0" | "no" => false,
+                _ => return Err(invalid(key, value)),
+            };
+        }
+        if let Some((_, value)) = var("TRUSTED_NETWORKS")? {
+            config.trusted_networks = value
+                .split(',')
+                .map(str::trim)
+                .filter(|network| !network.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        Ok(config)
+    }
+}
+
+/// Check that a namespace part can be sent in a header
+pub(crate) fn validate_namespace(value: &str) -> Result<(), ConfigError> {
+    if value.bytes().all(|b| b.is_ascii_graphic()) {
+        Ok(())
+    } else {
+        Err(ConfigError::InvalidNamespace(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    /// Test parsing format names
+    #[test]
+    fn test_format_from_str() {
+        for format in RequestIDFormat::ALL {
+            assert_eq!(format.as_str().parse::<RequestIDFormat>(), Ok(format));
+        }
+        assert_eq!("KSUID".parse(), Ok(RequestIDFormat::Ksuid));
+        assert_eq!(
+            "snowflake".parse::<RequestIDFormat>(),
+            Err(ConfigError::UnknownFormat("snowflake".to_string()))
+        );
+    }
+
+    /// Test reading the configuration from variables
+    #[test]
+    fn test_from_vars() {
+        let config = RequestIDConfig::from_vars(
+            "APP_REQUEST_ID_",
+            [
+                ("APP_REQUEST_ID_HEADER_NAME", "x-request-id"),
+                ("APP_REQUEST_ID_FORMAT", "Base62"),
+                ("APP_REQUEST_ID_LENGTH", "16"),
+                ("APP_REQUEST_ID_PREFIX", "billing"),
+                ("APP_REQUEST_ID_SUFFIX", ""),
+                ("APP_REQUEST_ID_PROPAGATE", "yes"),
+                ("APP_REQUEST_ID_TRUSTED_NETWORKS", "10.0.0.0/8, fd00::/8,"),
+                ("APP_REQUEST_ID_KEY", "ignored"),
+                ("LENGTH", "ignored"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            RequestIDConfig {
+                header_name: Some("x-request-id".to_string()),
+                format: RequestIDFormat::Base62,
+                length: Some(16),
+                prefix: Some("billing".to_string()),
+                suffix: None,
+                separator: None,
+                propagate: true,
+                trusted_networks: vec!["10.0.0.0/8".to_string(), "fd00::/8".to_string()],
+            }
+        );
+
+        let empty = RequestIDConfig::from_vars("", Vec::<(String, String)>::new()).unwrap();
+        assert_eq!(empty, RequestIDConfig::default());
+    }
+
+    /// Test that unparsable variables are reported by name
+    #[test]
+    fn test_from_vars_invalid() {
+        assert_eq!(
+            RequestIDConfig::from_vars("RID", [("RID_LENGTH", "long")]),
+            Err(ConfigError::InvalidValue {
+                key: "RID_LENGTH".to_string(),
+                value: "long".to_string()
+            })
+        );
+        assert_eq!(
+            RequestIDConfig::from_vars("RID", [("RID_PROPAGATE", "maybe")]),
+            Err(ConfigError::InvalidValue {
+                key: "RID_PROPAGATE".to_string(),
+                value: "maybe".to_string()
+            })
+        );
+        assert_eq!(
+            RequestIDConfig::from_vars("RID", [("RID_FORMAT", "ulid")]),
+            Err(ConfigError::UnknownFormat("ulid".to_string()))
+        );
+    }
+
+    /// Test deserializing the configuration
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        let config: RequestIDConfig = serde_json::from_str(
+            r#"{"format": "url-safe-base64", "propagate": true, "trusted_networks": ["10.0.0.0/8"]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.format, RequestIDFormat::UrlSafeBase64);
+        assert!(config.propagate);
+        assert_eq!(config.header_name, None);
+
+        assert!(serde_json::from_str::<RequestIDConfig>(r#"{"lenght": 12}"#).is_err());
+    }
+}
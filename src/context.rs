@@ -0,0 +1,295 @@
+//! Request ID of the code currently running on a thread
+//!
+//! The middleware installs the ID of a request while that request's future is being
+//! polled and restores the previous one afterwards, so requests interleaved on the same
+//! worker thread never observe each other's IDs. Work handed off to other tasks or
+//! threads captures the current ID and installs it the same way.
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::error::BlockingError;
+use actix_web::rt::task::JoinHandle;
+use pin_project_lite::pin_project;
+
+use crate::RequestID;
+
+thread_local! {
+    static CURRENT_REQUEST_ID: RefCell<Option<RequestID>> = const { RefCell::new(None) };
+}
+
+/// Replace the current request ID, returning the previous one
+pub(crate) fn replace(id: Option<RequestID>) -> Option<RequestID> {
+    CURRENT_REQUEST_ID.with(|current| current.replace(id))
+}
+
+/// Borrow the current request ID
+///
+/// # Panics
+///
+/// Panics if `f` replaces the current request ID.
+pub(crate) fn with<R>(f: impl FnOnce(Option<&RequestID>) -> R) -> R {
+    CURRENT_REQUEST_ID.with(|current| f(current.borrow().as_ref()))
+}
+
+/// Run `f` with `id` as the current request ID, restoring the previous ID afterwards
+pub(crate) fn enter<R>(id: Option<&RequestID>, f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(replace(id.cloned()));
+    f()
+}
+
+/// Restores the previous request ID when dropped, including during unwinding
+struct Restore(Option<RequestID>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        // The thread-local may already be gone when a thread shuts down
+        let _ = CURRENT_REQUEST_ID.try_with(|current| current.replace(previous));
+    }
+}
+
+pin_project! {
+    /// Future that runs with a request ID as the current ID
+    ///
+    /// Created by [`RequestID::scope`] and [`FutureExt::with_current_request_id`]. While
+    /// the inner future is polled, [`RequestID::current`] and `get_current_request_id`
+    /// return the scoped ID; between polls the thread's previous ID is restored.
+    #[must_use = "futures do nothing unless polled"]
+    pub struct Scoped<F> {
+        id: Option<RequestID>,
+        #[pin]
+        future: F,
+    }
+}
+
+impl<F> Scoped<F> {
+    /// Create a future that polls `future` with `id` as the current request ID
+    ///
+    /// With `None`, the future runs without a request ID.
+    pub(crate) fn new(id: Option<RequestID>, future: F) -> Self {
+        Self { id, future }
+    }
+
+    /// Get the request ID installed while the inner future is polled
+    ///
+    /// Returns `None` if the future runs without a request ID, e.g. when it was created
+    /// by [`FutureExt::with_current_request_id`] outside of a request.
+    pub fn id(&self) -> Option<&RequestID> {
+        self.id.as_ref()
+    }
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        enter(this.id.as_ref(), || this.future.poll(cx))
+    }
+}
+
+/// Adapter to carry the current request ID into futures that run elsewhere
+///
+/// Implemented for every future. Use it to keep the request ID in futures passed to
+/// executors other than the current task, e.g. `tokio::spawn`.
+pub trait FutureExt: Future + Sized {
+    /// Run this future under the request ID that is current when this is called
+    ///
+    /// The ID is captured immediately, so call this inside the request, before the
+    /// future is handed to another task or thread.
+    ///
+    /// # Returns
+    ///
+    /// A [`Scoped`] future with the same output. Outside of a request, the future runs
+    /// without a request ID, even if the task polling it has one.
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{get_current_request_id, FutureExt, RequestID};
+    ///
+    /// # actix_web::rt::System::new().block_on(async {
+    /// async fn handler() {
+    ///     let task = async { get_current_request_id() }.with_current_request_id();
+    ///     let seen = actix_web::rt::spawn(task).await.unwrap();
+    ///     assert_eq!(seen, get_current_request_id());
+    /// }
+    /// RequestID::from("request-1").scope(handler()).await;
+    /// # });
+    /// ```
+    fn with_current_request_id(self) -> Scoped<Self> {
+        Scoped::new(RequestID::current(), self)
+    }
+}
+
+impl<F: Future> FutureExt for F {}
+
+/// Spawn a future on the current Actix runtime under the current request ID
+///
+/// Equivalent to `actix_web::rt::spawn(future.with_current_request_id())`. Without the
+/// wrapper, spawned tasks lose the request ID because it lives in thread-local storage.
+///
+/// # Arguments
+///
+/// * `future` - The future to run in a new task
+///
+/// # Returns
+///
+/// A `JoinHandle` for the task's output.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web::HttpResponse;
+/// use actix_web_request_uuid::{get_current_request_id, spawn_with_request_id};
+///
+/// async fn handler() -> HttpResponse {
+///     spawn_with_request_id(async {
+///         // Still logged with the ID of the request that started it
+///         println!("[{:?}] sending welcome email", get_current_request_id());
+///     });
+///     HttpResponse::Accepted().finish()
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Panics if called outside of an Actix runtime.
+pub fn spawn_with_request_id<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    actix_web::rt::spawn(future.with_current_request_id())
+}
+
+/// Run a blocking closure on the thread pool under the current request ID
+///
+/// Equivalent to `actix_web::web::block`, but the closure sees the request ID of the
+/// caller. The ID is captured when this function is called and removed from the pool
+/// thread again once the closure returns.
+///
+/// # Arguments
+///
+/// * `f` - The blocking closure to run
+///
+/// # Returns
+///
+/// A future resolving to the closure's output, or `BlockingError` if the thread pool is
+/// shut down.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web::{error, HttpResponse};
+/// use actix_web_request_uuid::{block_with_request_id, get_current_request_id};
+///
+/// async fn handler() -> actix_web::Result<HttpResponse> {
+///     let rows = block_with_request_id(|| {
+///         println!("[{:?}] running report query", get_current_request_id());
+///         42
+///     })
+///     .await
+///     .map_err(error::ErrorInternalServerError)?;
+///     Ok(HttpResponse::Ok().body(rows.to_string()))
+/// }
+/// ```
+pub fn block_with_request_id<F, R>(f: F) -> impl Future<Output = Result<R, BlockingError>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let id = RequestID::current();
+    actix_web::web::block(move || enter(id.as_ref(), f))
+}
+
+#[cfg(test)]
+mod context_tests {
+    use super::*;
+
+    fn current() -> Option<String> {
+        with(|id| id.map(|id| id.to_string()))
+    }
+
+    /// Test that entering a scope restores the previous ID, even when nested
+    #[test]
+    fn test_enter_restores_previous() {
+        let outer = RequestID::from("outer");
+        let inner = RequestID::from("inner");
+        assert_eq!(current(), None);
+        enter(Some(&outer), || {
+            assert_eq!(current().as_deref(), Some("outer"));
+            enter(Some(&inner), || {
+                assert_eq!(current().as_deref(), Some("inner"))
+            });
+            enter(None, || assert_eq!(current(), None));
+            assert_eq!(current().as_deref(), Some("outer"));
+        });
+        assert_eq!(current(), None);
+    }
+
+    /// Test that the previous ID is restored when the scoped code panics
+    #[test]
+    fn test_enter_restores_after_panic() {
+        let id = RequestID::from("panicking");
+        let result = std::panic::catch_unwind(|| enter(Some(&id), || panic!("handler failed")));
+        assert!(result.is_err());
+        assert_eq!(current(), None);
+    }
+
+    /// Test that spawned tasks and adapted futures keep the caller's ID
+    #[actix_rt::test]
+    async fn test_spawn_with_request_id() {
+        let handler = async {
+            let spawned = spawn_with_request_id(async { current() });
+            let adapted = actix_web::rt::spawn(async { current() }.with_current_request_id());
+            let plain = actix_web::rt::spawn(async { current() });
+            (
+                spawned.await.unwrap(),
+                adapted.await.unwrap(),
+                plain.await.unwrap(),
+            )
+        };
+        let (spawned, adapted, plain) = RequestID::from("request-1").scope(handler).await;
+        assert_eq!(spawned.as_deref(), Some("request-1"));
+        assert_eq!(adapted.as_deref(), Some("request-1"));
+        assert_eq!(plain, None);
+    }
+
+    /// Test that a future adapted outside of a request runs without an ID
+    #[actix_rt::test]
+    async fn test_with_current_request_id_outside_request() {
+        let task = async { current() }.with_current_request_id();
+        assert!(task.id().is_none());
+        let seen = RequestID::from("other").scope(task).await;
+        assert_eq!(seen, None);
+    }
+
+    /// Test that blocking closures see the caller's ID and do not leak it
+    #[actix_rt::test]
+    async fn test_block_with_request_id() {
+        let blocking = async {
+            block_with_request_id(|| {
+                let seen = current();
+                (seen, std::thread::current().id())
+            })
+            .await
+            .unwrap()
+        };
+        let (seen, thread) = RequestID::from("request-2").scope(blocking).await;
+        assert_eq!(seen.as_deref(), Some("request-2"));
+        assert_ne!(thread, std::thread::current().id());
+
+        let after = actix_web::web::block(current).await.unwrap();
+        assert_eq!(after, None);
+    }
+
+    /// Test that scoped futures can be sent to multi-threaded executors
+    #[test]
+    fn test_scoped_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&async {}.with_current_request_id());
+    }
+}
@@ -0,0 +1,304 @@
+//! Deterministic request ID generators for reproducible tests
+//!
+//! The middleware options [`RequestIDMiddleware::with_seeded_ids`] and
+//! [`RequestIDMiddleware::with_sequential_ids`] produce the same sequence of IDs as
+//! the [`SeededIds`] and [`SequentialIds`] iterators, so tests can compute the exact
+//! IDs a service will emit.
+//!
+//! [`RequestIDMiddleware::with_seeded_ids`]: crate::RequestIDMiddleware::with_seeded_ids
+//! [`RequestIDMiddleware::with_sequential_ids`]: crate::RequestIDMiddleware::with_sequential_ids
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::{Builder, Uuid};
+
+/// Default number of digits of sequential IDs
+pub const DEFAULT_SEQUENTIAL_WIDTH: usize = 6;
+
+/// Increment of the SplitMix64 state (the golden ratio in 64-bit fixed point)
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// SplitMix64 output function
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Build a UUID v4 from two PRNG outputs
+fn uuid_from(high: u64, low: u64) -> Uuid {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&high.to_be_bytes());
+    bytes[8..].copy_from_slice(&low.to_be_bytes());
+    Builder::from_random_bytes(bytes).into_uuid()
+}
+
+/// Build a seeded ID of the given length from a stream of UUIDs
+///
+/// Padding is drawn from the same stream so that IDs stay reproducible.
+fn seeded_id(mut next_uuid: impl FnMut() -> Uuid, length: Option<usize>) -> String {
+    let mut id = next_uuid().to_string();
+    if let Some(length) = length {
+        while id.len() < length {
+            id.push_str(&next_uuid().simple().to_string());
+        }
+        id.truncate(length);
+    }
+    id
+}
+
+/// Number of UUIDs [`seeded_id`] draws for one ID
+fn uuids_per_id(length: Option<usize>) -> usize {
+    // The first UUID is formatted with hyphens, padding UUIDs without
+    let padding = length.map_or(0, |length| length.saturating_sub(36));
+    1 + padding.div_ceil(32)
+}
+
+/// Iterator over the UUIDs produced by [`RequestIDMiddleware::with_seeded_ids`]
+///
+/// IDs are UUID v4 formatted, but generated by a SplitMix64 PRNG and therefore
+/// completely predictable. Never use them outside of tests.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::SeededIds;
+///
+/// let mut expected = SeededIds::new(42);
+/// let first = expected.next().unwrap();
+/// assert_eq!(first.len(), 36);
+///
+/// // The same seed always yields the same sequence
+/// assert_eq!(SeededIds::new(42).next().unwrap(), first);
+/// ```
+///
+/// [`RequestIDMiddleware::with_seeded_ids`]: crate::RequestIDMiddleware::with_seeded_ids
+#[derive(Debug, Clone)]
+pub struct SeededIds {
+    state: u64,
+    length: Option<usize>,
+}
+
+impl SeededIds {
+    /// Create an iterator starting from the given seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            length: None,
+        }
+    }
+
+    /// Create an iterator yielding IDs of a custom length
+    ///
+    /// This matches the middleware configured with `with_seeded_ids(seed).with_id_length(length)`.
+    pub fn with_length(seed: u64, length: usize) -> Self {
+        Self {
+            state: seed,
+            length: Some(length),
+        }
+    }
+
+    /// Get the next UUID of the sequence
+    pub fn next_uuid(&mut self) -> Uuid {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        let high = mix(self.state);
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        let low = mix(self.state);
+        uuid_from(high, low)
+    }
+}
+
+impl Iterator for SeededIds {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let length = self.length;
+        Some(seeded_id(|| self.next_uuid(), length))
+    }
+}
+
+/// Iterator over the IDs produced by [`RequestIDMiddleware::with_sequential_ids`]
+///
+/// Yields zero-padded decimal counters starting at 1, e.g. `000001`, `000002`, ...
+/// After the largest counter of the width, e.g. `999999`, the sequence wraps around to
+/// `000000`.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::SequentialIds;
+///
+/// let ids: Vec<String> = SequentialIds::new().take(2).collect();
+/// assert_eq!(ids, vec!["000001", "000002"]);
+/// ```
+///
+/// [`RequestIDMiddleware::with_sequential_ids`]: crate::RequestIDMiddleware::with_sequential_ids
+#[derive(Debug, Clone)]
+pub struct SequentialIds {
+    counter: u64,
+    width: usize,
+}
+
+impl Default for SequentialIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SequentialIds {
+    /// Create an iterator with the default width of 6 digits
+    pub fn new() -> Self {
+        Self::with_width(DEFAULT_SEQUENTIAL_WIDTH)
+    }
+
+    /// Create an iterator with a custom number of digits
+    ///
+    /// This matches the middleware configured with `with_sequential_ids().with_id_length(width)`.
+    pub fn with_width(width: usize) -> Self {
+        Self { counter: 0, width }
+    }
+}
+
+impl Iterator for SequentialIds {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.counter += 1;
+        Some(format_sequential(self.counter, self.width))
+    }
+}
+
+/// Format a counter value zero-padded to `width` digits
+///
+/// Counters that need more digits wrap around, so IDs always have `width` characters.
+fn format_sequential(counter: u64, width: usize) -> String {
+    if width == 0 {
+        return String::new();
+    }
+    let counter = match u32::try_from(width).ok().and_then(|w| 10u64.checked_pow(w)) {
+        Some(modulus) => counter % modulus,
+        // Every u64 fits into 20 digits
+        None => counter,
+    };
+    format!("{:0width$}", counter, width = width)
+}
+
+/// Seeded PRNG shared by all services created from one middleware
+#[derive(Debug, Clone)]
+pub(crate) struct SharedSeeded(Arc<AtomicU64>);
+
+impl SharedSeeded {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(seed)))
+    }
+
+    /// Get the next ID, matching [`SeededIds`]
+    ///
+    /// All PRNG steps of the ID are reserved at once, so services calling this
+    /// concurrently still produce the IDs of the sequence, only in a different order.
+    pub(crate) fn next_id(&self, length: Option<usize>) -> String {
+        // Two PRNG outputs per UUID
+        let steps = 2 * uuids_per_id(length) as u64;
+        let state = self
+            .0
+            .fetch_add(GOLDEN_GAMMA.wrapping_mul(steps), Ordering::SeqCst);
+        let mut ids = SeededIds { state, length };
+        seeded_id(|| ids.next_uuid(), length)
+    }
+}
+
+/// Counter shared by all services created from one middleware
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedCounter(Arc<AtomicU64>);
+
+impl SharedCounter {
+    /// Get the next counter value formatted to `width` digits
+    pub(crate) fn next_id(&self, width: usize) -> String {
+        let counter = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+        format_sequential(counter, width)
+    }
+}
+
+#[cfg(test)]
+mod deterministic_tests {
+    use super::*;
+
+    /// Test that the shared generator matches the public iterator
+    #[test]
+    fn test_seeded_matches_iterator() {
+        let shared = SharedSeeded::new(7);
+        let expected: Vec<String> = SeededIds::new(7).take(5).collect();
+        let actual: Vec<String> = (0..5).map(|_| shared.next_id(None)).collect();
+        assert_eq!(actual, expected);
+
+        let shared = SharedSeeded::new(7);
+        let expected: Vec<String> = SeededIds::with_length(7, 80).take(3).collect();
+        let actual: Vec<String> = (0..3).map(|_| shared.next_id(Some(80))).collect();
+        assert_eq!(actual, expected);
+        assert!(actual.iter().all(|id| id.len() == 80));
+
+        let other: Vec<String> = SeededIds::new(8).take(5).collect();
+        assert_ne!(other, expected);
+    }
+
+    /// Test that concurrent services produce the IDs of the sequence
+    #[test]
+    fn test_seeded_concurrent() {
+        for length in [None, Some(36), Some(37), Some(80)] {
+            let shared = SharedSeeded::new(3);
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    let shared = shared.clone();
+                    std::thread::spawn(move || {
+                        (0..100).map(|_| shared.next_id(length)).collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            let mut actual: Vec<String> = threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect();
+            let mut expected: Vec<String> = match length {
+                Some(length) => SeededIds::with_length(3, length).take(400).collect(),
+                None => SeededIds::new(3).take(400).collect(),
+            };
+            actual.sort();
+            expected.sort();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    /// Test that seeded IDs are valid UUID v4
+    #[test]
+    fn test_seeded_uuid_version() {
+        for id in SeededIds::new(0).take(10) {
+            let uuid = Uuid::parse_str(&id).unwrap();
+            assert_eq!(uuid.get_version_num(), 4);
+        }
+    }
+
+    /// Test sequential formatting
+    #[test]
+    fn test_sequential_ids() {
+        let counter = SharedCounter::default();
+        assert_eq!(counter.next_id(6), "000001");
+        assert_eq!(counter.next_id(3), "002");
+        assert_eq!(counter.next_id(1), "3");
+
+        let ids: Vec<String> = SequentialIds::with_width(2).skip(9).take(2).collect();
+        assert_eq!(ids, vec!["10", "11"]);
+    }
+
+    /// Test that counters wrap around instead of exceeding the width
+    #[test]
+    fn test_sequential_wraps() {
+        let counter = SharedCounter(Arc::new(AtomicU64::new(998)));
+        assert_eq!(counter.next_id(3), "999");
+        assert_eq!(counter.next_id(3), "000");
+        assert_eq!(counter.next_id(3), "001");
+        assert_eq!(counter.next_id(0), "");
+        assert_eq!(counter.next_id(25), format!("{:025}", 1003));
+
+        let ids: Vec<String> = SequentialIds::with_width(1).skip(8).take(3).collect();
+        assert_eq!(ids, vec!["9", "0", "1"]);
+    }
+}
@@ -0,0 +1,529 @@
+//! Request IDs carrying encrypted metadata
+//!
+//! An encrypted ID packs the time it was issued, the node that issued it and a
+//! tenant ID into an opaque token. Customers only see random-looking characters,
+//! while anyone holding the key can recover the metadata, e.g. with the
+//! `request-id-decode` binary.
+//!
+//! Tokens are URL-safe base64 (without padding) of a random 24-byte nonce followed
+//! by the XChaCha20-Poly1305 ciphertext and tag. Tampered tokens and tokens minted
+//! with another key fail authentication instead of decoding to garbage.
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::hex::decode_hex;
+use crate::rng::fill_random;
+use crate::time::format_rfc3339;
+
+/// Length of encryption keys in bytes
+pub const ENCRYPTED_ID_KEY_LENGTH: usize = 32;
+
+/// Version of the plaintext layout
+const FORMAT_VERSION: u8 = 1;
+/// Associated data binding tokens to their purpose
+const ASSOCIATED_DATA: &[u8] = b"actix-web-request-uuid/encrypted-id";
+const NONCE_LENGTH: usize = 24;
+const TAG_LENGTH: usize = 16;
+/// Version and flags bytes
+const HEADER_LENGTH: usize = 2;
+
+const FLAG_TIMESTAMP: u8 = 0b001;
+const FLAG_NODE_ID: u8 = 0b010;
+const FLAG_TENANT_ID: u8 = 0b100;
+
+/// Metadata recovered from an encrypted request ID
+///
+/// Fields that were not configured on the issuing middleware are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdMetadata {
+    /// Time the ID was issued, with millisecond precision
+    pub timestamp: Option<SystemTime>,
+    /// ID of the node that issued the ID
+    pub node_id: Option<u16>,
+    /// ID of the tenant the request belonged to
+    pub tenant_id: Option<u32>,
+}
+
+/// Formats one field per line, with `-` for fields that are not set:
+///
+/// ```text
+/// timestamp: 2025-06-01T12:34:56.789Z (1748781296789)
+/// node_id:   3
+/// tenant_id: -
+/// ```
+impl fmt::Display for IdMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.timestamp {
+            Some(timestamp) => {
+                let millis = timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis() as u64);
+                writeln!(f, "timestamp: {} ({})", format_rfc3339(timestamp), millis)?;
+            }
+            None => writeln!(f, "timestamp: -")?,
+        }
+        match self.node_id {
+            Some(node_id) => writeln!(f, "node_id:   {}", node_id)?,
+            None => writeln!(f, "node_id:   -")?,
+        }
+        match self.tenant_id {
+            Some(tenant_id) => write!(f, "tenant_id: {}", tenant_id),
+            None => write!(f, "tenant_id: -"),
+        }
+    }
+}
+
+/// Error returned when an encrypted request ID or key cannot be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptedIdError {
+    /// The key is not 64 hex characters
+    InvalidKey,
+    /// The token is not URL-safe base64
+    InvalidEncoding,
+    /// The token or its plaintext has an unexpected number of bytes
+    InvalidLength(usize),
+    /// The token was tampered with or encrypted with another key
+    AuthenticationFailed,
+    /// The token was produced by an incompatible version of this crate
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for EncryptedIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptedIdError::InvalidKey => write!(
+                f,
+                "encryption key must be {} hex characters",
+                2 * ENCRYPTED_ID_KEY_LENGTH
+            ),
+            EncryptedIdError::InvalidEncoding => write!(f, "request ID is not URL-safe base64"),
+            EncryptedIdError::InvalidLength(len) => {
+                write!(f, "unexpected encrypted request ID length of {} bytes", len)
+            }
+            EncryptedIdError::AuthenticationFailed => write!(
+                f,
+                "request ID was not encrypted with this key or was tampered with"
+            ),
+            EncryptedIdError::UnsupportedVersion(version) => {
+                write!(f, "unsupported encrypted request ID version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncryptedIdError {}
+
+/// Generator and decoder for request IDs carrying encrypted metadata
+///
+/// The issue timestamp is included by default; node and tenant IDs are included
+/// once configured. Every token uses a fresh random nonce, so IDs are unique even
+/// when the metadata is identical.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{EncryptedIds, RequestIDMiddleware};
+///
+/// let ids = EncryptedIds::new([7; 32]).with_node_id(3).with_tenant_id(42);
+///
+/// let token = ids.generate();
+/// let metadata = ids.decode(&token).unwrap();
+/// assert_eq!(metadata.node_id, Some(3));
+/// assert_eq!(metadata.tenant_id, Some(42));
+///
+/// let middleware = RequestIDMiddleware::new().with_encrypted_ids(ids);
+/// ```
+#[derive(Clone)]
+pub struct EncryptedIds {
+    cipher: XChaCha20Poly1305,
+    timestamp: bool,
+    node_id: Option<u16>,
+    tenant_id: Option<u32>,
+}
+
+impl EncryptedIds {
+    /// Create a generator with a 256-bit key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Secret key, e.g. from `openssl rand -hex 32`
+    pub fn new(key: [u8; ENCRYPTED_ID_KEY_LENGTH]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+            timestamp: true,
+            node_id: None,
+            tenant_id: None,
+        }
+    }
+
+    /// Create a generator with a key given as 64 hex characters
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EncryptedIds)` - If the key is valid
+    /// * `Err(EncryptedIdError::InvalidKey)` - If the key is not 64 hex characters
+    pub fn from_hex_key(key: &str) -> Result<Self, EncryptedIdError> {
+        let bytes = decode_hex(key.trim())
+            .and_then(|bytes| <[u8; ENCRYPTED_ID_KEY_LENGTH]>::try_from(bytes).ok())
+            .ok_or(EncryptedIdError::InvalidKey)?;
+        Ok(Self::new(bytes))
+    }
+
+    /// Include the ID of the issuing node
+    pub fn with_node_id(mut self, node_id: u16) -> Self {
+        self.node_id = Some(node_id);
+        self
+    }
+
+    /// Include a tenant ID
+    pub fn with_tenant_id(mut self, tenant_id: u32) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+
+    /// Omit the issue timestamp, shortening tokens by about 11 characters
+    pub fn without_timestamp(mut self) -> Self {
+        self.timestamp = false;
+        self
+    }
+
+    /// Get the length of the tokens produced by [`EncryptedIds::generate`]
+    pub fn token_length(&self) -> usize {
+        let mut plaintext = HEADER_LENGTH;
+        if self.timestamp {
+            plaintext += 8;
+        }
+        if self.node_id.is_some() {
+            plaintext += 2;
+        }
+        if self.tenant_id.is_some() {
+            plaintext += 4;
+        }
+        // Unpadded base64 encodes every 3 bytes as 4 characters
+        ((NONCE_LENGTH + plaintext + TAG_LENGTH) * 4).div_ceil(3)
+    }
+
+    /// Generate a token carrying the configured metadata and the current time
+    pub fn generate(&self) -> String {
+        self.encode(&IdMetadata {
+            timestamp: self.timestamp.then(SystemTime::now),
+            node_id: self.node_id,
+            tenant_id: self.tenant_id,
+        })
+    }
+
+    /// Encrypt arbitrary metadata into a token
+    ///
+    /// Timestamps are truncated to whole milliseconds.
+    pub fn encode(&self, metadata: &IdMetadata) -> String {
+        let mut flags = 0;
+        let mut plaintext = Vec::with_capacity(16);
+        plaintext.extend_from_slice(&[FORMAT_VERSION, 0]);
+
+        if let Some(timestamp) = metadata.timestamp {
+            flags |= FLAG_TIMESTAMP;
+            let millis = timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64);
+            plaintext.extend_from_slice(&millis.to_be_bytes());
+        }
+        if let Some(node_id) = metadata.node_id {
+            flags |= FLAG_NODE_ID;
+            plaintext.extend_from_slice(&node_id.to_be_bytes());
+        }
+        if let Some(tenant_id) = metadata.tenant_id {
+            flags |= FLAG_TENANT_ID;
+            plaintext.extend_from_slice(&tenant_id.to_be_bytes());
+        }
+        plaintext[1] = flags;
+
+        let mut nonce = [0u8; NONCE_LENGTH];
+        fill_random(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: ASSOCIATED_DATA,
+                },
+            )
+            .expect("plaintext is far below the XChaCha20-Poly1305 size limit");
+
+        let mut token = nonce.to_vec();
+        token.extend_from_slice(&ciphertext);
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    /// Decrypt the metadata of a token
+    ///
+    /// The token must be exactly as generated; use [`EncryptedIds::find_and_decode`]
+    /// for IDs with a prefix, suffix or signature.
+    pub fn decode(&self, token: &str) -> Result<IdMetadata, EncryptedIdError> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| EncryptedIdError::InvalidEncoding)?;
+        if bytes.len() < NONCE_LENGTH + HEADER_LENGTH + TAG_LENGTH {
+            return Err(EncryptedIdError::InvalidLength(bytes.len()));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: ASSOCIATED_DATA,
+                },
+            )
+            .map_err(|_| EncryptedIdError::AuthenticationFailed)?;
+
+        parse_plaintext(&plaintext)
+    }
+
+    /// Find an encrypted token inside a full request ID and decrypt it
+    ///
+    /// Tries the whole ID first, then every substring delimited by `_`, `-`, `.` or
+    /// `:`, so tokens can be found behind namespaces and signatures without knowing
+    /// the separator. Only a substring that authenticates is accepted.
+    pub fn find_and_decode(&self, id: &str) -> Result<IdMetadata, EncryptedIdError> {
+        let first_error = match self.decode(id) {
+            Ok(metadata) => return Ok(metadata),
+            Err(e) => e,
+        };
+
+        let separators: Vec<usize> = id
+            .match_indices(['_', '-', '.', ':'])
+            .map(|(index, _)| index)
+            .collect();
+        let starts = std::iter::once(0).chain(separators.iter().map(|i| i + 1));
+        for start in starts {
+            let ends = separators
+                .iter()
+                .copied()
+                .filter(|&end| end > start)
+                .chain(std::iter::once(id.len()));
+            for end in ends {
+                if (start, end) == (0, id.len()) {
+                    continue;
+                }
+                if let Ok(metadata) = self.decode(&id[start..end]) {
+                    return Ok(metadata);
+                }
+            }
+        }
+        Err(first_error)
+    }
+}
+
+impl fmt::Debug for EncryptedIds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the key
+        f.debug_struct("EncryptedIds")
+            .field("timestamp", &self.timestamp)
+            .field("node_id", &self.node_id)
+            .field("tenant_id", &self.tenant_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parse a decrypted plaintext
+fn parse_plaintext(plaintext: &[u8]) -> Result<IdMetadata, EncryptedIdError> {
+    let invalid_length = EncryptedIdError::InvalidLength(plaintext.len());
+    let (version, flags, mut fields) = match plaintext {
+        [version, flags, fields @ ..] => (*version, *flags, fields),
+        _ => return Err(invalid_length),
+    };
+    if version != FORMAT_VERSION {
+        return Err(EncryptedIdError::UnsupportedVersion(version));
+    }
+
+    let mut take = |n: usize| -> Result<&[u8], EncryptedIdError> {
+        if fields.len() < n {
+            return Err(invalid_length.clone());
+        }
+        let (field, rest) = fields.split_at(n);
+        fields = rest;
+        Ok(field)
+    };
+
+    let mut metadata = IdMetadata::default();
+    if flags & FLAG_TIMESTAMP != 0 {
+        let millis = u64::from_be_bytes(take(8)?.try_into().unwrap());
+        metadata.timestamp = Some(UNIX_EPOCH + Duration::from_millis(millis));
+    }
+    if flags & FLAG_NODE_ID != 0 {
+        metadata.node_id = Some(u16::from_be_bytes(take(2)?.try_into().unwrap()));
+    }
+    if flags & FLAG_TENANT_ID != 0 {
+        metadata.tenant_id = Some(u32::from_be_bytes(take(4)?.try_into().unwrap()));
+    }
+
+    if !fields.is_empty() {
+        return Err(invalid_length);
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod encrypted_tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// Test that generated tokens round trip with every field combination
+    #[test]
+    fn test_round_trip() {
+        let base = EncryptedIds::from_hex_key(KEY).unwrap();
+        let configs = vec![
+            base.clone(),
+            base.clone().without_timestamp(),
+            base.clone().with_node_id(7),
+            base.clone().with_tenant_id(u32::MAX).without_timestamp(),
+            base.with_node_id(65535).with_tenant_id(42),
+        ];
+
+        for ids in configs {
+            let before = SystemTime::now() - Duration::from_millis(1);
+            let token = ids.generate();
+            assert_eq!(token.len(), ids.token_length());
+            assert!(token
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+
+            let metadata = ids.decode(&token).unwrap();
+            assert_eq!(metadata.node_id, ids.node_id);
+            assert_eq!(metadata.tenant_id, ids.tenant_id);
+            assert_eq!(metadata.timestamp.is_some(), ids.timestamp);
+            if let Some(timestamp) = metadata.timestamp {
+                assert!(timestamp >= before && timestamp <= SystemTime::now());
+            }
+        }
+    }
+
+    /// Test that identical metadata still yields unique tokens
+    #[test]
+    fn test_tokens_are_unique() {
+        let ids = EncryptedIds::new([1; 32])
+            .without_timestamp()
+            .with_node_id(1);
+        let mut tokens: Vec<String> = (0..100).map(|_| ids.generate()).collect();
+        tokens.sort_unstable();
+        tokens.dedup();
+        assert_eq!(tokens.len(), 100);
+    }
+
+    /// Test that foreign and tampered tokens are rejected
+    #[test]
+    fn test_rejects_forgeries() {
+        let ids = EncryptedIds::new([1; 32]).with_tenant_id(5);
+        let token = ids.generate();
+
+        let other = EncryptedIds::new([2; 32]);
+        assert_eq!(
+            other.decode(&token),
+            Err(EncryptedIdError::AuthenticationFailed)
+        );
+
+        let mut tampered = token.clone().into_bytes();
+        let last = tampered.len() - 5;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        assert_eq!(
+            ids.decode(std::str::from_utf8(&tampered).unwrap()),
+            Err(EncryptedIdError::AuthenticationFailed)
+        );
+
+        assert_eq!(
+            ids.decode("not base64!"),
+            Err(EncryptedIdError::InvalidEncoding)
+        );
+        assert_eq!(ids.decode("AAAA"), Err(EncryptedIdError::InvalidLength(3)));
+    }
+
+    /// Test explicit metadata and millisecond truncation
+    #[test]
+    fn test_encode_metadata() {
+        let ids = EncryptedIds::new([3; 32]);
+        let metadata = IdMetadata {
+            timestamp: Some(UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)),
+            node_id: None,
+            tenant_id: Some(9),
+        };
+
+        let decoded = ids.decode(&ids.encode(&metadata)).unwrap();
+        assert_eq!(
+            decoded.timestamp,
+            Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123))
+        );
+        assert_eq!(decoded.tenant_id, Some(9));
+        assert_eq!(decoded.node_id, None);
+    }
+
+    /// Test the line-per-field output, as printed by `request-id-decode`
+    #[test]
+    fn test_display_metadata() {
+        let metadata = IdMetadata {
+            timestamp: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            node_id: None,
+            tenant_id: Some(9),
+        };
+        assert_eq!(
+            metadata.to_string(),
+            "timestamp: 2023-11-14T22:13:20.123Z (1700000000123)\nnode_id:   -\ntenant_id: 9"
+        );
+    }
+
+    /// Test finding tokens inside namespaced and signed IDs
+    #[test]
+    fn test_find_and_decode() {
+        let ids = EncryptedIds::new([4; 32]).with_node_id(12);
+        for _ in 0..20 {
+            let token = ids.generate();
+            for id in [
+                format!("api_{}", token),
+                format!("{}:v1", token),
+                format!("api_{}_eu.k1.0011aabb", token),
+            ] {
+                assert_eq!(ids.find_and_decode(&id).unwrap().node_id, Some(12));
+            }
+        }
+
+        assert_eq!(
+            ids.find_and_decode("api_550e8400-e29b-41d4"),
+            Err(EncryptedIdError::InvalidEncoding)
+        );
+    }
+
+    /// Test key parsing
+    #[test]
+    fn test_from_hex_key() {
+        assert!(EncryptedIds::from_hex_key(&KEY.to_uppercase()).is_ok());
+        assert_eq!(
+            EncryptedIds::from_hex_key(&KEY[2..]).err(),
+            Some(EncryptedIdError::InvalidKey)
+        );
+        assert_eq!(
+            EncryptedIds::from_hex_key(&KEY.replace('0', "g")).err(),
+            Some(EncryptedIdError::InvalidKey)
+        );
+        // `u8::from_str_radix` would accept "+0" as 0
+        assert_eq!(
+            EncryptedIds::from_hex_key(&"+0".repeat(32)).err(),
+            Some(EncryptedIdError::InvalidKey)
+        );
+    }
+
+    /// Test that the key is not printed
+    #[test]
+    fn test_debug_hides_key() {
+        let debug = format!("{:?}", EncryptedIds::new([0xab; 32]).with_node_id(1));
+        assert!(debug.contains("node_id"));
+        assert!(!debug.contains("171"));
+    }
+}
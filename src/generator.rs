@@ -0,0 +1,525 @@
+//! Request ID generators and length post-processing
+//!
+//! Every generator produces a core ID. If a length is configured on the middleware,
+//! the core ID is then truncated or padded with random characters so that ID length
+//! and ID format can be configured independently of each other.
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::{Builder, Uuid};
+
+use crate::deterministic::{SharedCounter, SharedSeeded, DEFAULT_SEQUENTIAL_WIDTH};
+use crate::rng::Entropy;
+#[cfg(feature = "encrypted-ids")]
+use crate::EncryptedIds;
+use crate::{Alphabet, Ksuid, KSUID_LENGTH};
+
+/// Type for request ID generator function
+pub(crate) type RequestIDGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Characters used to pad IDs that are shorter than the configured length
+const PADDING_CHARSET: &[u8] = b"0123456789abcdef";
+
+/// Generator used by the middleware to produce the core ID
+#[derive(Clone)]
+pub(crate) enum Generator {
+    /// UUID v4 with hyphens (36 characters)
+    Uuid,
+    /// UUID v4 without hyphens (32 characters)
+    SimpleUuid,
+    /// Time-ordered UUID v7 with hyphens (36 characters)
+    UuidV7,
+    /// Random characters drawn uniformly from an alphabet
+    Compact(Alphabet),
+    /// Nanoid-style ID drawn from a custom alphabet with a default size
+    Nanoid { alphabet: Arc<str>, size: usize },
+    /// KSUID with a second-precision timestamp (27 base62 characters)
+    Ksuid,
+    /// Opaque token carrying encrypted metadata
+    #[cfg(feature = "encrypted-ids")]
+    Encrypted(Arc<EncryptedIds>),
+    /// Reproducible UUID v4 formatted IDs from a seeded PRNG
+    Seeded(SharedSeeded),
+    /// Zero-padded counter
+    Sequential(SharedCounter),
+    /// User supplied generator function
+    Custom(RequestIDGenerator),
+}
+
+impl Generator {
+    /// Generate a new core ID
+    #[cfg(test)]
+    pub(crate) fn generate(&self) -> String {
+        let mut id = String::new();
+        self.write(&mut id, Entropy::default());
+        id
+    }
+
+    /// Generate a new core ID of exactly `length` characters
+    #[cfg(test)]
+    pub(crate) fn generate_with_length(&self, length: usize) -> String {
+        let mut id = String::with_capacity(length);
+        self.write_with_length(&mut id, length, Entropy::default());
+        id
+    }
+
+    /// Append a new core ID to `out`, drawing random bytes from `entropy`
+    ///
+    /// The built-in random generators write directly into `out` without allocating.
+    pub(crate) fn write(&self, out: &mut String, entropy: Entropy) {
+        match self {
+            Generator::Uuid => out.push_str(
+                uuid_v4(entropy)
+                    .hyphenated()
+                    .encode_lower(&mut Uuid::encode_buffer()),
+            ),
+            Generator::SimpleUuid => out.push_str(
+                uuid_v4(entropy)
+                    .simple()
+                    .encode_lower(&mut Uuid::encode_buffer()),
+            ),
+            Generator::UuidV7 => out.push_str(
+                uuid_v7(entropy)
+                    .hyphenated()
+                    .encode_lower(&mut Uuid::encode_buffer()),
+            ),
+            Generator::Compact(alphabet) => push_random(
+                out,
+                alphabet.chars().as_bytes(),
+                alphabet.default_length(),
+                entropy,
+            ),
+            Generator::Nanoid { alphabet, size } => {
+                push_random(out, alphabet.as_bytes(), *size, entropy)
+            }
+            Generator::Ksuid => {
+                let mut payload = [0u8; 16];
+                entropy.fill(&mut payload);
+                let ksuid = Ksuid::from_parts(SystemTime::now(), payload);
+                write!(out, "{}", ksuid).expect("writing to a String cannot fail")
+            }
+            #[cfg(feature = "encrypted-ids")]
+            Generator::Encrypted(ids) => out.push_str(&ids.generate()),
+            Generator::Seeded(seeded) => out.push_str(&seeded.next_id(None)),
+            Generator::Sequential(counter) => {
+                out.push_str(&counter.next_id(DEFAULT_SEQUENTIAL_WIDTH))
+            }
+            Generator::Custom(f) => out.push_str(&f()),
+        }
+    }
+
+    /// Append a new core ID of exactly `length` characters to `out`
+    pub(crate) fn write_with_length(&self, out: &mut String, length: usize, entropy: Entropy) {
+        match self {
+            // Compact IDs are generated at the requested length directly
+            Generator::Compact(alphabet) => {
+                push_random(out, alphabet.chars().as_bytes(), length, entropy)
+            }
+            Generator::Nanoid { alphabet, .. } => {
+                push_random(out, alphabet.as_bytes(), length, entropy)
+            }
+            // Deterministic generators must not be padded with random characters
+            Generator::Seeded(seeded) => out.push_str(&seeded.next_id(Some(length))),
+            Generator::Sequential(counter) => out.push_str(&counter.next_id(length)),
+            _ => {
+                let start = out.len();
+                self.write(out, entropy);
+                fit_length_at(out, start, length, self.padding_charset(), entropy);
+            }
+        }
+    }
+
+    /// Length of the IDs produced by this generator, if it is fixed
+    pub(crate) fn natural_length(&self) -> Option<usize> {
+        match self {
+            Generator::Uuid => Some(36),
+            Generator::SimpleUuid => Some(32),
+            Generator::UuidV7 => Some(36),
+            Generator::Compact(alphabet) => Some(alphabet.default_length()),
+            Generator::Nanoid { size, .. } => Some(*size),
+            Generator::Ksuid => Some(KSUID_LENGTH),
+            #[cfg(feature = "encrypted-ids")]
+            Generator::Encrypted(ids) => Some(ids.token_length()),
+            Generator::Seeded(_) => Some(36),
+            Generator::Sequential(_) => Some(DEFAULT_SEQUENTIAL_WIDTH),
+            Generator::Custom(_) => None,
+        }
+    }
+
+    /// Characters used to pad IDs from this generator
+    fn padding_charset(&self) -> &[u8] {
+        match self {
+            Generator::Compact(alphabet) => alphabet.chars().as_bytes(),
+            Generator::Nanoid { alphabet, .. } => alphabet.as_bytes(),
+            Generator::Ksuid => Alphabet::Base62.chars().as_bytes(),
+            #[cfg(feature = "encrypted-ids")]
+            Generator::Encrypted(_) => Alphabet::UrlSafeBase64.chars().as_bytes(),
+            _ => PADDING_CHARSET,
+        }
+    }
+
+    /// Bits of entropy in an ID of `length` characters, if known
+    ///
+    /// Returns `None` for custom generators, whose output cannot be inspected, and for
+    /// deterministic generators, which have no entropy but never collide either.
+    pub(crate) fn entropy_bits(&self, length: usize) -> Option<f64> {
+        let padding_bits = (PADDING_CHARSET.len() as f64).log2();
+        match self {
+            Generator::Uuid => {
+                let uuid_bits: u32 = (0..length.min(36)).map(hyphenated_uuid_bits).sum();
+                Some(uuid_bits as f64 + length.saturating_sub(36) as f64 * padding_bits)
+            }
+            Generator::SimpleUuid => {
+                let uuid_bits: u32 = (0..length.min(32)).map(simple_uuid_bits).sum();
+                Some(uuid_bits as f64 + length.saturating_sub(32) as f64 * padding_bits)
+            }
+            Generator::UuidV7 => {
+                let uuid_bits: u32 = (0..length.min(36)).map(hyphenated_uuid_v7_bits).sum();
+                Some(uuid_bits as f64 + length.saturating_sub(36) as f64 * padding_bits)
+            }
+            Generator::Compact(alphabet) => Some(length as f64 * alphabet.bits_per_char()),
+            Generator::Nanoid { alphabet, .. } => {
+                Some(length as f64 * (alphabet.len() as f64).log2())
+            }
+            Generator::Ksuid => {
+                // The leading characters encode the timestamp and the trailing ones the
+                // 128-bit payload, so truncation removes payload bits first
+                let bits_per_char = Alphabet::Base62.bits_per_char();
+                let encoded_bits = KSUID_LENGTH as f64 * bits_per_char;
+                let kept = (length.min(KSUID_LENGTH) as f64 * bits_per_char
+                    - (encoded_bits - 128.0))
+                    .clamp(0.0, 128.0);
+                Some(kept + length.saturating_sub(KSUID_LENGTH) as f64 * bits_per_char)
+            }
+            #[cfg(feature = "encrypted-ids")]
+            Generator::Encrypted(ids) => {
+                // The leading 32 characters encode the random nonce; the ciphertext that
+                // follows is determined by the nonce and the metadata
+                let bits_per_char = Alphabet::UrlSafeBase64.bits_per_char();
+                let nonce_chars = 32;
+                Some(
+                    length.min(nonce_chars) as f64 * bits_per_char
+                        + length.saturating_sub(ids.token_length()) as f64 * bits_per_char,
+                )
+            }
+            Generator::Seeded(_) | Generator::Sequential(_) | Generator::Custom(_) => None,
+        }
+    }
+}
+
+/// Random bits carried by the character at `index` of a hyphenated UUID v4
+fn hyphenated_uuid_bits(index: usize) -> u32 {
+    match index {
+        // Hyphens and the version nibble are fixed
+        8 | 13 | 14 | 18 | 23 => 0,
+        // The variant nibble has two random bits
+        19 => 2,
+        _ => 4,
+    }
+}
+
+/// Random bits carried by the character at `index` of a hyphenated UUID v7
+fn hyphenated_uuid_v7_bits(index: usize) -> u32 {
+    match index {
+        // The leading 48 bits are the timestamp
+        0..=12 => 0,
+        _ => hyphenated_uuid_bits(index),
+    }
+}
+
+/// Random bits carried by the character at `index` of a simple UUID v4
+fn simple_uuid_bits(index: usize) -> u32 {
+    match index {
+        12 => 0,
+        16 => 2,
+        _ => 4,
+    }
+}
+
+/// Estimate the probability of at least one collision among `count` IDs with `bits` of entropy
+///
+/// Uses the birthday bound `1 - exp(-n(n-1) / 2^(bits+1))`.
+pub(crate) fn collision_probability(bits: f64, count: u64) -> f64 {
+    let n = count as f64;
+    -(-(n * (n - 1.0)) / 2f64.powf(bits + 1.0)).exp_m1()
+}
+
+/// Fit the ID starting at byte `start` of `out` to the given length, in place
+///
+/// Longer IDs are truncated, shorter IDs are padded with random characters from
+/// `charset` so that an ID never carries less entropy than requested.
+fn fit_length_at(out: &mut String, start: usize, length: usize, charset: &[u8], entropy: Entropy) {
+    match out[start..].char_indices().nth(length) {
+        Some((end, _)) => out.truncate(start + end),
+        None => {
+            let missing = length - out[start..].chars().count();
+            push_random(out, charset, missing, entropy);
+        }
+    }
+}
+
+/// Create a random UUID v4
+fn uuid_v4(entropy: Entropy) -> Uuid {
+    let mut bytes = [0u8; 16];
+    entropy.fill(&mut bytes);
+    Builder::from_random_bytes(bytes).into_uuid()
+}
+
+/// Create a UUID v7 for the current time
+fn uuid_v7(entropy: Entropy) -> Uuid {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let mut random = [0u8; 10];
+    entropy.fill(&mut random);
+    Builder::from_unix_timestamp_millis(millis, &random).into_uuid()
+}
+
+/// Append `length` random characters drawn uniformly from `charset` to `out`
+///
+/// `charset` must contain between 1 and 256 ASCII characters. Random bytes are
+/// mapped onto the charset using rejection sampling so that every character is
+/// equally likely.
+fn push_random(out: &mut String, charset: &[u8], length: usize, entropy: Entropy) {
+    debug_assert!(!charset.is_empty() && charset.len() <= 256);
+
+    // Largest multiple of the charset size that fits in a byte; bytes at or above it are rejected
+    let limit = 256 - 256 % charset.len();
+    let end = out.len() + length;
+    out.reserve(length);
+    let mut buf = [0u8; 64];
+
+    while out.len() < end {
+        entropy.fill(&mut buf);
+        for &byte in buf.iter() {
+            if (byte as usize) < limit {
+                out.push(charset[byte as usize % charset.len()] as char);
+                if out.len() == end {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Check that a custom alphabet can be sampled uniformly and used in a header value
+///
+/// The alphabet must consist of 1 to 256 unique visible ASCII characters.
+pub(crate) fn validate_alphabet(alphabet: &str) -> Result<(), &'static str> {
+    if alphabet.is_empty() || alphabet.len() > 256 {
+        return Err("Alphabet must contain between 1 and 256 characters");
+    }
+    if !alphabet.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err("Alphabet must only contain visible ASCII characters");
+    }
+    let mut seen = [false; 128];
+    for b in alphabet.bytes() {
+        if std::mem::replace(&mut seen[b as usize], true) {
+            return Err("Alphabet must not contain duplicate characters");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod generator_tests {
+    use super::*;
+
+    fn fit_length(mut id: String, length: usize, charset: &[u8]) -> String {
+        fit_length_at(&mut id, 0, length, charset, Entropy::default());
+        id
+    }
+
+    fn random_string(charset: &[u8], length: usize) -> String {
+        let mut id = String::new();
+        push_random(&mut id, charset, length, Entropy::default());
+        id
+    }
+
+    /// Test truncation and padding of IDs
+    #[test]
+    fn test_fit_length() {
+        assert_eq!(fit_length("abcdef".to_string(), 3, PADDING_CHARSET), "abc");
+        assert_eq!(
+            fit_length("abcdef".to_string(), 6, PADDING_CHARSET),
+            "abcdef"
+        );
+
+        let padded = fit_length("abc".to_string(), 40, PADDING_CHARSET);
+        assert_eq!(padded.len(), 40);
+        assert!(padded.starts_with("abc"));
+        assert!(padded[3..].bytes().all(|b| PADDING_CHARSET.contains(&b)));
+    }
+
+    /// Test that random strings only use the given charset
+    #[test]
+    fn test_random_string_charset() {
+        let charset = b"xyz";
+        let id = random_string(charset, 1000);
+        assert_eq!(id.len(), 1000);
+        assert!(id.bytes().all(|b| charset.contains(&b)));
+    }
+
+    /// Test the natural length of built-in generators
+    #[test]
+    fn test_natural_length() {
+        for generator in [
+            Generator::Uuid,
+            Generator::SimpleUuid,
+            Generator::UuidV7,
+            Generator::Compact(Alphabet::Base58),
+            Generator::Ksuid,
+        ] {
+            assert_eq!(Some(generator.generate().len()), generator.natural_length());
+        }
+        let custom = Generator::Custom(Arc::new(|| "custom".to_string()));
+        assert_eq!(custom.natural_length(), None);
+    }
+
+    /// Test entropy accounting of built-in generators
+    #[test]
+    fn test_entropy_bits() {
+        assert_eq!(Generator::Uuid.entropy_bits(36), Some(122.0));
+        assert_eq!(Generator::SimpleUuid.entropy_bits(32), Some(122.0));
+
+        // "550e8400" is 8 hex characters, the hyphen adds nothing
+        assert_eq!(Generator::Uuid.entropy_bits(8), Some(32.0));
+        assert_eq!(Generator::Uuid.entropy_bits(9), Some(32.0));
+        // Padding beyond the UUID adds 4 bits per character
+        assert_eq!(Generator::Uuid.entropy_bits(40), Some(138.0));
+
+        // UUID v7 starts with a 48-bit timestamp
+        assert_eq!(Generator::UuidV7.entropy_bits(36), Some(74.0));
+        assert_eq!(Generator::UuidV7.entropy_bits(13), Some(0.0));
+
+        assert_eq!(Generator::Ksuid.entropy_bits(27), Some(128.0));
+        assert_eq!(Generator::Ksuid.entropy_bits(5), Some(0.0));
+
+        let base32 = Generator::Compact(Alphabet::Crockford32);
+        assert_eq!(base32.entropy_bits(10), Some(50.0));
+
+        let custom = Generator::Custom(Arc::new(|| "custom".to_string()));
+        assert_eq!(custom.entropy_bits(6), None);
+    }
+
+    /// Test compact IDs are generated at the requested length
+    #[test]
+    fn test_compact_generate_with_length() {
+        let generator = Generator::Compact(Alphabet::Base62);
+        let id = generator.generate_with_length(100);
+        assert_eq!(id.len(), 100);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    /// Test birthday bound estimates
+    #[test]
+    fn test_collision_probability() {
+        // 2^16 IDs with 32 bits of entropy: roughly 1 - e^-0.5
+        let p = collision_probability(32.0, 1 << 16);
+        assert!((p - 0.393).abs() < 0.001, "{}", p);
+
+        assert_eq!(collision_probability(32.0, 1), 0.0);
+        assert!(collision_probability(122.0, 1_000_000_000) < 1e-18);
+        assert!(collision_probability(8.0, 10_000) > 0.999);
+    }
+
+    /// Chi-squared statistic of character frequencies against a uniform distribution
+    fn chi_squared(charset: &[u8], sample: &str) -> f64 {
+        let mut counts = [0usize; 256];
+        for b in sample.bytes() {
+            counts[b as usize] += 1;
+        }
+        let expected = sample.len() as f64 / charset.len() as f64;
+        charset
+            .iter()
+            .map(|&c| {
+                let diff = counts[c as usize] as f64 - expected;
+                diff * diff / expected
+            })
+            .sum()
+    }
+
+    /// Test that rejection sampling yields a uniform distribution
+    ///
+    /// A plain `byte % 62` would make the first 8 characters ~25% more likely,
+    /// which is far beyond the critical values used here (p = 0.0001).
+    #[test]
+    fn test_random_string_uniformity() {
+        let generator = Generator::Nanoid {
+            alphabet: Arc::from(crate::NANOID_ALPHABET),
+            size: crate::DEFAULT_NANOID_SIZE,
+        };
+        let sample = generator.generate_with_length(200_000);
+        assert!(chi_squared(crate::NANOID_ALPHABET.as_bytes(), &sample) < 113.7);
+
+        let base62 = Alphabet::Base62.chars().as_bytes();
+        let sample = random_string(base62, 200_000);
+        assert!(chi_squared(base62, &sample) < 111.0);
+
+        let odd = b"abcde";
+        let sample = random_string(odd, 100_000);
+        assert!(chi_squared(odd, &sample) < 23.5);
+    }
+
+    /// Test validation of custom alphabets
+    #[test]
+    fn test_validate_alphabet() {
+        assert!(validate_alphabet("0123456789abcdef").is_ok());
+        assert!(validate_alphabet(crate::NANOID_ALPHABET).is_ok());
+        assert!(validate_alphabet("").is_err());
+        assert!(validate_alphabet("abca").is_err());
+        assert!(validate_alphabet("ab c").is_err());
+        assert!(validate_alphabet("abcé").is_err());
+    }
+
+    /// Test that IDs are appended after existing content and fitted on their own
+    #[test]
+    fn test_write_with_length_appends() {
+        let mut out = "prefix_".to_string();
+        Generator::Uuid.write_with_length(&mut out, 8, Entropy::Os);
+        assert_eq!(out.len(), 15);
+        assert!(out.starts_with("prefix_"));
+
+        let mut out = "p_".to_string();
+        Generator::SimpleUuid.write_with_length(&mut out, 40, Entropy::Os);
+        assert_eq!(out.len(), 42);
+        assert!(out[2..].bytes().all(|b| b.is_ascii_hexdigit()));
+    }
+
+    /// Test that UUID v7 carries the current time
+    #[test]
+    fn test_uuid_v7() {
+        let before = SystemTime::now();
+        let id = Generator::UuidV7.generate();
+        let uuid = Uuid::parse_str(&id).unwrap();
+
+        assert_eq!(uuid.get_version_num(), 7);
+        let (secs, _) = uuid.get_timestamp().unwrap().to_unix();
+        let before = before.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(secs >= before && secs <= before + 1);
+    }
+
+    /// Test that the thread-local source produces valid IDs of every kind
+    #[cfg(feature = "fast-rng")]
+    #[test]
+    fn test_thread_local_entropy() {
+        let mut ids = std::collections::HashSet::new();
+        for generator in [
+            Generator::Uuid,
+            Generator::UuidV7,
+            Generator::Compact(Alphabet::Base62),
+            Generator::Ksuid,
+        ] {
+            for _ in 0..100 {
+                let mut id = String::new();
+                generator.write(&mut id, Entropy::ThreadLocal);
+                assert_eq!(Some(id.len()), generator.natural_length());
+                assert!(ids.insert(id));
+            }
+        }
+
+        let mut id = String::new();
+        Generator::Uuid.write(&mut id, Entropy::ThreadLocal);
+        assert_eq!(Uuid::parse_str(&id).unwrap().get_version_num(), 4);
+    }
+}
@@ -0,0 +1,39 @@
+//! Strict hex decoding for keys and signatures
+
+/// Decode hex digits
+///
+/// Only `0-9`, `a-f` and `A-F` are accepted, in pairs; unlike `u8::from_str_radix`,
+/// signs and whitespace are rejected.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+
+    let digit = |b: u8| match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    };
+    pairs
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+#[cfg(test)]
+mod hex_tests {
+    use super::*;
+
+    /// Test that only pairs of hex digits are decoded
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("-1"), None);
+        assert_eq!(decode_hex(" 1"), None);
+        assert_eq!(decode_hex("0g"), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Request lifecycle hooks
+//!
+//! Hooks run inside the middleware with the request ID in hand, so applications can
+//! add audit logging, timing or cleanup without writing another middleware.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpRequest};
+
+use crate::{RequestID, StreamEnd};
+
+type HookFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+pub(crate) type RequestHook = Arc<dyn Fn(&RequestID, &ServiceRequest) + Send + Sync>;
+pub(crate) type AsyncRequestHook = Arc<dyn Fn(RequestID, HttpRequest) -> HookFuture + Send + Sync>;
+pub(crate) type ResponseHook =
+    Arc<dyn Fn(&RequestID, &ServiceResponse<()>, Duration) + Send + Sync>;
+pub(crate) type AsyncResponseHook =
+    Arc<dyn Fn(RequestID, HttpRequest, StatusCode, Duration) -> HookFuture + Send + Sync>;
+pub(crate) type ErrorHook = Arc<dyn Fn(&RequestID, &Error, Duration) + Send + Sync>;
+pub(crate) type StreamEndHook = Arc<dyn Fn(&RequestID, &StreamEnd) + Send + Sync>;
+
+/// Hooks registered on a middleware, run in registration order
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) request: Vec<RequestHook>,
+    pub(crate) request_async: Vec<AsyncRequestHook>,
+    pub(crate) response: Vec<ResponseHook>,
+    pub(crate) response_async: Vec<AsyncResponseHook>,
+    pub(crate) error: Vec<ErrorHook>,
+    pub(crate) stream_end: Vec<StreamEndHook>,
+}
+
+impl Hooks {
+    /// Box the future returned by an async hook
+    pub(crate) fn boxed<Fut: Future<Output = ()> + 'static>(fut: Fut) -> HookFuture {
+        Box::pin(fut)
+    }
+
+    /// Check whether the inner service must wait for async request hooks
+    pub(crate) fn defers_request(&self) -> bool {
+        !self.request_async.is_empty()
+    }
+
+    /// Run the synchronous request hooks
+    pub(crate) fn run_request(&self, id: &RequestID, req: &ServiceRequest) {
+        for hook in &self.request {
+            hook(id, req);
+        }
+    }
+
+    /// Run the async request hooks one after another
+    pub(crate) async fn run_request_async(&self, id: &RequestID, req: &HttpRequest) {
+        for hook in &self.request_async {
+            hook(id.clone(), req.clone()).await;
+        }
+    }
+
+    /// Run the response hooks, returning the response unchanged
+    ///
+    /// Synchronous hooks see the response without its body, which is split off for
+    /// the duration of the hooks so that they work for any body type.
+    pub(crate) async fn run_response<B>(
+        &self,
+        id: &RequestID,
+        res: ServiceResponse<B>,
+        elapsed: Duration,
+    ) -> ServiceResponse<B> {
+        if let Some(error) = res.response().error() {
+            self.run_error(id, error, elapsed);
+        }
+
+        let res = if self.response.is_empty() {
+            res
+        } else {
+            let (req, res) = res.into_parts();
+            let (head, body) = res.into_parts();
+            let head = ServiceResponse::new(req, head);
+            for hook in &self.response {
+                hook(id, &head, elapsed);
+            }
+            let (req, head) = head.into_parts();
+            ServiceResponse::new(req, head.set_body(body))
+        };
+
+        for hook in &self.response_async {
+            hook(id.clone(), res.request().clone(), res.status(), elapsed).await;
+        }
+        res
+    }
+
+    /// Run the error hooks
+    pub(crate) fn run_error(&self, id: &RequestID, error: &Error, elapsed: Duration) {
+        for hook in &self.error {
+            hook(id, error, elapsed);
+        }
+    }
+
+    /// Run the end-of-stream hooks
+    pub(crate) fn run_stream_end(&self, id: &RequestID, end: &StreamEnd) {
+        for hook in &self.stream_end {
+            hook(id, end);
+        }
+    }
+}
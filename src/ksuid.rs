@@ -0,0 +1,244 @@
+//! K-Sortable Unique IDentifiers
+//!
+//! A KSUID is 20 bytes: a 32-bit big-endian timestamp in seconds since
+//! [`KSUID_EPOCH`] followed by 128 random bits, encoded as 27 base62 characters.
+//! The string encoding sorts in the same order as the timestamp, so KSUIDs can be
+//! used as naturally ordered event keys.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::rng::fill_random;
+use crate::Alphabet;
+
+/// KSUID epoch in seconds since the Unix epoch (2014-05-13T16:53:20Z)
+pub const KSUID_EPOCH: u64 = 1_400_000_000;
+/// Length of the string encoding of a KSUID
+pub const KSUID_LENGTH: usize = 27;
+
+/// Number of bytes in a KSUID
+const KSUID_BYTES: usize = 20;
+
+/// A K-Sortable Unique IDentifier
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::Ksuid;
+///
+/// let ksuid: Ksuid = "0ujtsYcgvSTl8PAuAdqWYSMnLOv".parse().unwrap();
+/// assert_eq!(ksuid.unix_timestamp(), 1_507_608_047);
+/// assert_eq!(ksuid.to_string(), "0ujtsYcgvSTl8PAuAdqWYSMnLOv");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ksuid([u8; KSUID_BYTES]);
+
+/// Error returned when parsing an invalid KSUID string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KsuidParseError {
+    /// The string is not exactly 27 characters long
+    InvalidLength(usize),
+    /// The string contains a character that is not base62
+    InvalidCharacter(char),
+    /// The decoded value does not fit in 20 bytes
+    Overflow,
+}
+
+impl fmt::Display for KsuidParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KsuidParseError::InvalidLength(len) => write!(
+                f,
+                "KSUID must be {} characters long, got {}",
+                KSUID_LENGTH, len
+            ),
+            KsuidParseError::InvalidCharacter(c) => {
+                write!(f, "invalid base62 character {:?} in KSUID", c)
+            }
+            KsuidParseError::Overflow => write!(f, "KSUID value exceeds 160 bits"),
+        }
+    }
+}
+
+impl std::error::Error for KsuidParseError {}
+
+impl Default for Ksuid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ksuid {
+    /// Create a KSUID for the current time with a random payload
+    pub fn new() -> Self {
+        let mut payload = [0u8; 16];
+        fill_random(&mut payload);
+        Self::from_parts(SystemTime::now(), payload)
+    }
+
+    /// Create a KSUID from a timestamp and payload
+    ///
+    /// The timestamp is truncated to whole seconds and clamped to the range
+    /// representable by a KSUID.
+    pub fn from_parts(timestamp: SystemTime, payload: [u8; 16]) -> Self {
+        let unix = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let offset = unix.saturating_sub(KSUID_EPOCH).min(u32::MAX as u64) as u32;
+
+        let mut bytes = [0u8; KSUID_BYTES];
+        bytes[..4].copy_from_slice(&offset.to_be_bytes());
+        bytes[4..].copy_from_slice(&payload);
+        Self(bytes)
+    }
+
+    /// Create a KSUID from its 20-byte binary representation
+    pub fn from_bytes(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    /// Get the 20-byte binary representation
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Get the timestamp in seconds since the Unix epoch
+    pub fn unix_timestamp(&self) -> u64 {
+        let offset = u32::from_be_bytes([self.0[0], self.0[1], self.0[2], self.0[3]]);
+        KSUID_EPOCH + offset as u64
+    }
+
+    /// Get the timestamp as a [`SystemTime`]
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.unix_timestamp())
+    }
+
+    /// Get the 16-byte random payload
+    pub fn payload(&self) -> &[u8] {
+        &self.0[4..]
+    }
+}
+
+impl fmt::Display for Ksuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = Alphabet::Base62.chars().as_bytes();
+        let mut number = self.0;
+        let mut encoded = [digits[0]; KSUID_LENGTH];
+
+        // Repeated long division of the big-endian number by 62
+        for slot in encoded.iter_mut().rev() {
+            let mut remainder = 0u32;
+            for byte in number.iter_mut() {
+                let value = (remainder << 8) | *byte as u32;
+                *byte = (value / 62) as u8;
+                remainder = value % 62;
+            }
+            *slot = digits[remainder as usize];
+        }
+
+        // Base62 digits are ASCII
+        f.write_str(std::str::from_utf8(&encoded).unwrap())
+    }
+}
+
+impl FromStr for Ksuid {
+    type Err = KsuidParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != KSUID_LENGTH {
+            return Err(KsuidParseError::InvalidLength(s.chars().count()));
+        }
+
+        let mut number = [0u8; KSUID_BYTES];
+        for c in s.chars() {
+            let digit = match c {
+                '0'..='9' => c as u32 - '0' as u32,
+                'A'..='Z' => c as u32 - 'A' as u32 + 10,
+                'a'..='z' => c as u32 - 'a' as u32 + 36,
+                _ => return Err(KsuidParseError::InvalidCharacter(c)),
+            };
+
+            // number = number * 62 + digit
+            let mut carry = digit;
+            for byte in number.iter_mut().rev() {
+                let value = *byte as u32 * 62 + carry;
+                *byte = value as u8;
+                carry = value >> 8;
+            }
+            if carry != 0 {
+                return Err(KsuidParseError::Overflow);
+            }
+        }
+
+        Ok(Self(number))
+    }
+}
+
+#[cfg(test)]
+mod ksuid_tests {
+    use super::*;
+
+    /// Test against the reference example from the KSUID specification
+    #[test]
+    fn test_reference_ksuid() {
+        let ksuid: Ksuid = "0ujtsYcgvSTl8PAuAdqWYSMnLOv".parse().unwrap();
+        assert_eq!(ksuid.unix_timestamp(), 1_507_608_047);
+        assert_eq!(
+            ksuid.payload(),
+            &[
+                0xB5, 0xA1, 0xCD, 0x34, 0xB5, 0xF9, 0x9D, 0x11, 0x54, 0xFB, 0x68, 0x53, 0x34, 0x5C,
+                0x97, 0x35
+            ]
+        );
+        assert_eq!(ksuid.to_string(), "0ujtsYcgvSTl8PAuAdqWYSMnLOv");
+    }
+
+    /// Test the minimum and maximum values
+    #[test]
+    fn test_bounds() {
+        let min = Ksuid::from_bytes([0; 20]);
+        assert_eq!(min.to_string(), "000000000000000000000000000");
+
+        let max = Ksuid::from_bytes([0xff; 20]);
+        assert_eq!(max.to_string(), "aWgEPTl1tmebfsQzFP4bxwgy80V");
+        assert_eq!("aWgEPTl1tmebfsQzFP4bxwgy80V".parse::<Ksuid>(), Ok(max));
+        assert_eq!(
+            "aWgEPTl1tmebfsQzFP4bxwgy80W".parse::<Ksuid>(),
+            Err(KsuidParseError::Overflow)
+        );
+    }
+
+    /// Test parse errors
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "0ujtsYcgvSTl8PAuAdqWYSMnLO".parse::<Ksuid>(),
+            Err(KsuidParseError::InvalidLength(26))
+        );
+        assert_eq!(
+            "0ujtsYcgvSTl8PAuAdqWYSMnLO-".parse::<Ksuid>(),
+            Err(KsuidParseError::InvalidCharacter('-'))
+        );
+    }
+
+    /// Test that the string encoding sorts by timestamp
+    #[test]
+    fn test_sort_order() {
+        let earlier =
+            Ksuid::from_parts(UNIX_EPOCH + Duration::from_secs(1_700_000_000), [0xff; 16]);
+        let later = Ksuid::from_parts(UNIX_EPOCH + Duration::from_secs(1_700_000_001), [0x00; 16]);
+        assert!(earlier < later);
+        assert!(earlier.to_string() < later.to_string());
+    }
+
+    /// Test round trip of random KSUIDs
+    #[test]
+    fn test_round_trip() {
+        for _ in 0..100 {
+            let ksuid = Ksuid::new();
+            let encoded = ksuid.to_string();
+            assert_eq!(encoded.len(), KSUID_LENGTH);
+            assert_eq!(encoded.parse::<Ksuid>(), Ok(ksuid));
+        }
+    }
+}
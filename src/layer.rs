@@ -0,0 +1,320 @@
+//! `tower` layer for services built on the `http` crate
+//!
+//! Applies the configuration of a [`RequestIDMiddleware`] to hyper, tonic or any other
+//! `tower` service. IDs are generated, propagated, signed and counted by the same code as
+//! in the actix-web middleware, so both stacks behave identically.
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::{HeaderName, HeaderValue};
+use http::{Request, Response};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::resolver::IdResolver;
+use crate::{context, RequestID, RequestIDMiddleware, Scoped};
+
+/// `tower` layer assigning request IDs like [`RequestIDMiddleware`]
+///
+/// Available with the `tower` feature. The layer takes the header name, generator,
+/// length, namespace, propagation, trusted networks, signing and metrics settings of a
+/// middleware. Hooks, access logs, recent requests and timing headers are specific to
+/// actix-web and not applied.
+///
+/// For every request, the layer:
+///
+/// - keeps a `RequestID` that is already in the request extensions, or determines the ID
+///   from the inbound header like the middleware does, and stores it in the extensions
+/// - installs the ID as the current request ID while the inner service's future is polled
+/// - adds the ID header to the response, which for tonic is the response metadata
+///
+/// Trusted networks are checked against a `std::net::SocketAddr` in the request
+/// extensions. Without one, the peer is unknown and inbound IDs are only honored if no
+/// trusted networks are configured.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{RequestIDLayer, RequestIDMiddleware};
+///
+/// let middleware = RequestIDMiddleware::new().with_ksuid().with_propagation();
+/// let layer = RequestIDLayer::new(&middleware);
+///
+/// // actix-web:  App::new().wrap(middleware)
+/// // tonic:      Server::builder().layer(layer)
+/// ```
+#[derive(Clone)]
+pub struct RequestIDLayer {
+    resolver: Arc<IdResolver>,
+    header_name: Option<HeaderName>,
+}
+
+impl RequestIDLayer {
+    /// Create a layer with the settings of a middleware
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - The middleware whose settings are applied; it can still be used
+    ///   with actix-web afterwards
+    pub fn new(middleware: &RequestIDMiddleware) -> Self {
+        Self {
+            resolver: Arc::new(IdResolver::new(middleware)),
+            // An invalid name disables the header, as in the middleware
+            header_name: HeaderName::try_from(middleware.header_name.as_str()).ok(),
+        }
+    }
+}
+
+impl From<RequestIDMiddleware> for RequestIDLayer {
+    fn from(middleware: RequestIDMiddleware) -> Self {
+        Self::new(&middleware)
+    }
+}
+
+impl<S> Layer<S> for RequestIDLayer {
+    type Service = RequestIDTowerService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIDTowerService {
+            inner,
+            resolver: self.resolver.clone(),
+            header_name: self.header_name.clone(),
+        }
+    }
+}
+
+/// Service created by [`RequestIDLayer`]
+#[derive(Clone)]
+pub struct RequestIDTowerService<S> {
+    inner: S,
+    resolver: Arc<IdResolver>,
+    header_name: Option<HeaderName>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestIDTowerService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = RequestIDFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let id = match req.extensions().get::<RequestID>() {
+            Some(id) => id.clone(),
+            None => {
+                let inbound = self
+                    .header_name
+                    .as_ref()
+                    .and_then(|name| req.headers().get(name))
+                    .map(|value| value.as_bytes());
+                let peer = req.extensions().get::<SocketAddr>().copied();
+                let id = self.resolver.resolve(inbound, peer);
+                req.extensions_mut().insert(id.clone());
+                id
+            }
+        };
+
+        let header = self.header_name.clone().zip(
+            // Shares the ID's buffer instead of copying it
+            HeaderValue::from_maybe_shared(id.to_bytes()).ok(),
+        );
+        // The inner service may already run request code before returning its future
+        let future = context::enter(Some(&id), || self.inner.call(req));
+        RequestIDFuture {
+            inner: id.scope(future),
+            header,
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future of [`RequestIDTowerService`]
+    ///
+    /// Polls the inner future with the request ID installed and adds the ID header to
+    /// the response.
+    pub struct RequestIDFuture<F> {
+        #[pin]
+        inner: Scoped<F>,
+        header: Option<(HeaderName, HeaderValue)>,
+        resolver: Arc<IdResolver>,
+    }
+}
+
+impl<F, ResBody, E> Future for RequestIDFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = Result<Response<ResBody>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut res = match this.inner.poll(cx) {
+            Poll::Ready(Ok(res)) => res,
+            other => return other,
+        };
+        match this.header.take() {
+            Some((name, value)) => {
+                res.headers_mut().append(name, value);
+            }
+            None => this.resolver.record(|m| m.on_header_write_failed()),
+        }
+        Poll::Ready(Ok(res))
+    }
+}
+
+#[cfg(test)]
+mod layer_tests {
+    use super::*;
+    use crate::{get_current_request_id, RequestIDCounters, REQUEST_ID_HEADER};
+    use actix_web::web::Bytes;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Incoming;
+    use hyper_util::rt::TokioIo;
+    use hyper_util::service::TowerToHyperService;
+    use std::convert::Infallible;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Responds with the current request ID and the ID in the request extensions
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<Incoming>> for Echo {
+        type Response = Response<Full<Bytes>>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<Incoming>) -> Self::Future {
+            let extension = req.extensions().get::<RequestID>().unwrap().to_string();
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                let current = get_current_request_id().unwrap_or_default();
+                let body = format!("{} {}", current, extension);
+                Ok(Response::new(Full::new(Bytes::from(body))))
+            })
+        }
+    }
+
+    /// Stores the peer address in the request extensions, as the layer expects it
+    #[derive(Clone)]
+    struct WithPeer<S>(S, SocketAddr);
+
+    impl<S: Service<Request<Incoming>>> Service<Request<Incoming>> for WithPeer<S> {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+            self.0.poll_ready(cx)
+        }
+
+        fn call(&mut self, mut req: Request<Incoming>) -> Self::Future {
+            req.extensions_mut().insert(self.1);
+            self.0.call(req)
+        }
+    }
+
+    /// Serve the layered echo service on a local port
+    async fn serve(layer: RequestIDLayer) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        actix_rt::spawn(async move {
+            loop {
+                let (stream, peer) = listener.accept().await.unwrap();
+                let service = TowerToHyperService::new(WithPeer(layer.layer(Echo), peer));
+                actix_rt::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+        addr
+    }
+
+    /// Send a request, returning the response ID header and body
+    async fn get(addr: SocketAddr, inbound: Option<&str>) -> (Option<String>, String) {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        actix_rt::spawn(conn);
+
+        let mut req = Request::get("/").header("host", addr.to_string());
+        if let Some(inbound) = inbound {
+            req = req.header(REQUEST_ID_HEADER, inbound);
+        }
+        let res = sender
+            .send_request(req.body(Empty::<Bytes>::new()).unwrap())
+            .await
+            .unwrap();
+        let header = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (header, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Test that the layer generates IDs like the middleware and installs them in context
+    #[actix_rt::test]
+    async fn test_layer_generates_ids() {
+        let counters = RequestIDCounters::new();
+        let middleware = RequestIDMiddleware::new()
+            .with_prefix("grpc")
+            .with_id_length(16)
+            .with_metrics(counters.clone());
+        let addr = serve(RequestIDLayer::new(&middleware)).await;
+
+        let (header, body) = get(addr, Some("ignored-inbound-id")).await;
+        let header = header.unwrap();
+        assert!(header.starts_with("grpc_"));
+        assert_eq!(header.len(), "grpc_".len() + 16);
+        assert_eq!(body, format!("{} {}", header, header));
+
+        let (other, _) = get(addr, None).await;
+        assert_ne!(other.unwrap(), header);
+
+        let counts = counters.snapshot();
+        assert_eq!((counts.generated, counts.regenerated), (1, 1));
+    }
+
+    /// Test that inbound IDs are propagated from trusted peers only
+    #[actix_rt::test]
+    async fn test_layer_propagation() {
+        let trusted = RequestIDMiddleware::new()
+            .with_propagation()
+            .with_trusted_networks(["127.0.0.0/8".parse().unwrap()]);
+        let addr = serve(RequestIDLayer::from(trusted)).await;
+        let (header, body) = get(addr, Some("edge-1234")).await;
+        assert_eq!(header.as_deref(), Some("edge-1234"));
+        assert_eq!(body, "edge-1234 edge-1234");
+
+        let untrusted = RequestIDMiddleware::new()
+            .with_propagation()
+            .with_trusted_networks(["10.0.0.0/8".parse().unwrap()]);
+        let addr = serve(RequestIDLayer::new(&untrusted)).await;
+        let (header, _) = get(addr, Some("edge-1234")).await;
+        assert_ne!(header.as_deref(), Some("edge-1234"));
+    }
+
+    /// Test that layered services can run on multi-threaded executors
+    #[test]
+    fn test_service_is_send() {
+        fn assert_send<T: Send + Sync>(_: &T) {}
+        let service = RequestIDLayer::new(&RequestIDMiddleware::new()).layer(Echo);
+        assert_send(&service);
+    }
+}
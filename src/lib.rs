@@ -24,24 +24,109 @@
 use std::cell::RefCell;
 use std::convert::Infallible;
 use std::future::{ready, Future, Ready};
+use std::ops::Range;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+mod access_log;
+#[cfg(feature = "actix")]
+mod actor;
+mod alphabet;
+mod body;
+mod builder;
+mod config;
+mod context;
+mod deterministic;
+#[cfg(feature = "encrypted-ids")]
+mod encrypted;
+mod generator;
+#[cfg(any(feature = "signing", feature = "encrypted-ids"))]
+mod hex;
+mod hooks;
+mod ksuid;
+#[cfg(feature = "tower")]
+mod layer;
+mod logger;
+mod metrics;
+mod recent;
+mod resolver;
+mod rng;
+#[cfg(feature = "signing")]
+mod signing;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod time;
+mod trust;
+#[cfg(feature = "ws")]
+mod ws;
+
+#[cfg(feature = "metrics")]
+pub use self::metrics::MetricsExporter;
+#[cfg(feature = "prometheus")]
+pub use self::metrics::PrometheusMetrics;
+pub use self::metrics::{
+    InvalidReason, RequestIDCounters, RequestIDCounts, RequestIDMetrics, METRIC_NAME,
+};
+pub use access_log::{AccessLog, ChannelSink, FileSink, LogField, LogFormat, LogSink, StdoutSink};
+#[cfg(feature = "actix")]
+pub use actor::WithRequestID;
+pub use alphabet::Alphabet;
+pub use body::{RequestIDBody, StreamEnd};
+pub use builder::RequestIDMiddlewareBuilder;
+pub use config::{ConfigError, RequestIDConfig, RequestIDFormat};
+pub use context::{block_with_request_id, spawn_with_request_id, FutureExt, Scoped};
+pub use deterministic::{SeededIds, SequentialIds, DEFAULT_SEQUENTIAL_WIDTH};
+#[cfg(feature = "encrypted-ids")]
+pub use encrypted::{EncryptedIdError, EncryptedIds, IdMetadata, ENCRYPTED_ID_KEY_LENGTH};
+pub use ksuid::{Ksuid, KsuidParseError, KSUID_EPOCH, KSUID_LENGTH};
+#[cfg(feature = "tower")]
+pub use layer::{RequestIDFuture, RequestIDLayer, RequestIDTowerService};
+pub use logger::{logged_request_id, LoggerExt, LOGGER_REQUEST_ID_LABEL};
+pub use recent::{RecentRequests, RequestSummary};
+#[cfg(feature = "signing")]
+pub use signing::{Signer, SigningKey, DEFAULT_MAC_LENGTH};
+pub use trust::IpCidr;
+#[cfg(feature = "ws")]
+pub use ws::{WsRequestID, WS_MESSAGE_ID_SEPARATOR};
+
+use body::Records;
+use deterministic::{SharedCounter, SharedSeeded};
+use generator::{collision_probability, validate_alphabet, Generator};
+use hooks::Hooks;
+use resolver::IdResolver;
+use rng::Entropy;
+
+use actix_web::body::MessageBody;
 use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
 use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
 use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
 
 /// Default request ID header name
 pub const REQUEST_ID_HEADER: &str = "request-id";
 /// Default ID length (standard length for UUID v4)
 pub const DEFAULT_ID_LENGTH: usize = 36;
-
-/// Type for request ID generator function
-type RequestIDGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+/// Default separator placed between a namespace prefix/suffix and the core ID
+pub const DEFAULT_NAMESPACE_SEPARATOR: &str = "_";
+/// Maximum length of inbound request IDs that are propagated
+pub const MAX_INBOUND_ID_LENGTH: usize = 256;
+/// Header carrying the handling time, see [`RequestIDMiddleware::with_response_time`]
+pub const RESPONSE_TIME_HEADER: &str = "x-response-time";
+/// Name of the `Server-Timing` metric, see [`RequestIDMiddleware::with_server_timing`]
+pub const SERVER_TIMING_METRIC: &str = "total";
+/// Default nanoid alphabet (URL-safe, 64 characters)
+pub const NANOID_ALPHABET: &str =
+    "useandom-26T198340PX75pxJACKVERYMINDBUSHWOLF_GQZbfghjklqvwyzrict";
+/// Default nanoid size (21 characters, ~126 bits of entropy)
+pub const DEFAULT_NANOID_SIZE: usize = 21;
 
 thread_local! {
-    static CURRENT_REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Buffer in which new IDs are assembled before being copied into their `Arc<str>`
+    static ID_BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
 }
 
 /// Set the current request ID globally
@@ -68,10 +153,10 @@ thread_local! {
 /// - This function is thread-safe and uses thread-local storage
 /// - Each thread maintains its own request ID
 /// - The middleware automatically manages this for you in most cases
+/// - Within a request, the middleware restores the request's own ID whenever the request's
+///   future is polled again; use [`RequestID::scope`] to run a future under another ID
 pub fn set_current_request_id(id: &str) {
-    CURRENT_REQUEST_ID.with(|current| {
-        *current.borrow_mut() = Some(id.to_string());
-    });
+    context::replace(Some(RequestID::from_core(id)));
 }
 
 /// Get the current request ID globally
@@ -118,8 +203,9 @@ pub fn set_current_request_id(id: &str) {
 /// - This function is thread-safe and uses thread-local storage
 /// - Returns `None` if called outside of a request context or before middleware sets the ID
 /// - The request ID is automatically cleared after request completion
+/// - This copies the ID; [`RequestID::current`] and [`RequestID::with_current`] avoid the copy
 pub fn get_current_request_id() -> Option<String> {
-    CURRENT_REQUEST_ID.with(|current| current.borrow().clone())
+    context::with(|id| id.map(|id| id.as_str().to_string()))
 }
 
 /// Clear the current request ID globally
@@ -149,20 +235,337 @@ pub fn get_current_request_id() -> Option<String> {
 /// - Each thread maintains its own request ID, so this only affects the current thread
 /// - It's safe to call this function multiple times or when no request ID is set
 pub fn clear_current_request_id() {
-    CURRENT_REQUEST_ID.with(|current| {
-        *current.borrow_mut() = None;
-    });
+    context::replace(None);
 }
 
 /// A struct representing a request ID
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// A request ID consists of a core ID produced by the configured generator, optionally
+/// surrounded by a namespace prefix and/or suffix (see [`RequestIDMiddleware::with_prefix`]).
+/// The full string is what is sent in the response header; the individual parts can be
+/// retrieved with [`RequestID::prefix`], [`RequestID::core`] and [`RequestID::suffix`].
+///
+/// With the `signing` feature, the full string may additionally end with a signature
+/// (see `RequestIDMiddleware::with_signing`).
+///
+/// The ID is stored once in a reference-counted buffer, so clones kept in the request
+/// extensions, the thread-local context and the response header share it.
+///
+/// IDs compare and hash by their full string, whichever way they were created.
+#[derive(Debug, Clone)]
 pub struct RequestID {
-    inner: String,
+    inner: Arc<str>,
+    core: Range<usize>,
+    separator_len: usize,
+    unsigned_len: usize,
+    verified: bool,
+}
+
+impl RequestID {
+    /// Create a request ID without a namespace
+    pub(crate) fn from_core(core: impl Into<Arc<str>>) -> Self {
+        let core = core.into();
+        let len = core.len();
+        Self {
+            inner: core,
+            core: 0..len,
+            separator_len: 0,
+            unsigned_len: len,
+            verified: false,
+        }
+    }
+
+    /// Create a request ID by joining an optional prefix, the core ID and an optional suffix
+    #[cfg(test)]
+    pub(crate) fn namespaced(
+        prefix: Option<&str>,
+        core: &str,
+        suffix: Option<&str>,
+        separator: &str,
+    ) -> Self {
+        Self::assemble(prefix, suffix, separator, |out| out.push_str(core))
+    }
+
+    /// Create a request ID from a core ID written by `write_core` and the namespace
+    ///
+    /// The ID is assembled in a thread-local buffer, so the only allocation is the
+    /// shared buffer of the finished ID.
+    pub(crate) fn assemble(
+        prefix: Option<&str>,
+        suffix: Option<&str>,
+        separator: &str,
+        write_core: impl FnOnce(&mut String),
+    ) -> Self {
+        let assemble = |out: &mut String| {
+            out.clear();
+            if let Some(prefix) = prefix {
+                out.push_str(prefix);
+                out.push_str(separator);
+            }
+            let start = out.len();
+            write_core(out);
+            let end = out.len();
+            if let Some(suffix) = suffix {
+                out.push_str(separator);
+                out.push_str(suffix);
+            }
+
+            Self {
+                inner: Arc::from(out.as_str()),
+                core: start..end,
+                separator_len: separator.len(),
+                unsigned_len: out.len(),
+                verified: false,
+            }
+        };
+
+        // A custom generator could itself create IDs while the buffer is in use
+        ID_BUFFER.with(|buffer| match buffer.try_borrow_mut() {
+            Ok(mut buffer) => assemble(&mut buffer),
+            Err(_) => assemble(&mut String::new()),
+        })
+    }
+
+    /// Get the request ID of the request currently being handled on this thread
+    ///
+    /// This is the typed counterpart of [`get_current_request_id`]: the returned ID shares
+    /// its buffer with the one stored by the middleware, so nothing is copied.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(RequestID)` - Inside a request handled by the middleware, or inside
+    ///   [`RequestID::scope`]
+    /// * `None` - If no request ID is set for this thread
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestID;
+    ///
+    /// fn audit(action: &str) {
+    ///     let id = RequestID::current();
+    ///     println!("[{}] {}", id.as_ref().map_or("-", |id| id.as_str()), action);
+    /// }
+    /// ```
+    pub fn current() -> Option<RequestID> {
+        context::with(|id| id.cloned())
+    }
+
+    /// Call a closure with a reference to the current request ID
+    ///
+    /// Unlike [`RequestID::current`], this does not even clone the shared buffer, which
+    /// makes it the cheapest way to read the ID, e.g. when formatting log lines.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Closure receiving `Some(&RequestID)` inside a request, `None` otherwise
+    ///
+    /// # Returns
+    ///
+    /// The closure's return value.
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestID;
+    ///
+    /// let len = RequestID::with_current(|id| id.map_or(0, |id| id.as_str().len()));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the closure sets or clears the current request ID, e.g. by calling
+    /// [`set_current_request_id`] or polling a [`RequestID::scope`] future.
+    pub fn with_current<R>(f: impl FnOnce(Option<&RequestID>) -> R) -> R {
+        context::with(f)
+    }
+
+    /// Run a future with this ID as the current request ID
+    ///
+    /// Every time the returned future is polled, this ID is installed in the storage read by
+    /// [`RequestID::current`] and [`get_current_request_id`], and the thread's previous ID
+    /// is restored when the poll returns. The middleware runs every request this way.
+    ///
+    /// # Arguments
+    ///
+    /// * `future` - The future to run under this ID
+    ///
+    /// # Returns
+    ///
+    /// A [`Scoped`] future with the same output as `future`.
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{get_current_request_id, RequestID};
+    ///
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let id = RequestID::from("nightly-report");
+    /// let seen = id.scope(async { get_current_request_id() }).await;
+    ///
+    /// assert_eq!(seen.as_deref(), Some("nightly-report"));
+    /// assert!(get_current_request_id().is_none());
+    /// # });
+    /// ```
+    pub fn scope<F: Future>(self, future: F) -> Scoped<F> {
+        Scoped::new(Some(self), future)
+    }
+
+    /// Get the full request ID, including any namespace prefix, suffix and signature
+    pub fn as_str(&self) -> &str {
+        &self.inner
+    }
+
+    /// Get the full request ID as bytes that share the ID's buffer
+    ///
+    /// Useful to forward the ID in outgoing requests without copying it, e.g. with
+    /// `HeaderValue::from_maybe_shared`.
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::from_owner(SharedID(self.inner.clone()))
+    }
+
+    /// Get the full request ID as a header value sharing the ID's buffer
+    ///
+    /// Returns `None` if the ID contains characters not allowed in a header.
+    pub(crate) fn header_value(&self) -> Option<HeaderValue> {
+        HeaderValue::from_maybe_shared(self.to_bytes()).ok()
+    }
+
+    /// Check whether the ID carries a valid signature from a trusted key
+    ///
+    /// IDs generated by a middleware configured with `RequestIDMiddleware::with_signing`
+    /// are always verified. Inbound IDs are verified only if their signature matches one
+    /// of the configured keys, so downstream services can trust IDs minted by the edge
+    /// without trusting the network. Without signing, this always returns `false`.
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Get the core ID produced by the generator, without namespace prefix or suffix
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web::test::TestRequest;
+    /// use actix_web_request_uuid::RequestIDMessage;
+    ///
+    /// let req = TestRequest::default().to_http_request();
+    /// let id = req.request_id();
+    ///
+    /// // IDs without a namespace consist of the core ID only
+    /// assert_eq!(id.core(), id.as_str());
+    /// assert!(id.prefix().is_none());
+    /// ```
+    pub fn core(&self) -> &str {
+        &self.inner[self.core.clone()]
+    }
+
+    /// Get the namespace prefix, without its separator
+    ///
+    /// Returns `None` if the ID was generated without a prefix.
+    pub fn prefix(&self) -> Option<&str> {
+        if self.core.start == 0 {
+            return None;
+        }
+        Some(&self.inner[..self.core.start - self.separator_len])
+    }
+
+    /// Get the namespace suffix, without its separator
+    ///
+    /// Returns `None` if the ID was generated without a suffix.
+    pub fn suffix(&self) -> Option<&str> {
+        if self.core.end == self.unsigned_len {
+            return None;
+        }
+        Some(&self.inner[self.core.end + self.separator_len..self.unsigned_len])
+    }
+
+    /// Parse the core ID as a KSUID
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Ksuid)` - If the core ID is a valid KSUID, e.g. when generated with
+    ///   [`RequestIDMiddleware::with_ksuid`]
+    /// * `Err(KsuidParseError)` - If the core ID is not a KSUID
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{Ksuid, RequestID};
+    ///
+    /// let id = RequestID::from(Ksuid::new());
+    /// let ksuid = id.ksuid().unwrap();
+    /// println!("Request started at {:?}", ksuid.timestamp());
+    /// ```
+    pub fn ksuid(&self) -> Result<Ksuid, KsuidParseError> {
+        self.core().parse()
+    }
+
+    /// Decrypt the metadata carried by the core ID
+    ///
+    /// Available with the `encrypted-ids` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - Generator holding the key the ID was encrypted with
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(IdMetadata)` - If the core ID was generated with
+    ///   `RequestIDMiddleware::with_encrypted_ids` and the same key
+    /// * `Err(EncryptedIdError)` - If the core ID cannot be decrypted
+    #[cfg(feature = "encrypted-ids")]
+    pub fn metadata(&self, ids: &EncryptedIds) -> Result<IdMetadata, EncryptedIdError> {
+        ids.decode(self.core())
+    }
+}
+
+impl From<Ksuid> for RequestID {
+    fn from(ksuid: Ksuid) -> Self {
+        Self::from_core(ksuid.to_string())
+    }
+}
+
+/// Use an existing ID, e.g. to run a background job under [`RequestID::scope`]
+impl From<String> for RequestID {
+    fn from(id: String) -> Self {
+        Self::from_core(id)
+    }
+}
+
+/// Use an existing ID, e.g. to run a background job under [`RequestID::scope`]
+impl From<&str> for RequestID {
+    fn from(id: &str) -> Self {
+        Self::from_core(id)
+    }
 }
 
 impl From<RequestID> for String {
     fn from(r: RequestID) -> Self {
-        r.inner
+        r.inner.to_string()
+    }
+}
+
+/// Owner of the buffer behind [`RequestID::to_bytes`]
+struct SharedID(Arc<str>);
+
+impl AsRef<[u8]> for SharedID {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl PartialEq for RequestID {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for RequestID {}
+
+impl std::hash::Hash for RequestID {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
     }
 }
 
@@ -187,9 +590,102 @@ impl FromRequest for RequestID {
 /// This middleware generates a unique ID for each request and adds it to the response headers.
 /// ID generation methods and header names can be customized.
 pub struct RequestIDMiddleware {
-    generator: RequestIDGenerator,
+    generator: Generator,
+    entropy: Entropy,
     header_name: String,
-    id_length: usize,
+    id_length: Option<usize>,
+    namespace: Namespace,
+    propagate: bool,
+    trusted_networks: Vec<IpCidr>,
+    metrics: Option<Arc<dyn RequestIDMetrics>>,
+    hooks: Hooks,
+    access_log: Option<AccessLog>,
+    recent: Option<RecentRequests>,
+    server_timing: bool,
+    response_time: bool,
+    #[cfg(feature = "signing")]
+    signer: Option<Arc<Signer>>,
+    #[cfg(feature = "test-util")]
+    captured: Option<test_util::CapturedIds>,
+}
+
+/// Namespace prefix/suffix applied around every generated ID
+#[derive(Debug, Clone)]
+struct Namespace {
+    prefix: Option<String>,
+    suffix: Option<String>,
+    separator: String,
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self {
+            prefix: None,
+            suffix: None,
+            separator: DEFAULT_NAMESPACE_SEPARATOR.to_string(),
+        }
+    }
+}
+
+impl Namespace {
+    /// Number of characters the namespace adds around the core ID
+    fn len(&self) -> usize {
+        let prefix = self
+            .prefix
+            .as_ref()
+            .map_or(0, |p| p.len() + self.separator.len());
+        let suffix = self
+            .suffix
+            .as_ref()
+            .map_or(0, |s| s.len() + self.separator.len());
+        prefix + suffix
+    }
+
+    /// Wrap the core ID written by `write_core` with the configured prefix and suffix
+    fn apply(&self, write_core: impl FnOnce(&mut String)) -> RequestID {
+        RequestID::assemble(
+            self.prefix.as_deref(),
+            self.suffix.as_deref(),
+            &self.separator,
+            write_core,
+        )
+    }
+
+    /// Split an inbound ID into the configured prefix and suffix and its core ID
+    ///
+    /// A prefix or suffix is only split off if it is the configured one and leaves a
+    /// non-empty core; otherwise it remains part of the core ID. Characters from
+    /// `unsigned_len` on are the signature of a signed ID.
+    fn split(&self, id: &str, unsigned_len: usize) -> RequestID {
+        let separator = self.separator.as_str();
+        let mut core = &id[..unsigned_len];
+        let mut start = 0;
+        let prefixed = self.prefix.as_deref().and_then(|prefix| {
+            core.strip_prefix(prefix)?
+                .strip_prefix(separator)
+                .filter(|rest| !rest.is_empty())
+        });
+        if let Some(rest) = prefixed {
+            start = unsigned_len - rest.len();
+            core = rest;
+        }
+        let suffixed = self.suffix.as_deref().and_then(|suffix| {
+            core.strip_suffix(suffix)?
+                .strip_suffix(separator)
+                .filter(|rest| !rest.is_empty())
+        });
+        if let Some(rest) = suffixed {
+            core = rest;
+        }
+
+        RequestID {
+            inner: id.into(),
+            core: start..start + core.len(),
+            separator_len: separator.len(),
+            unsigned_len,
+            verified: false,
+        }
+    }
 }
 
 impl Default for RequestIDMiddleware {
@@ -202,35 +698,153 @@ impl RequestIDMiddleware {
     /// Create middleware with default ID length (36 characters)
     pub fn new() -> Self {
         Self {
-            generator: Arc::new(|| Uuid::new_v4().to_string()),
+            generator: Generator::Uuid,
+            entropy: Entropy::default(),
             header_name: REQUEST_ID_HEADER.to_string(),
-            id_length: DEFAULT_ID_LENGTH,
+            id_length: None,
+            namespace: Namespace::default(),
+            propagate: false,
+            trusted_networks: Vec::new(),
+            metrics: None,
+            hooks: Hooks::default(),
+            access_log: None,
+            recent: None,
+            server_timing: false,
+            response_time: false,
+            #[cfg(feature = "signing")]
+            signer: None,
+            #[cfg(feature = "test-util")]
+            captured: None,
+        }
+    }
+
+    /// Create a builder that reports invalid settings instead of panicking
+    ///
+    /// See [`RequestIDMiddlewareBuilder`].
+    pub fn builder() -> RequestIDMiddlewareBuilder {
+        RequestIDMiddlewareBuilder::new()
+    }
+
+    /// Create middleware from a [`RequestIDConfig`]
+    ///
+    /// All settings are validated, so configuration mistakes surface as an error at
+    /// startup rather than as a panic or as IDs that cannot be sent in a header.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Settings, e.g. deserialized from a configuration file
+    ///
+    /// # Returns
+    ///
+    /// The configured middleware, or the first invalid setting as reported by
+    /// [`RequestIDMiddlewareBuilder::build`]. Further builder methods, e.g. for hooks
+    /// or metrics, can be chained on the result.
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{ConfigError, RequestIDConfig, RequestIDFormat, RequestIDMiddleware};
+    ///
+    /// let config = RequestIDConfig {
+    ///     format: RequestIDFormat::Base62,
+    ///     propagate: true,
+    ///     trusted_networks: vec!["10.0.0.0/8".to_string()],
+    ///     ..Default::default()
+    /// };
+    /// let middleware = RequestIDMiddleware::from_config(&config).unwrap();
+    /// assert_eq!(middleware.get_id_length(), 22);
+    ///
+    /// let config = RequestIDConfig {
+    ///     length: Some(0),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(
+    ///     RequestIDMiddleware::from_config(&config).err(),
+    ///     Some(ConfigError::ZeroLength)
+    /// );
+    /// ```
+    pub fn from_config(config: &RequestIDConfig) -> Result<Self, ConfigError> {
+        let mut builder = Self::builder();
+
+        if let Some(header_name) = &config.header_name {
+            builder = builder.header_name(header_name.as_str());
+        }
+        builder = match config.format {
+            RequestIDFormat::Uuid => builder.with_full_uuid(),
+            RequestIDFormat::SimpleUuid => builder.with_simple_uuid(),
+            RequestIDFormat::UuidV7 => builder.with_uuid_v7(),
+            RequestIDFormat::Base62 => builder.with_compact_id(Alphabet::Base62),
+            RequestIDFormat::Base58 => builder.with_compact_id(Alphabet::Base58),
+            RequestIDFormat::Crockford32 => builder.with_compact_id(Alphabet::Crockford32),
+            RequestIDFormat::UrlSafeBase64 => builder.with_compact_id(Alphabet::UrlSafeBase64),
+            RequestIDFormat::Nanoid => builder.with_nanoid(),
+            RequestIDFormat::Ksuid => builder.with_ksuid(),
+        };
+        if let Some(length) = config.length {
+            builder = builder.with_id_length(length);
+        }
+        if let Some(prefix) = &config.prefix {
+            builder = builder.with_prefix(prefix.as_str());
+        }
+        if let Some(suffix) = &config.suffix {
+            builder = builder.with_suffix(suffix.as_str());
+        }
+        if let Some(separator) = &config.separator {
+            builder = builder.with_separator(separator.as_str());
         }
+        if config.propagate {
+            builder = builder.with_propagation();
+        }
+        for network in &config.trusted_networks {
+            builder = builder.with_trusted_network(network);
+        }
+        builder.build()
+    }
+
+    /// Create middleware from environment variables
+    ///
+    /// Shorthand for [`RequestIDConfig::from_env`] followed by
+    /// [`RequestIDMiddleware::from_config`].
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Prefix of the variable names, e.g. `REQUEST_ID` for
+    ///   `REQUEST_ID_FORMAT`
+    ///
+    /// # Usage
+    ///
+    /// ```rust,no_run
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// // REQUEST_ID_FORMAT=ksuid REQUEST_ID_PROPAGATE=true ./server
+    /// let middleware = RequestIDMiddleware::from_env("REQUEST_ID")
+    ///     .unwrap_or_else(|e| panic!("invalid request ID configuration: {}", e));
+    /// ```
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_config(&RequestIDConfig::from_env(prefix)?)
     }
 
     /// Set custom ID length
     ///
+    /// The length is applied to the output of whichever generator is configured,
+    /// independently of the order in which the builder methods are called.
+    /// Longer IDs are truncated; shorter IDs are padded with random hexadecimal
+    /// characters, so lengths beyond 36 produce longer IDs with more entropy.
+    ///
     /// # Arguments
     ///
     /// * `length` - The desired length of the request ID
     ///
     /// # Panics
     ///
-    /// Panics if `length` is 0.
+    /// Panics if `length` is 0. Use [`RequestIDMiddleware::builder`] to get an error
+    /// instead.
     pub fn with_id_length(mut self, length: usize) -> Self {
         if length == 0 {
             panic!("Request ID length must be greater than 0");
         }
 
-        self.id_length = length;
-        self.generator = Arc::new(move || {
-            let uuid = Uuid::new_v4().to_string();
-            if length >= uuid.len() {
-                uuid
-            } else {
-                uuid[..length].to_string()
-            }
-        });
+        self.id_length = Some(length);
         self
     }
 
@@ -243,12 +857,15 @@ impl RequestIDMiddleware {
     where
         F: Fn() -> String + Send + Sync + 'static,
     {
-        self.generator = Arc::new(f);
+        self.generator = Generator::Custom(Arc::new(f));
         self
     }
 
     /// Set a custom header name
     ///
+    /// An invalid header name is not detected here; the response is then sent without
+    /// the request ID header. Use [`RequestIDMiddleware::builder`] to validate it.
+    ///
     /// # Arguments
     ///
     /// * `header_name` - Header name to use
@@ -259,15 +876,23 @@ impl RequestIDMiddleware {
 
     /// Configure to use full UUID v4 format (36 characters with hyphens)
     pub fn with_full_uuid(mut self) -> Self {
-        self.generator = Arc::new(|| Uuid::new_v4().to_string());
-        self.id_length = 36;
+        self.generator = Generator::Uuid;
         self
     }
 
     /// Configure to use simple UUID format (32 characters without hyphens)
     pub fn with_simple_uuid(mut self) -> Self {
-        self.generator = Arc::new(|| Uuid::new_v4().simple().to_string());
-        self.id_length = 32;
+        self.generator = Generator::SimpleUuid;
+        self
+    }
+
+    /// Configure to use time-ordered UUID v7 (36 characters with hyphens)
+    ///
+    /// The first 48 bits hold the Unix time in milliseconds, so IDs sort by the time
+    /// requests were received, as database keys and in logs. The remaining 74 bits are
+    /// random; IDs created within the same millisecond are not ordered.
+    pub fn with_uuid_v7(mut self) -> Self {
+        self.generator = Generator::UuidV7;
         self
     }
 
@@ -280,149 +905,1473 @@ impl RequestIDMiddleware {
     where
         F: Fn(Uuid) -> String + Send + Sync + 'static,
     {
-        self.generator = Arc::new(move || formatter(Uuid::new_v4()));
+        self.generator = Generator::Custom(Arc::new(move || formatter(Uuid::new_v4())));
         self
     }
 
-    /// Get the currently configured ID length
-    pub fn get_id_length(&self) -> usize {
-        self.id_length
-    }
-}
-
-impl<S, B> Transform<S, ServiceRequest> for RequestIDMiddleware
-where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
-    S::Future: 'static,
-{
-    type Response = ServiceResponse<B>;
-    type Error = Error;
-    type Transform = RequestIDService<S>;
-    type InitError = ();
-    type Future = Ready<Result<Self::Transform, Self::InitError>>;
-
-    fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(RequestIDService {
-            wrapped_service: service,
-            generator: self.generator.clone(),
-            header_name: self.header_name.clone(),
-            id_length: self.id_length,
-        }))
+    /// Configure to use compact random IDs drawn from the given alphabet
+    ///
+    /// Every character is filled with entropy, unlike a truncated UUID where hyphens and
+    /// the version nibble carry none. By default the ID is just long enough to carry at
+    /// least 128 bits of entropy (22 characters for base62); use
+    /// [`RequestIDMiddleware::with_id_length`] to choose a different length.
+    ///
+    /// # Arguments
+    ///
+    /// * `alphabet` - Alphabet to draw characters from
+    pub fn with_compact_id(mut self, alphabet: Alphabet) -> Self {
+        self.generator = Generator::Compact(alphabet);
+        self
     }
-}
-
-/// Service that handles request IDs
-///
-/// This service generates IDs during request processing and adds them to response headers.
-/// It also maintains IDs in thread-local variables during request processing.
-pub struct RequestIDService<S> {
-    wrapped_service: S,
-    generator: RequestIDGenerator,
-    header_name: String,
-
-    #[allow(dead_code)]
-    id_length: usize,
-}
-
-impl<S, B> Service<ServiceRequest> for RequestIDService<S>
-where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
-    S::Future: 'static,
-{
-    type Response = ServiceResponse<B>;
-    type Error = Error;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
 
-    fn poll_ready(
-        &self,
-        ctx: &mut core::task::Context<'_>,
-    ) -> std::task::Poll<Result<(), Self::Error>> {
-        self.wrapped_service.poll_ready(ctx)
+    /// Draw random IDs from a per-thread CSPRNG instead of the operating system
+    ///
+    /// Available with the `fast-rng` feature. By default every ID costs a call to the
+    /// operating system RNG. With this option each worker thread runs a ChaCha20
+    /// generator that is seeded from the operating system and then produces IDs in
+    /// user space. It is reseeded after every 64 KiB of output, and also when the
+    /// process ID changes, so processes forked after startup do not repeat IDs.
+    ///
+    /// Applies to UUID v4 and v7, compact, nanoid and KSUID IDs and to the padding added
+    /// by [`RequestIDMiddleware::with_id_length`].
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{Alphabet, RequestIDMiddleware};
+    ///
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .with_compact_id(Alphabet::Base62)
+    ///     .with_fast_rng();
+    /// ```
+    #[cfg(feature = "fast-rng")]
+    pub fn with_fast_rng(mut self) -> Self {
+        self.entropy = Entropy::ThreadLocal;
+        self
     }
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        // Generate request ID
-        let id = self.generate_request_id(&req);
-
-        // Set request ID in thread-local variable
-        set_current_request_id(&id);
-
-        let fut = self.wrapped_service.call(req);
-        let header_name = self.header_name.clone();
-
-        Box::pin(async move {
-            let mut res = fut.await?;
-            // Add request ID to response headers
-            res.headers_mut().append(
-                HeaderName::try_from(header_name).unwrap(),
-                HeaderValue::from_str(&id).unwrap(),
-            );
-
-            // Clear thread-local variable after response completion
-            clear_current_request_id();
-
-            Ok(res)
-        })
+    /// Configure to use nanoid-style IDs (21 URL-safe characters)
+    ///
+    /// IDs have the same shape as those produced by the JavaScript `nanoid()` function,
+    /// so request IDs can match client-side IDs.
+    pub fn with_nanoid(self) -> Self {
+        self.with_custom_nanoid(NANOID_ALPHABET, DEFAULT_NANOID_SIZE)
     }
-}
 
-impl<S> RequestIDService<S> {
-    /// Generate request ID or retrieve from request extensions
-    fn generate_request_id(&self, req: &ServiceRequest) -> String {
-        // Use existing ID if it exists in extensions
-        if let Some(id) = req.extensions().get::<RequestID>() {
-            return id.inner.clone();
+    /// Configure to use nanoid-style IDs with a custom alphabet and size
+    ///
+    /// Equivalent to nanoid's `customAlphabet(alphabet, size)`. Characters are drawn
+    /// using rejection sampling, so every character of the alphabet is equally likely.
+    ///
+    /// # Arguments
+    ///
+    /// * `alphabet` - Characters to draw from
+    /// * `size` - Default ID length, overridden by [`RequestIDMiddleware::with_id_length`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0, or if `alphabet` is empty, longer than 256 characters,
+    /// contains duplicates or contains characters other than visible ASCII. Use
+    /// [`RequestIDMiddleware::builder`] to get an error instead.
+    pub fn with_custom_nanoid(mut self, alphabet: &str, size: usize) -> Self {
+        if size == 0 {
+            panic!("Request ID length must be greater than 0");
+        }
+        if let Err(message) = validate_alphabet(alphabet) {
+            panic!("{}", message);
         }
 
-        // Generate new ID and save to extensions
-        let new_id = RequestID {
-            inner: (self.generator)(),
+        self.generator = Generator::Nanoid {
+            alphabet: Arc::from(alphabet),
+            size,
         };
-        req.extensions_mut().insert(new_id.clone());
-        new_id.inner
+        self
     }
-}
 
-/// Extension trait for retrieving request IDs from HttpMessage
-pub trait RequestIDMessage {
-    /// Get the request ID associated with the request
+    /// Configure to use KSUIDs (27 base62 characters)
     ///
-    /// If no ID exists, a new one will be generated
-    fn request_id(&self) -> RequestID;
+    /// KSUIDs start with a second-precision timestamp, so request IDs sort in the order
+    /// requests were received and can be used directly as event keys. Use
+    /// [`RequestID::ksuid`] to parse the ID again.
+    ///
+    /// Note that combining this with [`RequestIDMiddleware::with_id_length`] produces IDs
+    /// that are no longer valid KSUIDs.
+    pub fn with_ksuid(mut self) -> Self {
+        self.generator = Generator::Ksuid;
+        self
+    }
+
+    /// Configure to use opaque IDs carrying encrypted metadata
+    ///
+    /// Available with the `encrypted-ids` feature. Each ID encrypts the issue time and
+    /// the node and tenant IDs configured on `ids`, so support staff holding the key can
+    /// tell where and when a request was handled, while clients only see random
+    /// characters. Use [`EncryptedIds::decode`], [`RequestID::metadata`] or the
+    /// `request-id-decode` binary to read the metadata.
+    ///
+    /// Note that combining this with [`RequestIDMiddleware::with_id_length`] produces IDs
+    /// that can no longer be decrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - Generator holding the key and the metadata to embed
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{EncryptedIds, RequestIDMiddleware};
+    ///
+    /// let key = std::env::var("REQUEST_ID_KEY").unwrap_or_else(|_| "00".repeat(32));
+    /// let middleware = RequestIDMiddleware::new().with_encrypted_ids(
+    ///     EncryptedIds::from_hex_key(&key)
+    ///         .expect("REQUEST_ID_KEY must be 64 hex characters")
+    ///         .with_node_id(3)
+    ///         .with_tenant_id(42),
+    /// );
+    /// ```
+    #[cfg(feature = "encrypted-ids")]
+    pub fn with_encrypted_ids(mut self, ids: EncryptedIds) -> Self {
+        self.generator = Generator::Encrypted(Arc::new(ids));
+        self
+    }
+
+    /// Configure to use reproducible UUID v4 formatted IDs from a seeded PRNG
+    ///
+    /// Intended for snapshot and integration tests that need to assert exact header
+    /// values. [`SeededIds`] yields the same sequence, so tests can compute the expected
+    /// IDs up front. The sequence is shared by all services created from this middleware
+    /// and advances once per generated ID; services generating IDs concurrently get
+    /// whole IDs of the sequence, in the order they ask for them.
+    ///
+    /// IDs are predictable by design; never use this in production.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Seed of the PRNG
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web::{test, web, App, HttpResponse};
+    /// use actix_web_request_uuid::{RequestIDMiddleware, SeededIds, REQUEST_ID_HEADER};
+    ///
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let app = test::init_service(
+    ///     App::new()
+    ///         .wrap(RequestIDMiddleware::new().with_seeded_ids(42))
+    ///         .route("/", web::get().to(HttpResponse::Ok)),
+    /// )
+    /// .await;
+    ///
+    /// let mut expected = SeededIds::new(42);
+    /// let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    /// assert_eq!(
+    ///     resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+    ///     expected.next().unwrap().as_str()
+    /// );
+    /// # });
+    /// ```
+    pub fn with_seeded_ids(mut self, seed: u64) -> Self {
+        self.generator = Generator::Seeded(SharedSeeded::new(seed));
+        self
+    }
+
+    /// Configure to use sequential IDs (`000001`, `000002`, ...)
+    ///
+    /// The counter starts at 1 and is shared by all services created from this
+    /// middleware. [`RequestIDMiddleware::with_id_length`] sets the number of digits;
+    /// counters that need more digits wrap around, e.g. from `999` to `000`.
+    /// Combine with a namespace to get IDs like `req-000001`:
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .with_sequential_ids()
+    ///     .with_prefix("req")
+    ///     .with_separator("-");
+    /// ```
+    ///
+    /// Each `HttpServer` worker builds its own middleware, so IDs are only unique per
+    /// worker. This is intended for tests built on `actix_web::test::init_service`.
+    pub fn with_sequential_ids(mut self) -> Self {
+        self.generator = Generator::Sequential(SharedCounter::default());
+        self
+    }
+
+    /// Set a namespace prefix placed before every generated ID
+    ///
+    /// The prefix is joined to the core ID with the configured separator
+    /// (`_` by default), e.g. `api-gw_550e8400-e29b-41d4-a716-446655440000`.
+    /// It is applied regardless of the chosen generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Prefix identifying where the ID originated, e.g. the service name
+    pub fn with_prefix<T: Into<String>>(mut self, prefix: T) -> Self {
+        self.namespace.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Set a namespace suffix placed after every generated ID
+    ///
+    /// # Arguments
+    ///
+    /// * `suffix` - Suffix appended to the core ID, e.g. a region or node name
+    pub fn with_suffix<T: Into<String>>(mut self, suffix: T) -> Self {
+        self.namespace.suffix = Some(suffix.into());
+        self
+    }
+
+    /// Set the separator placed between the namespace prefix/suffix and the core ID
+    ///
+    /// # Arguments
+    ///
+    /// * `separator` - Separator string, e.g. `":"` for `billing:550e8400-...`
+    pub fn with_separator<T: Into<String>>(mut self, separator: T) -> Self {
+        self.namespace.separator = separator.into();
+        self
+    }
+
+    /// Honor request IDs sent by clients in the request header
+    ///
+    /// When the request carries the configured header, its value is used as the request
+    /// ID instead of generating a new one, so IDs can be followed across services.
+    /// Values that are empty, longer than [`MAX_INBOUND_ID_LENGTH`] or contain characters
+    /// other than visible ASCII are ignored and a new ID is generated.
+    ///
+    /// Propagated IDs are used as-is: they are not namespaced, fitted to the configured
+    /// length or signed.
+    pub fn with_propagation(mut self) -> Self {
+        self.propagate = true;
+        self
+    }
+
+    /// Only honor inbound request IDs sent by peers in the given networks
+    ///
+    /// Typically the addresses of the load balancers or gateways in front of the
+    /// service: IDs they forward are propagated, while IDs sent directly by clients
+    /// are replaced. The peer is the address of the TCP connection, not a
+    /// `Forwarded` or `X-Forwarded-For` header, which clients can forge. Without
+    /// trusted networks every peer is trusted.
+    ///
+    /// Has no effect without [`RequestIDMiddleware::with_propagation`]. IDs verified
+    /// with `with_signing` are honored from any peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `networks` - Networks to trust, added to those already configured
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{IpCidr, RequestIDMiddleware};
+    ///
+    /// let lb: IpCidr = "10.0.0.0/8".parse().unwrap();
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .with_propagation()
+    ///     .with_trusted_networks([lb]);
+    /// ```
+    pub fn with_trusted_networks<I>(mut self, networks: I) -> Self
+    where
+        I: IntoIterator<Item = IpCidr>,
+    {
+        self.trusted_networks.extend(networks);
+        self
+    }
+
+    /// Report the provenance of every request ID to a metrics hook
+    ///
+    /// The hook is told whether each ID was generated, propagated from the request
+    /// header or regenerated in place of a rejected inbound ID, and when the header
+    /// could not be written. Use [`RequestIDCounters`] for in-memory counts, or the
+    /// `PrometheusMetrics` and `MetricsExporter` exporters behind the `prometheus` and
+    /// `metrics` features.
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics` - Hook receiving the callbacks
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{RequestIDCounters, RequestIDMiddleware};
+    ///
+    /// let counters = RequestIDCounters::new();
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .with_propagation()
+    ///     .with_metrics(counters.clone());
+    /// ```
+    pub fn with_metrics<M: RequestIDMetrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Run a hook when a request arrives, before it is passed on
+    ///
+    /// Hooks run in registration order, after the request ID has been assigned.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Function receiving the request ID and the request
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new().on_request(|id, req| {
+    ///     println!("{} {} {}", id, req.method(), req.path());
+    /// });
+    /// ```
+    pub fn on_request<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RequestID, &ServiceRequest) + Send + Sync + 'static,
+    {
+        self.hooks.request.push(Arc::new(hook));
+        self
+    }
+
+    /// Run an async hook when a request arrives, before it is passed on
+    ///
+    /// The request is only passed on once the returned future completes, so the hook
+    /// can e.g. write an audit record before the handler runs. Async hooks run after
+    /// all [`RequestIDMiddleware::on_request`] hooks.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Function receiving the request ID and the request, returning a future
+    pub fn on_request_async<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(RequestID, HttpRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.hooks
+            .request_async
+            .push(Arc::new(move |id, req| Hooks::boxed(hook(id, req))));
+        self
+    }
+
+    /// Run a hook when a response is ready, with the time spent handling the request
+    ///
+    /// The hook sees the response after the request ID header was added. The body is
+    /// not available to hooks, which is why the response is passed as
+    /// `ServiceResponse<()>`. Hooks do not run if the inner service fails with an
+    /// error instead of producing a response; see [`RequestIDMiddleware::on_error`].
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Function receiving the request ID, the response and the elapsed time
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new().on_response(|id, res, elapsed| {
+    ///     println!("{} {} in {:?}", id, res.status(), elapsed);
+    /// });
+    /// ```
+    pub fn on_response<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RequestID, &ServiceResponse<()>, Duration) + Send + Sync + 'static,
+    {
+        self.hooks.response.push(Arc::new(hook));
+        self
+    }
+
+    /// Run an async hook when a response is ready
+    ///
+    /// The response is sent once the returned future completes. Async hooks run after
+    /// all [`RequestIDMiddleware::on_response`] hooks.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Function receiving the request ID, the request, the response status
+    ///   and the elapsed time, returning a future
+    pub fn on_response_async<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(RequestID, HttpRequest, StatusCode, Duration) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.hooks
+            .response_async
+            .push(Arc::new(move |id, req, status, elapsed| {
+                Hooks::boxed(hook(id, req, status, elapsed))
+            }));
+        self
+    }
+
+    /// Run a hook when handling a request fails
+    ///
+    /// Runs both when the inner service returns an error and when it returns an error
+    /// response, e.g. from a handler returning `Err`. In the latter case the
+    /// [`RequestIDMiddleware::on_response`] hooks run as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Function receiving the request ID, the error and the elapsed time
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RequestID, &Error, Duration) + Send + Sync + 'static,
+    {
+        self.hooks.error.push(Arc::new(hook));
+        self
+    }
+
+    /// Run a hook when the response body has been sent
+    ///
+    /// [`RequestIDMiddleware::on_response`] hooks run when the response head is ready,
+    /// which for streaming bodies such as Server-Sent Events can be long before the last
+    /// byte is sent. End-of-stream hooks run once the body finished, failed or was dropped,
+    /// e.g. because the client disconnected, with the total latency of the request. The
+    /// request ID is installed while the hooks run. Hooks do not run if the inner service
+    /// fails without producing a response.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Function receiving the request ID and how the body ended
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new().on_stream_end(|id, end| {
+    ///     println!(
+    ///         "{} sent {} bytes in {:?} (complete: {})",
+    ///         id, end.bytes, end.latency, end.completed
+    ///     );
+    /// });
+    /// ```
+    pub fn on_stream_end<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RequestID, &StreamEnd) + Send + Sync + 'static,
+    {
+        self.hooks.stream_end.push(Arc::new(hook));
+        self
+    }
+
+    /// Write one access log line per request
+    ///
+    /// Lines carry the same request ID as the response header, so no `Logger` format
+    /// tricks are needed to correlate them. Lines are written once the response body was
+    /// sent, so `bytes` and `latency_ms` include streamed bodies. Requests that fail
+    /// without a response are logged with the status of their error.
+    ///
+    /// # Arguments
+    ///
+    /// * `access_log` - Format, fields and sink of the log
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{AccessLog, LogFormat, RequestIDMiddleware, StdoutSink};
+    ///
+    /// let middleware =
+    ///     RequestIDMiddleware::new().with_access_log(AccessLog::new(LogFormat::Json, StdoutSink));
+    /// ```
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Keep a summary of recent requests in an in-memory buffer
+    ///
+    /// See [`RecentRequests`] for size and age limits, and [`RecentRequests::scope`]
+    /// for querying the buffer by request ID over HTTP.
+    ///
+    /// # Arguments
+    ///
+    /// * `recent` - Buffer shared with the debug scope
+    pub fn with_recent_requests(mut self, recent: &RecentRequests) -> Self {
+        self.recent = Some(recent.clone());
+        self
+    }
+
+    /// Add a `Server-Timing` header with the handling time and the request ID
+    ///
+    /// The header reads `total;dur=12.345;desc="<request id>"`, so browser devtools and
+    /// RUM scripts see both the latency and the ID to look up in backend logs. The
+    /// duration is measured around the call of the inner service, in milliseconds.
+    ///
+    /// Cross-origin pages can only read the header if the response also carries a
+    /// `Timing-Allow-Origin` header.
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .with_server_timing()
+    ///     .with_response_time();
+    /// ```
+    pub fn with_server_timing(mut self) -> Self {
+        self.server_timing = true;
+        self
+    }
+
+    /// Add an `X-Response-Time` header with the handling time, e.g. `12.345ms`
+    ///
+    /// The duration is measured around the call of the inner service.
+    pub fn with_response_time(mut self) -> Self {
+        self.response_time = true;
+        self
+    }
+
+    /// Sign generated request IDs and verify inbound ones
+    ///
+    /// Available with the `signing` feature. Generated IDs get a truncated HMAC appended
+    /// (`<id>.<key-id>.<mac>`) and are marked as verified. Inbound IDs in the request
+    /// header are honored if their signature is valid, in which case
+    /// [`RequestID::is_verified`] returns `true`. Inbound IDs without a valid signature
+    /// are replaced with a new ID, unless [`RequestIDMiddleware::with_propagation`] is
+    /// also enabled, in which case they are propagated as unverified IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `signer` - Signer holding the current key and any verification keys
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::{RequestIDMiddleware, Signer, SigningKey};
+    ///
+    /// let secret = std::env::var("REQUEST_ID_SECRET").unwrap_or_default();
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .with_signing(Signer::new(SigningKey::new("k1", secret)));
+    /// ```
+    #[cfg(feature = "signing")]
+    pub fn with_signing(mut self, signer: Signer) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Record every request ID issued by this middleware
+    ///
+    /// Available with the `test-util` feature. See [`test_util::CapturedIds`].
+    ///
+    /// # Arguments
+    ///
+    /// * `captured` - Record shared with the test
+    #[cfg(feature = "test-util")]
+    pub fn capture_ids(mut self, captured: &test_util::CapturedIds) -> Self {
+        self.captured = Some(captured.clone());
+        self
+    }
+
+    /// Get the currently configured ID length
+    ///
+    /// This is the length of the core ID and does not include the namespace prefix
+    /// or suffix. See [`RequestIDMiddleware::get_total_id_length`].
+    ///
+    /// If no length was set with [`RequestIDMiddleware::with_id_length`], the natural
+    /// length of the configured generator is returned. Custom generators have no
+    /// natural length, in which case [`DEFAULT_ID_LENGTH`] is reported.
+    pub fn get_id_length(&self) -> usize {
+        self.id_length
+            .or_else(|| self.generator.natural_length())
+            .unwrap_or(DEFAULT_ID_LENGTH)
+    }
+
+    /// Get the length of the full ID sent in the header, including namespace prefix and suffix
+    ///
+    /// With signing enabled, this includes the length of the signature.
+    pub fn get_total_id_length(&self) -> usize {
+        #[cfg(feature = "signing")]
+        if let Some(signer) = &self.signer {
+            return self.get_id_length() + self.namespace.len() + signer.signature_length();
+        }
+
+        self.get_id_length() + self.namespace.len()
+    }
+
+    /// Get the bits of entropy in each generated ID
+    ///
+    /// Accounts for the configured generator and ID length, e.g. a UUID v4 carries
+    /// 122 random bits while `with_id_length(8)` leaves only 32. The namespace prefix
+    /// and suffix are constant and carry no entropy.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(f64)` - The bits of entropy per ID
+    /// * `None` - If a custom generator is configured, whose entropy cannot be known
+    pub fn entropy_bits(&self) -> Option<f64> {
+        self.generator.entropy_bits(self.get_id_length())
+    }
+
+    /// Estimate the probability of at least one ID collision
+    ///
+    /// # Arguments
+    ///
+    /// * `expected_requests` - The number of IDs expected to be generated, e.g. per retention period
+    ///
+    /// # Returns
+    ///
+    /// * `Some(f64)` - The estimated collision probability between 0 and 1
+    /// * `None` - If a custom generator is configured
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// // 8 hex characters (32 bits) collide with ~39% probability after 65,536 requests
+    /// let middleware = RequestIDMiddleware::new().with_id_length(8);
+    /// let p = middleware.collision_probability(65_536).unwrap();
+    /// assert!(p > 0.39 && p < 0.40);
+    /// ```
+    pub fn collision_probability(&self, expected_requests: u64) -> Option<f64> {
+        self.entropy_bits()
+            .map(|bits| collision_probability(bits, expected_requests))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIDMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<RequestIDBody<B>>;
+    type Error = Error;
+    type Transform = RequestIDService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIDService {
+            wrapped_service: Rc::new(service),
+            resolver: Rc::new(IdResolver::new(self)),
+            // Parsed once per worker; an invalid name disables the header
+            header_name: HeaderName::try_from(self.header_name.as_str()).ok(),
+            hooks: Rc::new(self.hooks.clone()),
+            access_log: self.access_log.clone().map(Rc::new),
+            recent: self.recent.clone(),
+            server_timing: self.server_timing,
+            response_time: self.response_time,
+            #[cfg(feature = "test-util")]
+            captured: self.captured.clone(),
+        }))
+    }
+}
+
+/// Service that handles request IDs
+///
+/// This service generates IDs during request processing and adds them to response headers.
+/// It also maintains IDs in thread-local variables during request processing.
+pub struct RequestIDService<S> {
+    wrapped_service: Rc<S>,
+    resolver: Rc<IdResolver>,
+    header_name: Option<HeaderName>,
+    hooks: Rc<Hooks>,
+    access_log: Option<Rc<AccessLog>>,
+    recent: Option<RecentRequests>,
+    server_timing: bool,
+    response_time: bool,
+    #[cfg(feature = "test-util")]
+    captured: Option<test_util::CapturedIds>,
 }
 
-impl<T> RequestIDMessage for T
-where
-    T: HttpMessage,
-{
-    fn request_id(&self) -> RequestID {
-        // Return existing ID if available
-        if let Some(id) = self.extensions().get::<RequestID>() {
-            return id.clone();
-        }
+/// Call of the inner service, possibly deferred until async request hooks completed
+enum Pending<F> {
+    Called(F),
+    Deferred(ServiceRequest),
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIDService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<RequestIDBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(
+        &self,
+        ctx: &mut core::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.wrapped_service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+
+        // Generate request ID
+        let id = self.generate_request_id(&req);
+
+        #[cfg(feature = "test-util")]
+        if let Some(captured) = &self.captured {
+            captured.record(id.as_str());
+        }
+
+        self.hooks.run_request(&id, &req);
+        let access_log = self
+            .access_log
+            .as_ref()
+            .map(|log| (log.clone(), log.start(&req)));
+        let recent = self
+            .recent
+            .as_ref()
+            .filter(|recent| recent.records(req.path()))
+            .map(|recent| (recent.clone(), recent.start(&req)));
+
+        let mut inner_started = Instant::now();
+        let pending = if self.hooks.defers_request() {
+            Pending::Deferred(req)
+        } else {
+            // The inner service may already run request code before returning its future
+            context::enter(Some(&id), || {
+                Pending::Called(self.wrapped_service.call(req))
+            })
+        };
+        let service = self.wrapped_service.clone();
+        let hooks = self.hooks.clone();
+        let header_name = self.header_name.clone();
+        let resolver = self.resolver.clone();
+        let (server_timing, response_time) = (self.server_timing, self.response_time);
+
+        // Installs the request ID in thread-local storage for every poll
+        let scoped = id.clone();
+        Box::pin(scoped.scope(async move {
+            let result = match pending {
+                Pending::Called(fut) => fut.await,
+                Pending::Deferred(req) => {
+                    hooks.run_request_async(&id, req.request()).await;
+                    inner_started = Instant::now();
+                    service.call(req).await
+                }
+            };
+            let inner_elapsed = inner_started.elapsed();
+            let mut res = match result {
+                Ok(res) => res,
+                Err(error) => {
+                    hooks.run_error(&id, &error, started.elapsed());
+                    if let Some((log, entry)) = access_log {
+                        let status = error.as_response_error().status_code();
+                        log.finish(entry, &id, status, None, started.elapsed());
+                    }
+                    if let Some((recent, summary)) = recent {
+                        let status = error.as_response_error().status_code();
+                        let message = Some(error.to_string());
+                        recent.finish(summary, &id, status, message, started.elapsed());
+                    }
+                    return Err(error);
+                }
+            };
+
+            // Add request ID to response headers, unless the name or ID is not a valid header
+            match (header_name, id.header_value()) {
+                (Some(name), Some(value)) => {
+                    res.headers_mut().append(name, value);
+                }
+                _ => {
+                    resolver.record(|m| m.on_header_write_failed());
+                }
+            }
+
+            if server_timing || response_time {
+                add_timing_headers(&mut res, &id, inner_elapsed, server_timing, response_time);
+            }
+
+            let res = hooks.run_response(&id, res, started.elapsed()).await;
+
+            // Requests to the debug scope would crowd out the ones it is meant to show
+            let recent = recent.filter(|_| !recent::is_scope_request(res.request()));
+
+            // Written when the body ended, with the bytes sent and the total latency
+            let records = (access_log.is_some() || recent.is_some()).then(|| Records {
+                status: res.status(),
+                error: res.response().error().map(|e| e.to_string()),
+                access_log,
+                recent,
+            });
+
+            // Streaming bodies are polled after this future completed
+            Ok(res.map_body(|_, body| {
+                RequestIDBody::new(body, id, started, hooks).with_records(records)
+            }))
+        }))
+    }
+}
+
+impl<S> RequestIDService<S> {
+    /// Generate request ID or retrieve from request extensions or headers
+    fn generate_request_id(&self, req: &ServiceRequest) -> RequestID {
+        // Use existing ID if it exists in extensions
+        if let Some(id) = req.extensions().get::<RequestID>() {
+            return id.clone();
+        }
+
+        let inbound = self
+            .header_name
+            .as_ref()
+            .and_then(|name| req.headers().get(name))
+            .map(|value| value.as_bytes());
+        let id = self.resolver.resolve(inbound, req.peer_addr());
+        req.extensions_mut().insert(id.clone());
+        id
+    }
+}
+
+/// Add the `Server-Timing` and/or `X-Response-Time` headers
+fn add_timing_headers<B>(
+    res: &mut ServiceResponse<B>,
+    id: &RequestID,
+    elapsed: Duration,
+    server_timing: bool,
+    response_time: bool,
+) {
+    let millis = elapsed.as_secs_f64() * 1000.0;
+
+    if server_timing {
+        // Inbound IDs may contain quotes or backslashes, which must be escaped
+        let desc = id.as_str().replace('\\', "\\\\").replace('"', "\\\"");
+        let value = format!(
+            "{};dur={:.3};desc=\"{}\"",
+            SERVER_TIMING_METRIC, millis, desc
+        );
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            res.headers_mut()
+                .append(HeaderName::from_static("server-timing"), value);
+        }
+    }
+
+    if response_time {
+        let value = HeaderValue::from_str(&format!("{:.3}ms", millis))
+            .expect("formatted duration is a valid header value");
+        res.headers_mut()
+            .insert(HeaderName::from_static(RESPONSE_TIME_HEADER), value);
+    }
+}
+
+/// Extension trait for retrieving request IDs from HttpMessage
+pub trait RequestIDMessage {
+    /// Get the request ID associated with the request
+    ///
+    /// If no ID exists, a new one will be generated
+    fn request_id(&self) -> RequestID;
+}
+
+impl<T> RequestIDMessage for T
+where
+    T: HttpMessage,
+{
+    fn request_id(&self) -> RequestID {
+        // Return existing ID if available
+        if let Some(id) = self.extensions().get::<RequestID>() {
+            return id.clone();
+        }
+
+        // Create new one if it doesn't exist
+        let new_id = RequestID::assemble(None, None, "", |out| {
+            Generator::Uuid.write(out, Entropy::Os)
+        });
+
+        self.extensions_mut().insert(new_id.clone());
+        new_id
+    }
+}
+
+#[cfg(test)]
+mod lib_actix_web_request_uuid_tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    /// Test custom ID length
+    #[actix_rt::test]
+    async fn test_custom_id_length() {
+        let id_length = 16;
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_id_length(id_length))
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(request_id.len(), id_length);
+    }
+
+    /// Test full UUID format
+    #[actix_rt::test]
+    async fn test_full_uuid_format() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_full_uuid())
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(request_id.len(), 36);
+        assert!(Uuid::parse_str(request_id).is_ok());
+    }
+
+    /// Test simple UUID format
+    #[actix_rt::test]
+    async fn test_simple_uuid_format() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_simple_uuid())
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(request_id.len(), 32);
+    }
+
+    /// Test custom header name
+    #[actix_rt::test]
+    async fn test_custom_header_name() {
+        let custom_header = "X-Request-ID";
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_simple_uuid()
+                        .header_name(custom_header),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert!(resp.headers().get(custom_header).is_some());
+    }
+
+    /// Test custom format
+    #[actix_rt::test]
+    async fn test_custom_format() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_custom_uuid_format(|uuid| format!("req-{}", uuid.simple())),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(request_id.starts_with("req-"));
+    }
+
+    /// Test panic when ID length is 0
+    #[actix_rt::test]
+    #[should_panic(expected = "Request ID length must be greater than 0")]
+    async fn test_zero_length_id_panics() {
+        RequestIDMiddleware::new().with_id_length(0);
+    }
+
+    /// Test thread-local request ID
+    #[actix_rt::test]
+    async fn test_thread_local_request_id() {
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
+            web::resource("/").to(|| async {
+                // Get request ID from thread-local variable
+                let request_id = get_current_request_id().unwrap_or_else(|| "missing".to_string());
+                HttpResponse::Ok().body(request_id)
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Get request ID from response body
+        let body = test::read_body(resp).await;
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+
+        // Verify that request ID length is 36
+        assert_eq!(body_str.len(), 36);
+
+        // Verify that it's a valid UUID format
+        assert!(Uuid::parse_str(&body_str).is_ok());
+    }
+
+    /// Test RequestID struct conversion traits
+    #[actix_rt::test]
+    async fn test_request_id_conversions() {
+        let id_str = "test-request-id-123";
+        let request_id = RequestID::from_core(id_str.to_string());
+
+        // Test Display trait
+        assert_eq!(format!("{}", request_id), id_str);
+
+        // Test From<RequestID> for String
+        let converted: String = request_id.clone().into();
+        assert_eq!(converted, id_str);
+
+        // Test Debug trait
+        let debug_str = format!("{:?}", request_id);
+        assert!(debug_str.contains("RequestID"));
+        assert!(debug_str.contains(id_str));
+
+        // Test PartialEq and Eq
+        let request_id2 = RequestID::from_core(id_str.to_string());
+        assert_eq!(request_id, request_id2);
+    }
+
+    /// Test RequestIDMessage trait implementation
+    #[actix_rt::test]
+    async fn test_request_id_message_trait() {
+        let req = test::TestRequest::default().to_http_request();
+
+        // First call should create new ID
+        let id1 = req.request_id();
+        assert_eq!(id1.inner.len(), 36);
+
+        // Second call should return the same ID
+        let id2 = req.request_id();
+        assert_eq!(id1, id2);
+    }
+
+    /// Test FromRequest implementation for RequestID
+    #[actix_rt::test]
+    async fn test_from_request_implementation() {
+        let app =
+            test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
+                web::resource("/").to(|req_id: RequestID| async move {
+                    HttpResponse::Ok().body(req_id.to_bytes())
+                }),
+            ))
+            .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body_str.len(), 36);
+    }
+
+    /// Test custom generator function
+    #[actix_rt::test]
+    async fn test_custom_generator() {
+        let custom_id = "custom-generated-id";
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().generator(move || custom_id.to_string()))
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(request_id, custom_id);
+    }
+
+    /// Test thread-local functions directly
+    #[actix_rt::test]
+    async fn test_thread_local_functions() {
+        // Initially should be None
+        assert!(get_current_request_id().is_none());
+
+        // Set request ID
+        let test_id = "test-thread-local-id";
+        set_current_request_id(test_id);
+
+        // Should be able to retrieve it
+        assert_eq!(get_current_request_id(), Some(test_id.to_string()));
+
+        // Clear request ID
+        clear_current_request_id();
+
+        // Should be None again
+        assert!(get_current_request_id().is_none());
+
+        // Test multiple set/clear cycles
+        set_current_request_id("id1");
+        assert_eq!(get_current_request_id(), Some("id1".to_string()));
+
+        set_current_request_id("id2");
+        assert_eq!(get_current_request_id(), Some("id2".to_string()));
+
+        clear_current_request_id();
+        assert!(get_current_request_id().is_none());
+    }
+
+    /// Test Default implementation for RequestIDMiddleware
+    #[actix_rt::test]
+    async fn test_middleware_default() {
+        let middleware = RequestIDMiddleware::default();
+        assert_eq!(middleware.get_id_length(), DEFAULT_ID_LENGTH);
+        assert_eq!(middleware.header_name, REQUEST_ID_HEADER);
+    }
+
+    /// Test that existing request ID in extensions is reused
+    #[actix_rt::test]
+    async fn test_existing_request_id_reused() {
+        let existing_id = "pre-existing-request-id";
+
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
+            web::resource("/").to(move |req: HttpRequest| async move {
+                // Pre-set a request ID in extensions
+                req.extensions_mut()
+                    .insert(RequestID::from_core(existing_id.to_string()));
+
+                // Get the request ID - should use the existing one
+                let req_id = req.request_id();
+                HttpResponse::Ok().body(req_id.to_bytes())
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        // Check that the existing ID was used in response header
+        let header_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+
+        // The header should have a generated UUID, not the pre-existing one
+        // because the middleware runs before the handler
+        assert_ne!(header_id, existing_id);
+        assert_eq!(header_id.len(), 36);
+    }
+
+    /// Test multiple concurrent requests have different IDs
+    #[actix_rt::test]
+    async fn test_concurrent_requests_different_ids() {
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
+            web::resource("/").to(|| async {
+                let id = get_current_request_id().unwrap();
+                HttpResponse::Ok().body(id)
+            }),
+        ))
+        .await;
+
+        // Make multiple requests
+        let req1 = test::TestRequest::with_uri("/").to_request();
+        let req2 = test::TestRequest::with_uri("/").to_request();
+
+        let resp1 = test::call_service(&app, req1).await;
+        let resp2 = test::call_service(&app, req2).await;
+
+        let body1 = test::read_body(resp1).await;
+        let body2 = test::read_body(resp2).await;
+
+        let id1 = String::from_utf8(body1.to_vec()).unwrap();
+        let id2 = String::from_utf8(body2.to_vec()).unwrap();
+
+        // IDs should be different
+        assert_ne!(id1, id2);
+    }
+
+    /// Test that requests interleaved on one thread each see their own ID
+    #[actix_rt::test]
+    async fn test_interleaved_requests_keep_ids() {
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
+            web::resource("/").to(|id: RequestID| async move {
+                let before = RequestID::current().unwrap();
+                actix_rt::task::yield_now().await;
+                let after = RequestID::with_current(|current| current.unwrap().to_string());
+                assert_eq!(before.as_str(), id.as_str());
+                HttpResponse::Ok().body(after)
+            }),
+        ))
+        .await;
+
+        let first = actix_rt::spawn(app.call(test::TestRequest::with_uri("/").to_request()));
+        let second = actix_rt::spawn(app.call(test::TestRequest::with_uri("/").to_request()));
+        for resp in [
+            first.await.unwrap().unwrap(),
+            second.await.unwrap().unwrap(),
+        ] {
+            let header = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+            assert_eq!(test::read_body(resp).await, header.as_bytes());
+        }
+        assert!(RequestID::current().is_none());
+    }
+
+    /// Test that a future runs under the ID given to `RequestID::scope`
+    #[actix_rt::test]
+    async fn test_scope() {
+        set_current_request_id("outer");
+        let id = RequestID::from("background-job");
+        let seen = id
+            .scope(async {
+                actix_rt::task::yield_now().await;
+                get_current_request_id()
+            })
+            .await;
+        assert_eq!(seen.as_deref(), Some("background-job"));
+        assert_eq!(get_current_request_id().as_deref(), Some("outer"));
+        clear_current_request_id();
+    }
+
+    /// Test ID length edge cases
+    #[actix_rt::test]
+    async fn test_id_length_edge_cases() {
+        // Test with length longer than UUID (should pad with additional entropy)
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_id_length(100))
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+
+        // Should be the requested length, starting with a full UUID
+        assert_eq!(request_id.len(), 100);
+        assert!(Uuid::parse_str(&request_id[..36]).is_ok());
+        assert!(request_id[36..].chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    /// Test that ID length composes with every generator regardless of call order
+    #[actix_rt::test]
+    async fn test_id_length_order_insensitive() {
+        let middlewares = vec![
+            RequestIDMiddleware::new()
+                .with_id_length(16)
+                .with_simple_uuid(),
+            RequestIDMiddleware::new()
+                .with_simple_uuid()
+                .with_id_length(16),
+            RequestIDMiddleware::new()
+                .with_id_length(16)
+                .with_full_uuid(),
+            RequestIDMiddleware::new()
+                .with_id_length(16)
+                .generator(|| "custom".to_string()),
+            RequestIDMiddleware::new()
+                .with_id_length(16)
+                .with_custom_uuid_format(|uuid| format!("req-{}", uuid.simple())),
+        ];
+
+        for middleware in middlewares {
+            assert_eq!(middleware.get_id_length(), 16);
+
+            let app = test::init_service(
+                App::new()
+                    .wrap(middleware)
+                    .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+            )
+            .await;
+
+            let req = test::TestRequest::with_uri("/").to_request();
+            let resp = test::call_service(&app, req).await;
+
+            let request_id = resp
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert_eq!(request_id.len(), 16);
+        }
+    }
+
+    /// Test that short custom IDs are padded to the configured length
+    #[actix_rt::test]
+    async fn test_custom_generator_padded() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .generator(|| "custom".to_string())
+                        .with_id_length(12),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(request_id.len(), 12);
+        assert!(request_id.starts_with("custom"));
+    }
+
+    /// Test namespace prefix and suffix around generated IDs
+    #[actix_rt::test]
+    async fn test_namespaced_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_prefix("billing")
+                        .with_suffix("eu1")
+                        .with_separator(":"),
+                )
+                .service(web::resource("/").to(|req_id: RequestID| async move {
+                    HttpResponse::Ok().json(serde_json::json!({
+                        "prefix": req_id.prefix(),
+                        "core": req_id.core(),
+                        "suffix": req_id.suffix(),
+                    }))
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(request_id.starts_with("billing:"));
+        assert!(request_id.ends_with(":eu1"));
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["prefix"], "billing");
+        assert_eq!(body["suffix"], "eu1");
+        let core = body["core"].as_str().unwrap();
+        assert!(Uuid::parse_str(core).is_ok());
+        assert_eq!(request_id, format!("billing:{}:eu1", core));
+    }
+
+    /// Test namespace length accounting together with a custom ID length
+    #[actix_rt::test]
+    async fn test_namespace_with_id_length() {
+        let middleware = RequestIDMiddleware::new()
+            .with_id_length(8)
+            .with_prefix("api-gw");
+        assert_eq!(middleware.get_id_length(), 8);
+        assert_eq!(middleware.get_total_id_length(), 8 + "api-gw_".len());
+
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
 
-        // Create new one if it doesn't exist
-        let new_id = RequestID {
-            inner: Uuid::new_v4().to_string(),
-        };
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
 
-        self.extensions_mut().insert(new_id.clone());
-        new_id
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(request_id.starts_with("api-gw_"));
+        assert_eq!(request_id.len(), 15);
     }
-}
 
-#[cfg(test)]
-mod lib_actix_web_request_uuid_tests {
-    use super::*;
-    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    /// Test splitting of namespaced request IDs
+    #[actix_rt::test]
+    async fn test_request_id_namespace_split() {
+        let id = RequestID::namespaced(Some("api-gw"), "01HZ", None, "_");
+        assert_eq!(id.as_str(), "api-gw_01HZ");
+        assert_eq!(id.prefix(), Some("api-gw"));
+        assert_eq!(id.core(), "01HZ");
+        assert_eq!(id.suffix(), None);
+
+        let id = RequestID::namespaced(None, "01HZ", Some("eu1"), "::");
+        assert_eq!(id.as_str(), "01HZ::eu1");
+        assert_eq!(id.prefix(), None);
+        assert_eq!(id.core(), "01HZ");
+        assert_eq!(id.suffix(), Some("eu1"));
+
+        let id = RequestID::from_core("plain".to_string());
+        assert_eq!(id.core(), "plain");
+        assert_eq!(id.prefix(), None);
+        assert_eq!(id.suffix(), None);
+    }
 
-    /// Test custom ID length
+    /// Test that IDs compare and hash by their string, not by how they were split
     #[actix_rt::test]
-    async fn test_custom_id_length() {
-        let id_length = 16;
+    async fn test_request_id_equality() {
+        use std::collections::HashSet;
+
+        let resolver = resolver::IdResolver::new(&RequestIDMiddleware::new().with_prefix("api"));
+        let generated = resolver.resolve(None, None);
+        assert_eq!(generated.prefix(), Some("api"));
+
+        let plain = RequestID::from(generated.as_str());
+        assert_eq!(plain.prefix(), None);
+        assert_eq!(generated, plain);
+        assert_eq!(
+            RequestID::namespaced(Some("api"), "1234", None, "_"),
+            RequestID::from("api_1234")
+        );
+        assert_ne!(generated, RequestID::from("api_1234"));
+
+        let set: HashSet<RequestID> = [generated, plain].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+
+    /// Test compact ID generation
+    #[actix_rt::test]
+    async fn test_compact_id_format() {
         let app = test::init_service(
             App::new()
-                .wrap(RequestIDMiddleware::new().with_id_length(id_length))
+                .wrap(RequestIDMiddleware::new().with_compact_id(Alphabet::Crockford32))
                 .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
         )
         .await;
@@ -430,22 +2379,49 @@ mod lib_actix_web_request_uuid_tests {
         let req = test::TestRequest::with_uri("/").to_request();
         let resp = test::call_service(&app, req).await;
 
-        assert_eq!(resp.status(), StatusCode::OK);
         let request_id = resp
             .headers()
             .get(REQUEST_ID_HEADER)
             .unwrap()
             .to_str()
             .unwrap();
-        assert_eq!(request_id.len(), id_length);
+        assert_eq!(request_id.len(), 26);
+        assert!(request_id
+            .chars()
+            .all(|c| Alphabet::Crockford32.chars().contains(c)));
     }
 
-    /// Test full UUID format
+    /// Test entropy and collision estimates on the middleware
     #[actix_rt::test]
-    async fn test_full_uuid_format() {
+    async fn test_entropy_estimates() {
+        assert_eq!(RequestIDMiddleware::new().entropy_bits(), Some(122.0));
+        assert_eq!(
+            RequestIDMiddleware::new().with_id_length(8).entropy_bits(),
+            Some(32.0)
+        );
+
+        let compact = RequestIDMiddleware::new()
+            .with_compact_id(Alphabet::UrlSafeBase64)
+            .with_id_length(8)
+            .with_prefix("api");
+        assert_eq!(compact.entropy_bits(), Some(48.0));
+        assert!(compact.collision_probability(1000).unwrap() < 1e-8);
+
+        let custom = RequestIDMiddleware::new().generator(|| "custom".to_string());
+        assert_eq!(custom.entropy_bits(), None);
+        assert_eq!(custom.collision_probability(1000), None);
+    }
+
+    /// Test nanoid generation with default and custom alphabets
+    #[actix_rt::test]
+    async fn test_nanoid_format() {
+        let middleware = RequestIDMiddleware::new().with_nanoid();
+        assert_eq!(middleware.get_id_length(), DEFAULT_NANOID_SIZE);
+        assert_eq!(middleware.entropy_bits(), Some(126.0));
+
         let app = test::init_service(
             App::new()
-                .wrap(RequestIDMiddleware::new().with_full_uuid())
+                .wrap(middleware)
                 .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
         )
         .await;
@@ -459,321 +2435,984 @@ mod lib_actix_web_request_uuid_tests {
             .unwrap()
             .to_str()
             .unwrap();
-        assert_eq!(request_id.len(), 36);
-        assert!(Uuid::parse_str(request_id).is_ok());
+        assert_eq!(request_id.len(), DEFAULT_NANOID_SIZE);
+        assert!(request_id.chars().all(|c| NANOID_ALPHABET.contains(c)));
+
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_custom_nanoid("1234567890abcdef", 10))
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(request_id.len(), 10);
+        assert!(request_id.chars().all(|c| c.is_ascii_hexdigit()));
     }
 
-    /// Test simple UUID format
+    /// Test panic on an invalid nanoid alphabet
     #[actix_rt::test]
-    async fn test_simple_uuid_format() {
+    #[should_panic(expected = "Alphabet must not contain duplicate characters")]
+    async fn test_invalid_nanoid_alphabet_panics() {
+        RequestIDMiddleware::new().with_custom_nanoid("aabc", 10);
+    }
+
+    /// Test KSUID generation and parsing through the extractor
+    #[actix_rt::test]
+    async fn test_ksuid_format() {
         let app = test::init_service(
             App::new()
-                .wrap(RequestIDMiddleware::new().with_simple_uuid())
-                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+                .wrap(RequestIDMiddleware::new().with_ksuid().with_prefix("evt"))
+                .service(web::resource("/").to(|req_id: RequestID| async move {
+                    let ksuid = req_id.ksuid().unwrap();
+                    HttpResponse::Ok().body(ksuid.unix_timestamp().to_string())
+                })),
         )
         .await;
 
+        let before = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
         let req = test::TestRequest::with_uri("/").to_request();
         let resp = test::call_service(&app, req).await;
 
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(request_id.len(), "evt_".len() + KSUID_LENGTH);
+        assert!(request_id[4..].parse::<Ksuid>().is_ok());
+
+        let body = test::read_body(resp).await;
+        let timestamp: u64 = String::from_utf8(body.to_vec()).unwrap().parse().unwrap();
+        assert!(timestamp >= before && timestamp <= before + 1);
+    }
+
+    /// Test that non-KSUID request IDs fail to parse
+    #[actix_rt::test]
+    async fn test_request_id_ksuid_parse_error() {
+        let id = RequestID::from_core(Uuid::new_v4().to_string());
+        assert_eq!(id.ksuid(), Err(KsuidParseError::InvalidLength(36)));
+    }
+
+    /// Test seeded IDs match the public iterator in headers and JSON bodies
+    #[actix_rt::test]
+    async fn test_seeded_ids() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_seeded_ids(1234))
+                .service(web::resource("/").to(|req_id: RequestID| async move {
+                    HttpResponse::Ok().json(serde_json::json!({ "request_id": req_id.to_string() }))
+                })),
+        )
+        .await;
+
+        for expected in SeededIds::new(1234).take(3) {
+            let req = test::TestRequest::with_uri("/").to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(
+                resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+                expected.as_str()
+            );
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body, serde_json::json!({ "request_id": expected }));
+        }
+    }
+
+    /// Test sequential IDs with a namespace and custom width
+    #[actix_rt::test]
+    async fn test_sequential_ids() {
+        let middleware = RequestIDMiddleware::new()
+            .with_sequential_ids()
+            .with_prefix("req")
+            .with_separator("-");
+        assert_eq!(middleware.entropy_bits(), None);
+
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        for expected in ["req-000001", "req-000002", "req-000003"] {
+            let req = test::TestRequest::with_uri("/").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), expected);
+        }
+
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_sequential_ids()
+                        .with_id_length(3),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        // Past request 1000, where the counter wraps around to `000`
+        for expected in SequentialIds::with_width(3).take(1001) {
+            assert_eq!(expected.len(), 3);
+            let req = test::TestRequest::with_uri("/").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(
+                resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+                expected.as_str()
+            );
+        }
+    }
+
+    /// Test propagation of inbound request IDs
+    #[actix_rt::test]
+    async fn test_propagation() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_propagation())
+                .service(web::resource("/").to(|req_id: RequestID| async move {
+                    HttpResponse::Ok().body(req_id.to_string())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "upstream-id-1"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "upstream-id-1"
+        );
+        assert_eq!(test::read_body(resp).await, "upstream-id-1");
+
+        // Invalid inbound IDs are replaced
+        let too_long = "a".repeat(MAX_INBOUND_ID_LENGTH + 1);
+        for invalid in ["", "with space", too_long.as_str()] {
+            let req = test::TestRequest::with_uri("/")
+                .insert_header((REQUEST_ID_HEADER, invalid))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let request_id = resp
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert!(Uuid::parse_str(request_id).is_ok());
+        }
+    }
+
+    /// Test that inbound request IDs are ignored without propagation
+    #[actix_rt::test]
+    async fn test_inbound_ignored_by_default() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new())
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "upstream-id-1"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "upstream-id-1"
+        );
+    }
+
+    /// Test signed request IDs minted at the edge and verified downstream
+    #[cfg(feature = "signing")]
+    #[actix_rt::test]
+    async fn test_signed_request_ids() {
+        let edge = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_prefix("edge")
+                        .with_signing(Signer::new(SigningKey::new("k1", "secret"))),
+                )
+                .service(web::resource("/").to(|req_id: RequestID| async move {
+                    assert!(req_id.is_verified());
+                    assert_eq!(req_id.prefix(), Some("edge"));
+                    assert_eq!(req_id.suffix(), None);
+                    HttpResponse::Ok().finish()
+                })),
+        )
+        .await;
+
+        let resp = test::call_service(&edge, test::TestRequest::with_uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let minted = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(minted.starts_with("edge_"));
+        assert!(minted.contains(".k1."));
+
+        // Downstream rotated to a new key but still accepts the old one
+        let downstream = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new().with_propagation().with_signing(
+                        Signer::new(SigningKey::new("k2", "new secret"))
+                            .with_verification_key(SigningKey::new("k1", "secret")),
+                    ),
+                )
+                .service(web::resource("/").to(|req_id: RequestID| async move {
+                    HttpResponse::Ok().body(format!("{} {}", req_id, req_id.is_verified()))
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, minted.as_str()))
+            .to_request();
+        let resp = test::call_service(&downstream, req).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            format!("{} true", minted).into_bytes()
+        );
+
+        // Forged IDs are propagated but not verified
+        let forged = minted.replacen("edge_", "evil_", 1);
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, forged.as_str()))
+            .to_request();
+        let resp = test::call_service(&downstream, req).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            format!("{} false", forged).into_bytes()
+        );
+    }
+
+    /// Test that unverified inbound IDs are replaced when only signing is enabled
+    #[cfg(feature = "signing")]
+    #[actix_rt::test]
+    async fn test_unsigned_inbound_replaced() {
+        let signer = Signer::new(SigningKey::new("k1", "secret"));
+        let middleware = RequestIDMiddleware::new().with_signing(signer.clone());
+        assert_eq!(
+            middleware.get_total_id_length(),
+            36 + signer.signature_length()
+        );
+
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "client-chosen-id"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
         let request_id = resp
             .headers()
             .get(REQUEST_ID_HEADER)
             .unwrap()
             .to_str()
             .unwrap();
-        assert_eq!(request_id.len(), 32);
+        assert_ne!(request_id, "client-chosen-id");
+        assert_eq!(signer.verify(request_id).map(str::len), Some(36));
     }
 
-    /// Test custom header name
+    /// Test that encrypted IDs carry metadata through the middleware
+    #[cfg(feature = "encrypted-ids")]
+    #[actix_rt::test]
+    async fn test_encrypted_ids() {
+        let ids = EncryptedIds::new([9; 32])
+            .with_node_id(3)
+            .with_tenant_id(42);
+        let middleware = RequestIDMiddleware::new()
+            .with_encrypted_ids(ids.clone())
+            .with_prefix("api");
+        assert_eq!(middleware.get_id_length(), ids.token_length());
+        assert_eq!(middleware.entropy_bits(), Some(192.0));
+
+        let handler_ids = ids.clone();
+        let app = test::init_service(App::new().wrap(middleware).service(web::resource("/").to(
+            move |req_id: RequestID| {
+                let metadata = req_id.metadata(&handler_ids).unwrap();
+                async move { HttpResponse::Ok().body(metadata.tenant_id.unwrap().to_string()) }
+            },
+        )))
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(request_id.starts_with("api_"));
+
+        let metadata = ids.find_and_decode(&request_id).unwrap();
+        assert_eq!(metadata.node_id, Some(3));
+        assert_eq!(metadata.tenant_id, Some(42));
+        assert!(metadata.timestamp.is_some());
+        assert_eq!(test::read_body(resp).await, "42");
+    }
+
+    /// Test that every ID is reported to the metrics hook with its provenance
+    #[actix_rt::test]
+    async fn test_metrics_provenance() {
+        let counters = RequestIDCounters::new();
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_propagation()
+                        .with_metrics(counters.clone()),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let requests = [None, Some("client-id"), Some("bad id"), None];
+        for inbound in requests {
+            let mut req = test::TestRequest::with_uri("/");
+            if let Some(inbound) = inbound {
+                req = req.insert_header((REQUEST_ID_HEADER, inbound));
+            }
+            test::call_service(&app, req.to_request()).await;
+        }
+
+        assert_eq!(
+            counters.snapshot(),
+            RequestIDCounts {
+                generated: 2,
+                propagated: 1,
+                malformed: 1,
+                regenerated: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    /// Test that valid inbound IDs are regenerated without propagation
+    #[actix_rt::test]
+    async fn test_metrics_without_propagation() {
+        let counters = RequestIDCounters::new();
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_metrics(counters.clone()))
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "client-id"))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let counts = counters.snapshot();
+        assert_eq!(counts.regenerated, 1);
+        assert_eq!(counts.invalid(), 0);
+        assert_eq!(counts.generated + counts.propagated, 0);
+    }
+
+    /// Test that unwritable IDs are reported instead of panicking
+    #[actix_rt::test]
+    async fn test_header_write_failed() {
+        let counters = RequestIDCounters::new();
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .generator(|| "line\nbreak".to_string())
+                        .with_metrics(counters.clone()),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+        assert!(resp.status().is_success());
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+        assert_eq!(counters.snapshot().header_write_failed, 1);
+    }
+
+    /// Test that unverified IDs are reported with their reason
+    #[cfg(feature = "signing")]
+    #[actix_rt::test]
+    async fn test_metrics_unverified() {
+        let counters = RequestIDCounters::new();
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_signing(Signer::new(SigningKey::new("k1", "secret")))
+                        .with_metrics(counters.clone()),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "forged.k1.00000000"))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let counts = counters.snapshot();
+        assert_eq!(counts.unverified, 1);
+        assert_eq!(counts.regenerated, 1);
+    }
+
+    /// Test that request and response hooks see the same ID and the final response
+    #[actix_rt::test]
+    async fn test_lifecycle_hooks() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_request = seen.clone();
+        let on_response = seen.clone();
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .on_request(move |id, req| {
+                            on_request.lock().unwrap().push(format!(
+                                "request {} {}",
+                                id,
+                                req.path()
+                            ));
+                        })
+                        .on_response(move |id, res, elapsed| {
+                            assert!(elapsed > Duration::ZERO);
+                            assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), id.as_str());
+                            on_response.lock().unwrap().push(format!(
+                                "response {} {}",
+                                id,
+                                res.status()
+                            ));
+                        }),
+                )
+                .service(web::resource("/hooks").to(|| async { HttpResponse::Created().finish() })),
+        )
+        .await;
+
+        let resp =
+            test::call_service(&app, test::TestRequest::with_uri("/hooks").to_request()).await;
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                format!("request {} /hooks", request_id),
+                format!("response {} 201 Created", request_id),
+            ]
+        );
+    }
+
+    /// Test that async hooks complete before the handler runs and the response is sent
     #[actix_rt::test]
-    async fn test_custom_header_name() {
-        let custom_header = "X-Request-ID";
+    async fn test_async_lifecycle_hooks() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_request = seen.clone();
+        let on_response = seen.clone();
+        let handler = seen.clone();
         let app = test::init_service(
             App::new()
                 .wrap(
                     RequestIDMiddleware::new()
-                        .with_simple_uuid()
-                        .header_name(custom_header),
+                        .on_request_async(move |id, _req| {
+                            let seen = on_request.clone();
+                            async move {
+                                actix_rt::task::yield_now().await;
+                                seen.lock().unwrap().push(format!("request {}", id));
+                            }
+                        })
+                        .on_response_async(move |id, req, status, _elapsed| {
+                            let seen = on_response.clone();
+                            async move {
+                                actix_rt::task::yield_now().await;
+                                seen.lock().unwrap().push(format!(
+                                    "response {} {} {}",
+                                    id,
+                                    req.path(),
+                                    status
+                                ));
+                            }
+                        }),
                 )
-                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+                .service(web::resource("/").to(move || {
+                    handler
+                        .lock()
+                        .unwrap()
+                        .push(format!("handler {}", get_current_request_id().unwrap()));
+                    async { HttpResponse::Ok().finish() }
+                })),
         )
         .await;
 
-        let req = test::TestRequest::with_uri("/").to_request();
-        let resp = test::call_service(&app, req).await;
-
-        assert!(resp.headers().get(custom_header).is_some());
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+        let request_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                format!("request {}", request_id),
+                format!("handler {}", request_id),
+                format!("response {} / 200 OK", request_id),
+            ]
+        );
     }
 
-    /// Test custom format
+    /// Test that error hooks run for error responses and service errors
     #[actix_rt::test]
-    async fn test_custom_format() {
+    async fn test_error_hooks() {
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let responses = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let on_error = errors.clone();
+        let on_response = responses.clone();
         let app = test::init_service(
             App::new()
+                .wrap_fn(|req, srv| {
+                    let fail = req.path() == "/fail";
+                    let fut = srv.call(req);
+                    async move {
+                        if fail {
+                            return Err(actix_web::error::ErrorServiceUnavailable("down"));
+                        }
+                        fut.await
+                    }
+                })
                 .wrap(
                     RequestIDMiddleware::new()
-                        .with_custom_uuid_format(|uuid| format!("req-{}", uuid.simple())),
+                        .on_error(move |id, error, _elapsed| {
+                            on_error
+                                .lock()
+                                .unwrap()
+                                .push((id.to_string(), error.to_string()));
+                        })
+                        .on_response(move |_, _, _| {
+                            on_response.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        }),
                 )
-                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+                .service(web::resource("/handler").to(|| async {
+                    Err::<HttpResponse, _>(actix_web::error::ErrorBadRequest("bad input"))
+                })),
         )
         .await;
 
-        let req = test::TestRequest::with_uri("/").to_request();
-        let resp = test::call_service(&app, req).await;
-
+        let resp =
+            test::call_service(&app, test::TestRequest::with_uri("/handler").to_request()).await;
+        assert_eq!(resp.status(), 400);
         let request_id = resp
             .headers()
             .get(REQUEST_ID_HEADER)
             .unwrap()
             .to_str()
             .unwrap();
-        assert!(request_id.starts_with("req-"));
-    }
-
-    /// Test panic when ID length is 0
-    #[actix_rt::test]
-    #[should_panic(expected = "Request ID length must be greater than 0")]
-    async fn test_zero_length_id_panics() {
-        RequestIDMiddleware::new().with_id_length(0);
+        assert_eq!(
+            errors.lock().unwrap()[0],
+            (request_id.to_string(), "bad input".to_string())
+        );
+        assert_eq!(responses.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let result = app
+            .call(test::TestRequest::with_uri("/fail").to_request())
+            .await;
+        assert!(result.is_err());
+        assert_eq!(errors.lock().unwrap()[1].1, "down");
+        assert_eq!(responses.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(get_current_request_id().is_none());
     }
 
-    /// Test thread-local request ID
+    /// Test that access log lines carry the header ID for responses and errors
     #[actix_rt::test]
-    async fn test_thread_local_request_id() {
-        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
-            web::resource("/").to(|| async {
-                // Get request ID from thread-local variable
-                let request_id = get_current_request_id().unwrap_or_else(|| "missing".to_string());
-                HttpResponse::Ok().body(request_id)
-            }),
-        ))
+    async fn test_access_log() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let fail = req.path() == "/fail";
+                    let fut = srv.call(req);
+                    async move {
+                        if fail {
+                            return Err(actix_web::error::ErrorServiceUnavailable("down"));
+                        }
+                        fut.await
+                    }
+                })
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_propagation()
+                        .with_access_log(AccessLog::new(LogFormat::Json, ChannelSink::new(tx))),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().body("hello") })),
+        )
         .await;
 
-        let req = test::TestRequest::with_uri("/").to_request();
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "inbound-id"))
+            .insert_header(("user-agent", "test-agent"))
+            .peer_addr("192.0.2.1:4000".parse().unwrap())
+            .to_request();
         let resp = test::call_service(&app, req).await;
-
-        assert_eq!(resp.status(), StatusCode::OK);
-
-        // Get request ID from response body
-        let body = test::read_body(resp).await;
-        let body_str = String::from_utf8(body.to_vec()).unwrap();
-
-        // Verify that request ID length is 36
-        assert_eq!(body_str.len(), 36);
-
-        // Verify that it's a valid UUID format
-        assert!(Uuid::parse_str(&body_str).is_ok());
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "inbound-id");
+        // The line is written once the body was sent
+        assert!(rx.try_recv().is_err());
+        test::read_body(resp).await;
+
+        let line: serde_json::Value = serde_json::from_str(&rx.recv().unwrap()).unwrap();
+        assert_eq!(line["id"], "inbound-id");
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["bytes"], 5);
+        assert_eq!(line["peer"], "192.0.2.1");
+        assert_eq!(line["user_agent"], "test-agent");
+        assert!(line["latency_ms"].as_f64().unwrap() >= 0.0);
+
+        let result = app
+            .call(test::TestRequest::with_uri("/fail").to_request())
+            .await;
+        assert!(result.is_err());
+        let line: serde_json::Value = serde_json::from_str(&rx.recv().unwrap()).unwrap();
+        assert_eq!(line["status"], 503);
+        assert_eq!(line["path"], "/fail");
+        assert!(line["id"].as_str().unwrap().len() == 36);
     }
 
-    /// Test RequestID struct conversion traits
+    /// Test looking up recent requests through the debug scope
     #[actix_rt::test]
-    async fn test_request_id_conversions() {
-        let id_str = "test-request-id-123";
-        let request_id = RequestID {
-            inner: id_str.to_string(),
-        };
-
-        // Test Display trait
-        assert_eq!(format!("{}", request_id), id_str);
-
-        // Test From<RequestID> for String
-        let converted: String = request_id.clone().into();
-        assert_eq!(converted, id_str);
-
-        // Test Debug trait
-        let debug_str = format!("{:?}", request_id);
-        assert!(debug_str.contains("RequestID"));
-        assert!(debug_str.contains(id_str));
-
-        // Test PartialEq and Eq
-        let request_id2 = RequestID {
-            inner: id_str.to_string(),
-        };
-        assert_eq!(request_id, request_id2);
-    }
+    async fn test_recent_requests_scope() {
+        let recent = RecentRequests::new(10);
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_recent_requests(&recent))
+                .service(recent.scope("/_debug/requests"))
+                .service(web::resource("/ok").to(|| async { HttpResponse::Ok().finish() }))
+                .service(web::resource("/bad").to(|| async {
+                    Err::<HttpResponse, _>(actix_web::error::ErrorBadRequest("missing field"))
+                })),
+        )
+        .await;
 
-    /// Test RequestIDMessage trait implementation
-    #[actix_rt::test]
-    async fn test_request_id_message_trait() {
-        let req = test::TestRequest::default().to_http_request();
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/ok").to_request()).await;
+        let ok_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        drop(resp);
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/bad").to_request()).await;
+        let bad_id = resp
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        drop(resp);
+
+        let req = test::TestRequest::with_uri(&format!("/_debug/requests/{}", bad_id)).to_request();
+        let summary: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(summary["id"], bad_id.as_str());
+        assert_eq!(summary["path"], "/bad");
+        assert_eq!(summary["status"], 400);
+        assert_eq!(summary["error"], "missing field");
+        assert!(summary["started_at"].as_str().unwrap().ends_with('Z'));
+
+        let req = test::TestRequest::with_uri("/_debug/requests?limit=5").to_request();
+        let list: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let ids: Vec<&str> = list
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec![bad_id.as_str(), ok_id.as_str()]);
+        assert_eq!(list[1]["error"], serde_json::Value::Null);
 
-        // First call should create new ID
-        let id1 = req.request_id();
-        assert_eq!(id1.inner.len(), 36);
+        // Debug requests themselves are not recorded
+        assert_eq!(recent.len(), 2);
 
-        // Second call should return the same ID
-        let id2 = req.request_id();
-        assert_eq!(id1, id2);
+        let req = test::TestRequest::with_uri("/_debug/requests/unknown").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 
-    /// Test FromRequest implementation for RequestID
+    /// Test that a debug scope nested in another scope is not recorded either
     #[actix_rt::test]
-    async fn test_from_request_implementation() {
+    async fn test_recent_requests_nested_scope() {
+        let recent = RecentRequests::new(10);
         let app = test::init_service(
-            App::new().wrap(RequestIDMiddleware::new()).service(
-                web::resource("/")
-                    .to(|req_id: RequestID| async move { HttpResponse::Ok().body(req_id.inner) }),
-            ),
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_recent_requests(&recent))
+                .service(web::scope("/_debug").service(recent.scope("/requests")))
+                .service(web::resource("/ok").to(|| async { HttpResponse::Ok().finish() })),
         )
         .await;
 
-        let req = test::TestRequest::with_uri("/").to_request();
-        let resp = test::call_service(&app, req).await;
-
-        assert_eq!(resp.status(), StatusCode::OK);
-        let body = test::read_body(resp).await;
-        let body_str = String::from_utf8(body.to_vec()).unwrap();
-        assert_eq!(body_str.len(), 36);
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/ok").to_request()).await;
+        test::read_body(resp).await;
+        assert_eq!(recent.len(), 1);
+
+        for uri in [
+            "/_debug/requests",
+            "/_debug/requests?limit=oops",
+            "/_debug/requests/unknown",
+            "/_debug/requests/unknown/more",
+        ] {
+            let resp =
+                test::call_service(&app, test::TestRequest::with_uri(uri).to_request()).await;
+            test::read_body(resp).await;
+        }
+        assert_eq!(recent.len(), 1);
     }
 
-    /// Test custom generator function
+    /// Test the Server-Timing and X-Response-Time headers
     #[actix_rt::test]
-    async fn test_custom_generator() {
-        let custom_id = "custom-generated-id";
+    async fn test_timing_headers() {
         let app = test::init_service(
             App::new()
-                .wrap(RequestIDMiddleware::new().generator(move || custom_id.to_string()))
-                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_propagation()
+                        .with_server_timing()
+                        .with_response_time(),
+                )
+                .service(web::resource("/").to(|| async {
+                    actix_rt::time::sleep(Duration::from_millis(5)).await;
+                    HttpResponse::Ok()
+                        .insert_header(("server-timing", "db;dur=1"))
+                        .finish()
+                })),
         )
         .await;
 
-        let req = test::TestRequest::with_uri("/").to_request();
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, r#"id-with-"quote""#))
+            .to_request();
         let resp = test::call_service(&app, req).await;
 
-        let request_id = resp
+        let timings: Vec<&str> = resp
             .headers()
-            .get(REQUEST_ID_HEADER)
+            .get_all("server-timing")
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(timings[0], "db;dur=1");
+        let total = timings[1];
+        assert!(total.starts_with("total;dur="));
+        assert!(total.ends_with(r#";desc="id-with-\"quote\"""#));
+        let dur: f64 = total["total;dur=".len()..total.find(";desc").unwrap()]
+            .parse()
+            .unwrap();
+        assert!(dur >= 5.0);
+
+        let response_time = resp
+            .headers()
+            .get(RESPONSE_TIME_HEADER)
             .unwrap()
             .to_str()
             .unwrap();
-        assert_eq!(request_id, custom_id);
-    }
-
-    /// Test thread-local functions directly
-    #[actix_rt::test]
-    async fn test_thread_local_functions() {
-        // Initially should be None
-        assert!(get_current_request_id().is_none());
-
-        // Set request ID
-        let test_id = "test-thread-local-id";
-        set_current_request_id(test_id);
-
-        // Should be able to retrieve it
-        assert_eq!(get_current_request_id(), Some(test_id.to_string()));
-
-        // Clear request ID
-        clear_current_request_id();
-
-        // Should be None again
-        assert!(get_current_request_id().is_none());
-
-        // Test multiple set/clear cycles
-        set_current_request_id("id1");
-        assert_eq!(get_current_request_id(), Some("id1".to_string()));
-
-        set_current_request_id("id2");
-        assert_eq!(get_current_request_id(), Some("id2".to_string()));
-
-        clear_current_request_id();
-        assert!(get_current_request_id().is_none());
-    }
-
-    /// Test Default implementation for RequestIDMiddleware
-    #[actix_rt::test]
-    async fn test_middleware_default() {
-        let middleware = RequestIDMiddleware::default();
-        assert_eq!(middleware.get_id_length(), DEFAULT_ID_LENGTH);
-        assert_eq!(middleware.header_name, REQUEST_ID_HEADER);
+        let millis: f64 = response_time.strip_suffix("ms").unwrap().parse().unwrap();
+        assert!(millis >= 5.0);
     }
 
-    /// Test that existing request ID in extensions is reused
+    /// Test building the middleware from a configuration
     #[actix_rt::test]
-    async fn test_existing_request_id_reused() {
-        let existing_id = "pre-existing-request-id";
-
-        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
-            web::resource("/").to(move |req: HttpRequest| async move {
-                // Pre-set a request ID in extensions
-                req.extensions_mut().insert(RequestID {
-                    inner: existing_id.to_string(),
-                });
+    async fn test_from_config() {
+        let config = RequestIDConfig::from_vars(
+            "REQUEST_ID",
+            [
+                ("REQUEST_ID_HEADER_NAME", "x-request-id"),
+                ("REQUEST_ID_FORMAT", "crockford32"),
+                ("REQUEST_ID_LENGTH", "12"),
+                ("REQUEST_ID_PREFIX", "billing"),
+            ],
+        )
+        .unwrap();
+        let middleware = RequestIDMiddleware::from_config(&config).unwrap();
+        assert_eq!(middleware.get_total_id_length(), 20);
 
-                // Get the request ID - should use the existing one
-                let req_id = req.request_id();
-                HttpResponse::Ok().body(req_id.inner)
-            }),
-        ))
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
         .await;
-
-        let req = test::TestRequest::with_uri("/").to_request();
-        let resp = test::call_service(&app, req).await;
-
-        // Check that the existing ID was used in response header
-        let header_id = resp
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+        let id = resp
             .headers()
-            .get(REQUEST_ID_HEADER)
+            .get("x-request-id")
             .unwrap()
             .to_str()
             .unwrap();
-
-        // The header should have a generated UUID, not the pre-existing one
-        // because the middleware runs before the handler
-        assert_ne!(header_id, existing_id);
-        assert_eq!(header_id.len(), 36);
+        let core = id.strip_prefix("billing_").unwrap();
+        assert_eq!(core.len(), 12);
+        assert!(core
+            .chars()
+            .all(|c| Alphabet::Crockford32.chars().contains(c)));
     }
 
-    /// Test multiple concurrent requests have different IDs
+    /// Test that invalid configurations are rejected instead of panicking
     #[actix_rt::test]
-    async fn test_concurrent_requests_different_ids() {
-        let app = test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
-            web::resource("/").to(|| async {
-                let id = get_current_request_id().unwrap();
-                HttpResponse::Ok().body(id)
-            }),
-        ))
-        .await;
-
-        // Make multiple requests
-        let req1 = test::TestRequest::with_uri("/").to_request();
-        let req2 = test::TestRequest::with_uri("/").to_request();
+    async fn test_from_config_invalid() {
+        let error = |config: RequestIDConfig| RequestIDMiddleware::from_config(&config).err();
 
-        let resp1 = test::call_service(&app, req1).await;
-        let resp2 = test::call_service(&app, req2).await;
+        assert_eq!(
+            error(RequestIDConfig {
+                header_name: Some("request id".to_string()),
+                ..Default::default()
+            }),
+            Some(ConfigError::InvalidHeaderName("request id".to_string()))
+        );
+        assert_eq!(
+            error(RequestIDConfig {
+                length: Some(0),
+                ..Default::default()
+            }),
+            Some(ConfigError::ZeroLength)
+        );
+        assert_eq!(
+            error(RequestIDConfig {
+                prefix: Some("api gw".to_string()),
+                ..Default::default()
+            }),
+            Some(ConfigError::InvalidNamespace("api gw".to_string()))
+        );
+        assert_eq!(
+            error(RequestIDConfig {
+                propagate: true,
+                trusted_networks: vec!["10.0.0.0/8".to_string(), "lb.internal".to_string()],
+                ..Default::default()
+            }),
+            Some(ConfigError::InvalidCidr("lb.internal".to_string()))
+        );
+    }
 
-        let body1 = test::read_body(resp1).await;
-        let body2 = test::read_body(resp2).await;
+    /// Test that inbound IDs are only honored from trusted networks
+    #[actix_rt::test]
+    async fn test_trusted_networks() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_propagation()
+                        .with_trusted_networks(["10.0.0.0/8".parse().unwrap()]),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
 
-        let id1 = String::from_utf8(body1.to_vec()).unwrap();
-        let id2 = String::from_utf8(body2.to_vec()).unwrap();
+        for (peer, honored) in [("10.1.2.3:5000", true), ("203.0.113.9:5000", false)] {
+            let req = test::TestRequest::with_uri("/")
+                .peer_addr(peer.parse().unwrap())
+                .insert_header((REQUEST_ID_HEADER, "client-id"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let id = resp.headers().get(REQUEST_ID_HEADER).unwrap();
+            assert_eq!(id == "client-id", honored, "{}", peer);
+        }
+    }
 
-        // IDs should be different
-        assert_ne!(id1, id2);
+    /// Test that the header value and bytes share the ID's buffer instead of copying it
+    #[actix_rt::test]
+    async fn test_shared_id_buffer() {
+        let id = RequestID::namespaced(Some("api"), "01HZ", None, "_");
+        let clone = id.clone();
+
+        assert_eq!(clone.as_str().as_ptr(), id.as_str().as_ptr());
+        assert_eq!(id.to_bytes().as_ptr(), id.as_str().as_ptr());
+        assert_eq!(
+            id.header_value().unwrap().as_bytes().as_ptr(),
+            id.as_str().as_ptr()
+        );
+        assert_eq!(id.to_bytes(), "api_01HZ");
+
+        assert!(RequestID::from_core("line\nbreak").header_value().is_none());
     }
 
-    /// Test ID length edge cases
+    /// Test that UUID v7 IDs are issued and sort by time
     #[actix_rt::test]
-    async fn test_id_length_edge_cases() {
-        // Test with length longer than UUID (should return full UUID)
+    async fn test_uuid_v7() {
         let app = test::init_service(
             App::new()
-                .wrap(RequestIDMiddleware::new().with_id_length(100))
+                .wrap(RequestIDMiddleware::new().with_uuid_v7())
                 .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
         )
         .await;
 
-        let req = test::TestRequest::with_uri("/").to_request();
-        let resp = test::call_service(&app, req).await;
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let resp =
+                test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+            let id = resp
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert_eq!(Uuid::parse_str(id).unwrap().get_version_num(), 7);
+            ids.push(id.to_string());
+            actix_rt::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert!(ids[0] < ids[1]);
+    }
 
-        let request_id = resp
-            .headers()
-            .get(REQUEST_ID_HEADER)
-            .unwrap()
-            .to_str()
+    /// Test that the per-thread CSPRNG can be selected
+    #[cfg(feature = "fast-rng")]
+    #[actix_rt::test]
+    async fn test_fast_rng() {
+        let middleware = RequestIDMiddleware::builder()
+            .with_fast_rng()
+            .with_prefix("api")
+            .build()
             .unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().finish() })),
+        )
+        .await;
 
-        // Should be standard UUID length
-        assert_eq!(request_id.len(), 36);
+        let mut ids = std::collections::HashSet::new();
+        for _ in 0..50 {
+            let resp =
+                test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+            let id = resp
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            let core = id.strip_prefix("api_").unwrap();
+            assert_eq!(Uuid::parse_str(core).unwrap().get_version_num(), 4);
+            assert!(ids.insert(id.to_string()));
+        }
     }
 }
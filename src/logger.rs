@@ -0,0 +1,174 @@
+//! Request IDs in actix-web's `Logger`
+//!
+//! `Logger` formats can include values computed by the application through
+//! `%{label}xi` (from the request) and `%{label}xo` (from the response). This module
+//! provides the label and replacement functions for the request ID, so access lines
+//! carry the ID even if the response header is renamed or disabled.
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Logger;
+use actix_web::HttpMessage;
+
+use crate::RequestID;
+
+/// Label of the request ID in `Logger` formats
+///
+/// Use `%{request-id}xi` if the `Logger` is wrapped inside the middleware, and
+/// `%{request-id}xo` if it is wrapped outside of it. See [`LoggerExt::with_request_id`].
+pub const LOGGER_REQUEST_ID_LABEL: &str = "request-id";
+
+/// Get the request ID of a request for log output
+///
+/// Unlike [`RequestIDMessage::request_id`](crate::RequestIDMessage::request_id), this
+/// never creates an ID, which would otherwise be kept by the middleware in place of the
+/// configured generator or an inbound ID.
+///
+/// # Arguments
+///
+/// * `msg` - A request, e.g. a `ServiceRequest` or `HttpRequest`
+///
+/// # Returns
+///
+/// The request ID, or `"-"` if the middleware has not assigned one (yet), following the
+/// `Logger` convention for missing values.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web::test::TestRequest;
+/// use actix_web_request_uuid::{logged_request_id, RequestIDMessage};
+///
+/// let req = TestRequest::default().to_http_request();
+/// assert_eq!(logged_request_id(&req), "-");
+///
+/// let id = req.request_id();
+/// assert_eq!(logged_request_id(&req), id.as_str());
+/// ```
+pub fn logged_request_id<T: HttpMessage>(msg: &T) -> String {
+    match msg.extensions().get::<RequestID>() {
+        Some(id) => id.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// Extension trait registering the request ID on actix-web's `Logger`
+pub trait LoggerExt {
+    /// Fill `%{request-id}xi` and `%{request-id}xo` with the request ID
+    ///
+    /// `Logger` renders `xi` values when the request arrives and `xo` values when the
+    /// response is ready. Middleware wrapped last runs first, so:
+    ///
+    /// - with `.wrap(Logger).wrap(RequestIDMiddleware)`, the ID is assigned before the
+    ///   `Logger` sees the request, and both `xi` and `xo` work
+    /// - with `.wrap(RequestIDMiddleware).wrap(Logger)`, the request arrives at the
+    ///   `Logger` before it has an ID, so only `xo` works and `xi` renders `"-"`
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web::{middleware::Logger, App};
+    /// use actix_web_request_uuid::{LoggerExt, RequestIDMiddleware};
+    ///
+    /// let app = App::new()
+    ///     .wrap(RequestIDMiddleware::new().header_name("x-correlation-id"))
+    ///     .wrap(Logger::new(r#"%a "%r" %s %b %T %{request-id}xo"#).with_request_id());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the `Logger` has been cloned, like `Logger::custom_request_replace`.
+    fn with_request_id(self) -> Self;
+}
+
+impl LoggerExt for Logger {
+    fn with_request_id(self) -> Self {
+        self.custom_request_replace(LOGGER_REQUEST_ID_LABEL, |req: &ServiceRequest| {
+            logged_request_id(req)
+        })
+        .custom_response_replace(LOGGER_REQUEST_ID_LABEL, |res: &ServiceResponse| {
+            logged_request_id(res.request())
+        })
+    }
+}
+
+#[cfg(test)]
+mod logger_tests {
+    use super::*;
+    use crate::{RequestIDMiddleware, REQUEST_ID_HEADER};
+    use actix_web::{test, web, App, HttpResponse};
+    use std::sync::{Mutex, Once};
+
+    /// Lines written by actix-web's `Logger`
+    static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if record.target().starts_with("actix_web::middleware::logger") {
+                LINES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+    }
+
+    /// Take the captured line starting with the given tag
+    fn take_line(tag: &str) -> String {
+        let mut lines = LINES.lock().unwrap();
+        let index = lines.iter().position(|line| line.starts_with(tag)).unwrap();
+        lines.remove(index)
+    }
+
+    /// Test that both wrapping orders log the ID the middleware assigned
+    #[actix_rt::test]
+    async fn test_logger_formats() {
+        capture();
+        let format = |tag| format!("{} %{{request-id}}xi %{{request-id}}xo", tag);
+
+        // Logger inside the middleware, and no ID header at all
+        let app = test::init_service(
+            App::new()
+                .wrap(Logger::new(&format("inner")).with_request_id())
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_prefix("log")
+                        .header_name("invalid header"),
+                )
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let resp = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let id = logged_request_id(resp.request());
+        assert!(id.starts_with("log_"));
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+        drop(resp);
+        assert_eq!(take_line("inner"), format!("inner {} {}", id, id));
+
+        // Logger outside the middleware; its request replacement must not create an ID
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new().with_prefix("log"))
+                .wrap(Logger::new(&format("outer")).with_request_id())
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let resp = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let id = resp.headers().get(REQUEST_ID_HEADER).unwrap();
+        let id = id.to_str().unwrap().to_string();
+        assert!(id.starts_with("log_"));
+        drop(resp);
+        assert_eq!(take_line("outer"), format!("outer - {}", id));
+    }
+}
@@ -0,0 +1,456 @@
+//! Metrics on the provenance of request IDs
+//!
+//! Every request ends up in exactly one of three outcomes: a new ID was generated
+//! because the client sent none, an inbound ID was propagated, or an inbound ID was
+//! replaced with a regenerated one. Inbound IDs that fail validation are additionally
+//! reported as invalid, with the reason they were rejected.
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Name of the counter exported by the `prometheus` and `metrics` exporters
+pub const METRIC_NAME: &str = "request_ids_total";
+
+/// Reason an inbound request ID was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvalidReason {
+    /// Empty, too long, or containing characters other than visible ASCII
+    Malformed,
+    /// Not signed with a known key (with the `signing` feature)
+    Unverified,
+}
+
+impl InvalidReason {
+    /// Get the label value used by the exporters
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvalidReason::Malformed => "malformed",
+            InvalidReason::Unverified => "unverified",
+        }
+    }
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Callbacks invoked by the middleware for every request ID it handles
+///
+/// All methods default to doing nothing, so implementations only override the
+/// events they are interested in. Callbacks run on the request path and must not
+/// block.
+///
+/// IDs preset in the request extensions, e.g. by an outer middleware, are not
+/// reported.
+///
+/// # Usage
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use actix_web_request_uuid::{RequestIDMetrics, RequestIDMiddleware};
+///
+/// #[derive(Default)]
+/// struct Propagated(AtomicU64);
+///
+/// impl RequestIDMetrics for Propagated {
+///     fn on_propagated(&self) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let middleware = RequestIDMiddleware::new()
+///     .with_propagation()
+///     .with_metrics(Propagated::default());
+/// ```
+pub trait RequestIDMetrics: Send + Sync {
+    /// A new ID was generated for a request without an inbound ID
+    fn on_generated(&self) {}
+
+    /// An inbound ID from the request header was used
+    fn on_propagated(&self) {}
+
+    /// An inbound ID was rejected
+    fn on_invalid(&self, reason: InvalidReason) {
+        let _ = reason;
+    }
+
+    /// A new ID was generated in place of an inbound ID
+    ///
+    /// Follows [`RequestIDMetrics::on_invalid`] for rejected IDs, and is also
+    /// reported alone for valid inbound IDs when propagation is disabled.
+    fn on_regenerated(&self) {}
+
+    /// The request ID could not be written to the response header
+    ///
+    /// This happens when a custom generator or header name produces characters that
+    /// are not allowed in HTTP headers. The response is sent without the header.
+    fn on_header_write_failed(&self) {}
+}
+
+impl<M: RequestIDMetrics + ?Sized> RequestIDMetrics for Arc<M> {
+    fn on_generated(&self) {
+        (**self).on_generated()
+    }
+
+    fn on_propagated(&self) {
+        (**self).on_propagated()
+    }
+
+    fn on_invalid(&self, reason: InvalidReason) {
+        (**self).on_invalid(reason)
+    }
+
+    fn on_regenerated(&self) {
+        (**self).on_regenerated()
+    }
+
+    fn on_header_write_failed(&self) {
+        (**self).on_header_write_failed()
+    }
+}
+
+/// Point-in-time values of [`RequestIDCounters`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestIDCounts {
+    /// IDs generated for requests without an inbound ID
+    pub generated: u64,
+    /// Inbound IDs propagated
+    pub propagated: u64,
+    /// Inbound IDs rejected as malformed
+    pub malformed: u64,
+    /// Inbound IDs rejected as unverified
+    pub unverified: u64,
+    /// IDs generated in place of an inbound ID
+    pub regenerated: u64,
+    /// Responses sent without the request ID header
+    pub header_write_failed: u64,
+}
+
+impl RequestIDCounts {
+    /// Get the number of rejected inbound IDs, for any reason
+    pub fn invalid(&self) -> u64 {
+        self.malformed + self.unverified
+    }
+}
+
+/// In-memory [`RequestIDMetrics`] implementation
+///
+/// Clones share the same counters, so one handle can be registered with the
+/// middleware while another is read, e.g. by a status endpoint.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{RequestIDCounters, RequestIDMiddleware};
+///
+/// let counters = RequestIDCounters::new();
+/// let middleware = RequestIDMiddleware::new().with_metrics(counters.clone());
+///
+/// // ... serve requests ...
+/// let counts = counters.snapshot();
+/// println!("{} of {} IDs came from clients", counts.propagated, counts.generated + counts.propagated);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RequestIDCounters {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    generated: AtomicU64,
+    propagated: AtomicU64,
+    malformed: AtomicU64,
+    unverified: AtomicU64,
+    regenerated: AtomicU64,
+    header_write_failed: AtomicU64,
+}
+
+impl RequestIDCounters {
+    /// Create counters starting at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read all counters
+    pub fn snapshot(&self) -> RequestIDCounts {
+        let c = &self.inner;
+        RequestIDCounts {
+            generated: c.generated.load(Ordering::Relaxed),
+            propagated: c.propagated.load(Ordering::Relaxed),
+            malformed: c.malformed.load(Ordering::Relaxed),
+            unverified: c.unverified.load(Ordering::Relaxed),
+            regenerated: c.regenerated.load(Ordering::Relaxed),
+            header_write_failed: c.header_write_failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all counters to zero
+    pub fn reset(&self) {
+        let c = &self.inner;
+        for counter in [
+            &c.generated,
+            &c.propagated,
+            &c.malformed,
+            &c.unverified,
+            &c.regenerated,
+            &c.header_write_failed,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl RequestIDMetrics for RequestIDCounters {
+    fn on_generated(&self) {
+        self.inner.generated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_propagated(&self) {
+        self.inner.propagated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_invalid(&self, reason: InvalidReason) {
+        match reason {
+            InvalidReason::Malformed => &self.inner.malformed,
+            InvalidReason::Unverified => &self.inner.unverified,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_regenerated(&self) {
+        self.inner.regenerated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_header_write_failed(&self) {
+        self.inner
+            .header_write_failed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// [`RequestIDMetrics`] exporter for the `prometheus` crate
+///
+/// Available with the `prometheus` feature. Registers a `request_ids_total` counter
+/// with an `outcome` label (`generated`, `propagated`, `invalid`, `regenerated` or
+/// `header_write_failed`) and a `reason` label that is only set for `invalid`.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{PrometheusMetrics, RequestIDMiddleware};
+///
+/// let registry = prometheus::Registry::new();
+/// let middleware = RequestIDMiddleware::new()
+///     .with_metrics(PrometheusMetrics::new(&registry).unwrap());
+/// ```
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    counter: prometheus::IntCounterVec,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusMetrics {
+    /// Create the counter and register it with `registry`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(PrometheusMetrics)` - If the counter was registered
+    /// * `Err(prometheus::Error)` - If a metric with the same name is already registered
+    pub fn new(registry: &prometheus::Registry) -> prometheus::Result<Self> {
+        let counter = prometheus::IntCounterVec::new(
+            prometheus::Opts::new(METRIC_NAME, "Request IDs handled, by outcome"),
+            &["outcome", "reason"],
+        )?;
+        registry.register(Box::new(counter.clone()))?;
+        Ok(Self { counter })
+    }
+
+    fn inc(&self, outcome: &str, reason: &str) {
+        self.counter.with_label_values(&[outcome, reason]).inc();
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl RequestIDMetrics for PrometheusMetrics {
+    fn on_generated(&self) {
+        self.inc("generated", "");
+    }
+
+    fn on_propagated(&self) {
+        self.inc("propagated", "");
+    }
+
+    fn on_invalid(&self, reason: InvalidReason) {
+        self.inc("invalid", reason.as_str());
+    }
+
+    fn on_regenerated(&self) {
+        self.inc("regenerated", "");
+    }
+
+    fn on_header_write_failed(&self) {
+        self.inc("header_write_failed", "");
+    }
+}
+
+/// [`RequestIDMetrics`] exporter for the `metrics` crate facade
+///
+/// Available with the `metrics` feature. Increments a `request_ids_total` counter on
+/// the globally installed recorder, with the same labels as [`PrometheusMetrics`]
+/// except that `reason` is omitted instead of empty.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsExporter;
+
+#[cfg(feature = "metrics")]
+impl RequestIDMetrics for MetricsExporter {
+    fn on_generated(&self) {
+        ::metrics::counter!(METRIC_NAME, "outcome" => "generated").increment(1);
+    }
+
+    fn on_propagated(&self) {
+        ::metrics::counter!(METRIC_NAME, "outcome" => "propagated").increment(1);
+    }
+
+    fn on_invalid(&self, reason: InvalidReason) {
+        ::metrics::counter!(METRIC_NAME, "outcome" => "invalid", "reason" => reason.as_str())
+            .increment(1);
+    }
+
+    fn on_regenerated(&self) {
+        ::metrics::counter!(METRIC_NAME, "outcome" => "regenerated").increment(1);
+    }
+
+    fn on_header_write_failed(&self) {
+        ::metrics::counter!(METRIC_NAME, "outcome" => "header_write_failed").increment(1);
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+
+    /// Test counting, snapshots and reset
+    #[test]
+    fn test_counters() {
+        let counters = RequestIDCounters::new();
+        let shared: Arc<dyn RequestIDMetrics> = Arc::new(counters.clone());
+
+        shared.on_generated();
+        shared.on_generated();
+        shared.on_propagated();
+        shared.on_invalid(InvalidReason::Malformed);
+        shared.on_invalid(InvalidReason::Unverified);
+        shared.on_regenerated();
+        shared.on_header_write_failed();
+
+        let counts = counters.snapshot();
+        assert_eq!(
+            counts,
+            RequestIDCounts {
+                generated: 2,
+                propagated: 1,
+                malformed: 1,
+                unverified: 1,
+                regenerated: 1,
+                header_write_failed: 1,
+            }
+        );
+        assert_eq!(counts.invalid(), 2);
+
+        counters.reset();
+        assert_eq!(counters.snapshot(), RequestIDCounts::default());
+    }
+
+    /// Test the prometheus exporter
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_exporter() {
+        let registry = prometheus::Registry::new();
+        let exporter = PrometheusMetrics::new(&registry).unwrap();
+        exporter.on_generated();
+        exporter.on_generated();
+        exporter.on_invalid(InvalidReason::Unverified);
+
+        assert!(PrometheusMetrics::new(&registry).is_err());
+        assert_eq!(
+            exporter.counter.with_label_values(&["generated", ""]).get(),
+            2
+        );
+        assert_eq!(
+            exporter
+                .counter
+                .with_label_values(&["invalid", "unverified"])
+                .get(),
+            1
+        );
+        assert_eq!(registry.gather()[0].name(), METRIC_NAME);
+    }
+
+    /// Test the metrics crate exporter with a minimal recorder
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_exporter() {
+        use ::metrics::{
+            Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString,
+            Unit,
+        };
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorded(Mutex<Vec<String>>);
+
+        struct KeyCounter(String, Arc<Recorded>);
+
+        impl CounterFn for KeyCounter {
+            fn increment(&self, _: u64) {
+                self.1 .0.lock().unwrap().push(self.0.clone());
+            }
+
+            fn absolute(&self, _: u64) {}
+        }
+
+        struct TestRecorder(Arc<Recorded>);
+
+        impl Recorder for TestRecorder {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                let labels: Vec<String> = key
+                    .labels()
+                    .map(|l| format!("{}={}", l.key(), l.value()))
+                    .collect();
+                let name = format!("{}{{{}}}", key.name(), labels.join(","));
+                Counter::from_arc(Arc::new(KeyCounter(name, self.0.clone())))
+            }
+
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::noop()
+            }
+        }
+
+        let recorded = Arc::new(Recorded::default());
+        let recorder = TestRecorder(recorded.clone());
+        ::metrics::with_local_recorder(&recorder, || {
+            MetricsExporter.on_propagated();
+            MetricsExporter.on_invalid(InvalidReason::Malformed);
+        });
+
+        assert_eq!(
+            *recorded.0.lock().unwrap(),
+            vec![
+                "request_ids_total{outcome=propagated}",
+                "request_ids_total{outcome=invalid,reason=malformed}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! In-memory buffer of recent requests, queryable by request ID
+//!
+//! Meant for staging and debugging: answers "what happened to request X?" without a
+//! log pipeline. The buffer is bounded by size and optionally by age, and every
+//! access takes a lock, so keep it small.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Scope};
+
+use crate::access_log::{LineWriter, LogFormat};
+use crate::time::format_rfc3339;
+use crate::RequestID;
+
+/// Summary of a handled request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestSummary {
+    /// Request ID, as sent in the response header
+    pub id: String,
+    /// Time the request arrived
+    pub started_at: SystemTime,
+    /// Time the response was ready or the request failed
+    pub finished_at: SystemTime,
+    /// HTTP method
+    pub method: String,
+    /// Request path without the query string
+    pub path: String,
+    /// Response status code, or the status of the error if the request failed
+    pub status: u16,
+    /// Time spent handling the request
+    pub latency: Duration,
+    /// Error message, if the request failed or produced an error response
+    pub error: Option<String>,
+}
+
+impl RequestSummary {
+    /// Serialize as a JSON object
+    fn to_json(&self) -> String {
+        let mut json = LineWriter::new(LogFormat::Json);
+        json.string("id", Some(&self.id));
+        json.string("started_at", Some(&format_rfc3339(self.started_at)));
+        json.string("finished_at", Some(&format_rfc3339(self.finished_at)));
+        json.string("method", Some(&self.method));
+        json.string("path", Some(&self.path));
+        json.raw("status", Some(self.status.to_string()));
+        json.raw(
+            "latency_ms",
+            Some(format!("{:.3}", self.latency.as_secs_f64() * 1000.0)),
+        );
+        json.string("error", self.error.as_deref());
+        json.finish()
+    }
+}
+
+/// Bounded buffer of recent request summaries
+///
+/// Register with [`RequestIDMiddleware::with_recent_requests`] and mount
+/// [`RecentRequests::scope`] to query it over HTTP. Clones share the same buffer, so
+/// one instance can be shared by all workers.
+///
+/// # Usage
+///
+/// ```rust
+/// use std::time::Duration;
+/// use actix_web::App;
+/// use actix_web_request_uuid::{RecentRequests, RequestIDMiddleware};
+///
+/// let recent = RecentRequests::new(500).with_ttl(Duration::from_secs(600));
+///
+/// let app = App::new()
+///     .wrap(RequestIDMiddleware::new().with_recent_requests(&recent))
+///     .service(recent.scope("/_debug/requests"));
+/// ```
+///
+/// [`RequestIDMiddleware::with_recent_requests`]: crate::RequestIDMiddleware::with_recent_requests
+#[derive(Debug, Clone)]
+pub struct RecentRequests {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    capacity: usize,
+    ttl: Option<Duration>,
+    excluded: Vec<String>,
+    entries: VecDeque<(Instant, RequestSummary)>,
+}
+
+impl State {
+    /// Drop entries older than the TTL
+    fn evict_expired(&mut self) {
+        if let Some(ttl) = self.ttl {
+            while let Some((recorded, _)) = self.entries.front() {
+                if recorded.elapsed() <= ttl {
+                    break;
+                }
+                self.entries.pop_front();
+            }
+        }
+    }
+}
+
+impl RecentRequests {
+    /// Create a buffer keeping the last `capacity` requests
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        if capacity == 0 {
+            panic!("Recent requests capacity must be greater than 0");
+        }
+
+        Self {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                ttl: None,
+                excluded: Vec::new(),
+                entries: VecDeque::with_capacity(capacity),
+            })),
+        }
+    }
+
+    /// Forget requests older than `ttl`
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        self.lock().ttl = Some(ttl);
+        self
+    }
+
+    /// Do not record requests to `prefix` or to paths below it
+    ///
+    /// Whole path segments are matched: excluding `/health` also excludes
+    /// `/health/live`, but not `/healthz`. Requests served by [`RecentRequests::scope`]
+    /// are never recorded, wherever it is mounted.
+    pub fn exclude_path<T: Into<String>>(self, prefix: T) -> Self {
+        self.exclude(prefix.into());
+        self
+    }
+
+    /// Look up a request by its ID
+    pub fn get(&self, id: &str) -> Option<RequestSummary> {
+        let mut state = self.lock();
+        state.evict_expired();
+        state
+            .entries
+            .iter()
+            .rev()
+            .find(|(_, summary)| summary.id == id)
+            .map(|(_, summary)| summary.clone())
+    }
+
+    /// Get up to `limit` requests, most recent first
+    pub fn recent(&self, limit: usize) -> Vec<RequestSummary> {
+        let mut state = self.lock();
+        state.evict_expired();
+        state
+            .entries
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|(_, summary)| summary.clone())
+            .collect()
+    }
+
+    /// Get the number of buffered requests
+    pub fn len(&self) -> usize {
+        let mut state = self.lock();
+        state.evict_expired();
+        state.entries.len()
+    }
+
+    /// Check whether no requests are buffered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget all buffered requests
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// Create a scope serving the buffer as JSON
+    ///
+    /// * `GET <path>` lists buffered requests, most recent first; `?limit=N` caps
+    ///   the number of entries (default 100)
+    /// * `GET <path>/{id}` returns one request, or 404 if it is unknown or expired
+    ///
+    /// Requests served by the scope are not recorded, even if it is nested in other
+    /// scopes. Do not expose the scope publicly: it reveals paths and error messages
+    /// of other users' requests.
+    pub fn scope(&self, path: &str) -> Scope {
+        let list = self.clone();
+        let lookup = self.clone();
+        web::scope(path)
+            .route(
+                "",
+                web::get().to(move |req: HttpRequest| {
+                    mark_scope_request(&req);
+                    let query =
+                        web::Query::<HashMap<String, String>>::from_query(req.query_string());
+                    let limit = query
+                        .ok()
+                        .and_then(|query| query.get("limit")?.parse().ok())
+                        .unwrap_or(DEFAULT_LIST_LIMIT);
+                    let summaries = list.recent(limit);
+                    let body: Vec<String> = summaries.iter().map(|s| s.to_json()).collect();
+                    async move { json_response(format!("[{}]", body.join(","))) }
+                }),
+            )
+            .route(
+                "/{id}",
+                web::get().to(move |req: HttpRequest, id: web::Path<String>| {
+                    mark_scope_request(&req);
+                    let summary = lookup.get(&id);
+                    async move {
+                        match summary {
+                            Some(summary) => json_response(summary.to_json()),
+                            None => HttpResponse::NotFound().finish(),
+                        }
+                    }
+                }),
+            )
+            .default_service(web::to(|req: HttpRequest| {
+                mark_scope_request(&req);
+                async { HttpResponse::NotFound().finish() }
+            }))
+    }
+
+    /// Check whether requests to `path` are recorded
+    pub(crate) fn records(&self, path: &str) -> bool {
+        !self
+            .lock()
+            .excluded
+            .iter()
+            .any(|prefix| is_below(path, prefix))
+    }
+
+    /// Add an excluded path prefix, unless it is already excluded
+    fn exclude(&self, prefix: String) {
+        let mut state = self.lock();
+        if !state.excluded.contains(&prefix) {
+            state.excluded.push(prefix);
+        }
+    }
+
+    /// Capture the request side of a summary
+    pub(crate) fn start(&self, req: &ServiceRequest) -> RequestSummary {
+        RequestSummary {
+            id: String::new(),
+            started_at: SystemTime::now(),
+            finished_at: UNIX_EPOCH,
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            status: 0,
+            latency: Duration::ZERO,
+            error: None,
+        }
+    }
+
+    /// Complete a summary and add it to the buffer
+    pub(crate) fn finish(
+        &self,
+        mut summary: RequestSummary,
+        id: &RequestID,
+        status: StatusCode,
+        error: Option<String>,
+        latency: Duration,
+    ) {
+        summary.id = id.as_str().to_string();
+        summary.finished_at = SystemTime::now();
+        summary.status = status.as_u16();
+        summary.latency = latency;
+        summary.error = error;
+        self.record(summary);
+    }
+
+    /// Add a request summary, evicting the oldest one if the buffer is full
+    fn record(&self, summary: RequestSummary) {
+        let mut state = self.lock();
+        state.evict_expired();
+        if state.entries.len() == state.capacity {
+            state.entries.pop_front();
+        }
+        state.entries.push_back((Instant::now(), summary));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Marker in the extensions of requests served by [`RecentRequests::scope`]
+struct ScopeRequest;
+
+fn mark_scope_request(req: &HttpRequest) {
+    req.extensions_mut().insert(ScopeRequest);
+}
+
+/// Check whether a request was served by [`RecentRequests::scope`]
+pub(crate) fn is_scope_request(req: &HttpRequest) -> bool {
+    req.extensions().contains::<ScopeRequest>()
+}
+
+/// Check whether `path` is `prefix` or a path below it
+fn is_below(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Default number of requests listed by the debug scope
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+fn json_response(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(body)
+}
+
+#[cfg(test)]
+mod recent_tests {
+    use super::*;
+
+    fn summary(id: &str) -> RequestSummary {
+        RequestSummary {
+            id: id.to_string(),
+            started_at: UNIX_EPOCH,
+            finished_at: UNIX_EPOCH,
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status: 200,
+            latency: Duration::ZERO,
+            error: None,
+        }
+    }
+
+    /// Test that the buffer keeps the most recent entries
+    #[test]
+    fn test_capacity() {
+        let recent = RecentRequests::new(2);
+        for id in ["a", "b", "c"] {
+            recent.record(summary(id));
+        }
+
+        assert_eq!(recent.len(), 2);
+        assert!(recent.get("a").is_none());
+        assert_eq!(recent.get("c").unwrap().id, "c");
+        let ids: Vec<String> = recent.recent(10).into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec!["c", "b"]);
+    }
+
+    /// Test that expired entries are dropped
+    #[test]
+    fn test_ttl() {
+        let recent = RecentRequests::new(10).with_ttl(Duration::from_millis(20));
+        recent.record(summary("old"));
+        std::thread::sleep(Duration::from_millis(40));
+        recent.record(summary("new"));
+
+        assert!(recent.get("old").is_none());
+        assert_eq!(recent.len(), 1);
+    }
+
+    /// Test path exclusion
+    #[test]
+    fn test_excluded_paths() {
+        let recent = RecentRequests::new(10).exclude_path("/health");
+
+        assert!(recent.records("/users"));
+        assert!(!recent.records("/health"));
+        assert!(!recent.records("/health/live"));
+        assert!(recent.records("/healthz"));
+
+        let recent = RecentRequests::new(10).exclude_path("/static/");
+        assert!(!recent.records("/static/app.js"));
+        assert!(recent.records("/static"));
+    }
+
+    /// Test that creating a scope in every worker excludes no paths
+    #[test]
+    fn test_scope_per_worker() {
+        let recent = RecentRequests::new(10);
+        for _ in 0..4 {
+            let _scope = recent.scope("/_debug/requests");
+        }
+        assert!(recent.lock().excluded.is_empty());
+        assert!(recent.records("/_debug/requests"));
+    }
+}
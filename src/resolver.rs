@@ -0,0 +1,214 @@
+//! Framework-agnostic request ID resolution
+//!
+//! Decides from the inbound header value and the peer address whether a request keeps
+//! its inbound ID or is assigned a new one, and generates, signs and counts IDs. The
+//! actix-web middleware and the tower layer only differ in how they read the header and
+//! the peer address, so both share this core and behave identically.
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::generator::Generator;
+use crate::rng::Entropy;
+#[cfg(feature = "signing")]
+use crate::Signer;
+use crate::{trust, InvalidReason, IpCidr, Namespace, RequestID, RequestIDMetrics};
+use crate::{RequestIDMiddleware, MAX_INBOUND_ID_LENGTH};
+
+/// Generation and propagation settings of a middleware
+pub(crate) struct IdResolver {
+    generator: Generator,
+    entropy: Entropy,
+    id_length: Option<usize>,
+    namespace: Namespace,
+    propagate: bool,
+    trusted_networks: Vec<IpCidr>,
+    metrics: Option<Arc<dyn RequestIDMetrics>>,
+    #[cfg(feature = "signing")]
+    signer: Option<Arc<Signer>>,
+}
+
+/// Outcome of looking up the inbound request ID
+enum Inbound {
+    /// The inbound ID is used
+    Honored(RequestID),
+    /// The inbound ID is replaced, with the reason if it was invalid
+    Replaced(Option<InvalidReason>),
+}
+
+impl IdResolver {
+    /// Take the generation and propagation settings of a middleware
+    pub(crate) fn new(middleware: &RequestIDMiddleware) -> Self {
+        Self {
+            generator: middleware.generator.clone(),
+            entropy: middleware.entropy,
+            id_length: middleware.id_length,
+            namespace: middleware.namespace.clone(),
+            propagate: middleware.propagate,
+            trusted_networks: middleware.trusted_networks.clone(),
+            metrics: middleware.metrics.clone(),
+            #[cfg(feature = "signing")]
+            signer: middleware.signer.clone(),
+        }
+    }
+
+    /// Determine the ID of a request
+    ///
+    /// # Arguments
+    ///
+    /// * `inbound` - Value of the request ID header, if the request has one
+    /// * `peer` - Address of the client connection, if known
+    pub(crate) fn resolve(&self, inbound: Option<&[u8]>, peer: Option<SocketAddr>) -> RequestID {
+        let replaced = match inbound.map(|value| self.inbound_request_id(value, peer)) {
+            None => false,
+            Some(Inbound::Honored(inbound_id)) => {
+                self.record(|m| m.on_propagated());
+                return inbound_id;
+            }
+            Some(Inbound::Replaced(reason)) => {
+                if let Some(reason) = reason {
+                    self.record(|m| m.on_invalid(reason));
+                }
+                true
+            }
+        };
+
+        let new_id = self.generate();
+        if replaced {
+            self.record(|m| m.on_regenerated());
+        } else {
+            self.record(|m| m.on_generated());
+        }
+        new_id
+    }
+
+    /// Generate a new ID, fitted to the configured length, namespaced and signed
+    fn generate(&self) -> RequestID {
+        let new_id = self.namespace.apply(|out| match self.id_length {
+            Some(length) => self.generator.write_with_length(out, length, self.entropy),
+            None => self.generator.write(out, self.entropy),
+        });
+
+        #[cfg(feature = "signing")]
+        let new_id = match &self.signer {
+            Some(signer) => signer.sign_request_id(new_id),
+            None => new_id,
+        };
+
+        new_id
+    }
+
+    /// Decide whether an inbound request ID is honored
+    fn inbound_request_id(&self, value: &[u8], peer: Option<SocketAddr>) -> Inbound {
+        let value = match std::str::from_utf8(value) {
+            Ok(value) if is_valid_inbound_id(value) => value,
+            _ => return Inbound::Replaced(Some(InvalidReason::Malformed)),
+        };
+
+        #[cfg(feature = "signing")]
+        if let Some(signer) = &self.signer {
+            if let Some(verified_id) = signer.verify_request_id(value, &self.namespace) {
+                return Inbound::Honored(verified_id);
+            }
+            if !self.propagate {
+                return Inbound::Replaced(Some(InvalidReason::Unverified));
+            }
+        }
+
+        if self.propagate && trust::is_trusted(&self.trusted_networks, peer) {
+            Inbound::Honored(self.namespace.split(value, value.len()))
+        } else {
+            Inbound::Replaced(None)
+        }
+    }
+
+    /// Invoke the metrics hook, if one is configured
+    pub(crate) fn record(&self, event: impl FnOnce(&dyn RequestIDMetrics)) {
+        if let Some(metrics) = &self.metrics {
+            event(metrics.as_ref());
+        }
+    }
+}
+
+/// Check whether an inbound ID may be propagated
+fn is_valid_inbound_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_INBOUND_ID_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod resolver_tests {
+    use super::*;
+    use crate::RequestIDCounters;
+
+    /// Test that the same settings resolve IDs the same way, whatever the framework
+    #[test]
+    fn test_resolve() {
+        let counters = RequestIDCounters::new();
+        let core = IdResolver::new(
+            &RequestIDMiddleware::new()
+                .with_sequential_ids()
+                .with_propagation()
+                .with_metrics(counters.clone()),
+        );
+
+        assert_eq!(core.resolve(None, None).as_str(), "000001");
+        assert_eq!(core.resolve(Some(b"from-edge"), None).as_str(), "from-edge");
+        assert_eq!(core.resolve(Some(b"bad id"), None).as_str(), "000002");
+        assert_eq!(core.resolve(Some(b"\xff"), None).as_str(), "000003");
+
+        let counts = counters.snapshot();
+        assert_eq!(counts.generated, 1);
+        assert_eq!(counts.propagated, 1);
+        assert_eq!(counts.malformed, 2);
+        assert_eq!(counts.regenerated, 2);
+    }
+
+    /// Test that honored inbound IDs are split with the configured namespace
+    #[test]
+    fn test_inbound_namespace() {
+        let core = IdResolver::new(
+            &RequestIDMiddleware::new()
+                .with_prefix("api")
+                .with_suffix("eu")
+                .with_propagation(),
+        );
+
+        let id = core.resolve(Some(b"api_1234_eu"), None);
+        assert_eq!(id.prefix(), Some("api"));
+        assert_eq!(id.core(), "1234");
+        assert_eq!(id.suffix(), Some("eu"));
+
+        let id = core.resolve(Some(b"api_1234"), None);
+        assert_eq!(
+            (id.prefix(), id.core(), id.suffix()),
+            (Some("api"), "1234", None)
+        );
+
+        // Other namespaces and bare namespaces are part of the core ID
+        for inbound in ["web_1234", "api_"] {
+            let id = core.resolve(Some(inbound.as_bytes()), None);
+            assert_eq!(id.as_str(), inbound);
+            assert_eq!(id.prefix(), None);
+        }
+    }
+
+    /// Test that verified IDs are split with the configured namespace
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_verified_namespace() {
+        use crate::{Signer, SigningKey};
+
+        let middleware = || {
+            RequestIDMiddleware::new()
+                .with_prefix("api")
+                .with_signing(Signer::new(SigningKey::new("k1", "secret")))
+        };
+        let signed = IdResolver::new(&middleware()).resolve(None, None);
+        let id = IdResolver::new(&middleware()).resolve(Some(signed.as_str().as_bytes()), None);
+
+        assert!(id.is_verified());
+        assert_eq!(id.as_str(), signed.as_str());
+        assert_eq!(id.prefix(), Some("api"));
+        assert_eq!(id.core(), signed.core());
+        assert!(!id.core().contains('_'));
+    }
+}
@@ -0,0 +1,128 @@
+//! Sources of random bytes for the generators
+//!
+//! By default every ID draws its randomness from the operating system, which costs a
+//! system call per ID. With the `fast-rng` feature, each thread can instead run its
+//! own ChaCha20 CSPRNG seeded from the operating system, so most IDs are produced
+//! without leaving user space.
+#[cfg(feature = "fast-rng")]
+use std::cell::RefCell;
+
+#[cfg(feature = "fast-rng")]
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+#[cfg(feature = "fast-rng")]
+use rand_chacha::ChaCha20Rng;
+
+/// Number of bytes a thread-local generator produces before it is reseeded
+#[cfg(feature = "fast-rng")]
+pub(crate) const RESEED_THRESHOLD: usize = 64 * 1024;
+
+/// Source of the random bytes used by the generators
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Entropy {
+    /// Operating system RNG, queried for every ID
+    #[default]
+    Os,
+    /// Per-thread ChaCha20 generator, reseeded from the operating system
+    #[cfg(feature = "fast-rng")]
+    ThreadLocal,
+}
+
+impl Entropy {
+    /// Fill a buffer with random bytes from this source
+    pub(crate) fn fill(self, buf: &mut [u8]) {
+        match self {
+            Entropy::Os => fill_random(buf),
+            #[cfg(feature = "fast-rng")]
+            Entropy::ThreadLocal => fill_thread_local(buf),
+        }
+    }
+}
+
+/// Fill a buffer with random bytes from the operating system
+pub(crate) fn fill_random(buf: &mut [u8]) {
+    getrandom::fill(buf).expect("failed to obtain random bytes from the operating system");
+}
+
+/// ChaCha20 generator owned by one thread
+#[cfg(feature = "fast-rng")]
+struct ThreadRng {
+    rng: ChaCha20Rng,
+    /// Process the generator was seeded in
+    pid: u32,
+    /// Bytes left until the next reseed
+    remaining: usize,
+}
+
+#[cfg(feature = "fast-rng")]
+impl ThreadRng {
+    fn seeded(pid: u32) -> Self {
+        let mut seed = [0u8; 32];
+        fill_random(&mut seed);
+        Self {
+            rng: ChaCha20Rng::from_seed(seed),
+            pid,
+            remaining: RESEED_THRESHOLD,
+        }
+    }
+}
+
+#[cfg(feature = "fast-rng")]
+thread_local! {
+    static THREAD_RNG: RefCell<Option<ThreadRng>> = const { RefCell::new(None) };
+}
+
+/// Fill a buffer from the calling thread's generator
+///
+/// The generator is seeded lazily and reseeded from the operating system after
+/// [`RESEED_THRESHOLD`] bytes. It is also reseeded when the process ID changed, so a
+/// child process created with `fork` never repeats the parent's IDs.
+#[cfg(feature = "fast-rng")]
+fn fill_thread_local(buf: &mut [u8]) {
+    THREAD_RNG.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        let pid = std::process::id();
+        let rng = match &mut *slot {
+            Some(rng) if rng.pid == pid && rng.remaining >= buf.len() => rng,
+            slot => slot.insert(ThreadRng::seeded(pid)),
+        };
+        rng.rng.fill_bytes(buf);
+        rng.remaining -= buf.len().min(rng.remaining);
+    });
+}
+
+#[cfg(all(test, feature = "fast-rng"))]
+mod rng_tests {
+    use super::*;
+
+    fn remaining() -> usize {
+        THREAD_RNG.with(|cell| cell.borrow().as_ref().unwrap().remaining)
+    }
+
+    /// Test that the thread-local generator is reseeded after the threshold
+    #[test]
+    fn test_reseed() {
+        let mut buf = [0u8; 1024];
+        Entropy::ThreadLocal.fill(&mut buf);
+        assert_eq!(remaining(), RESEED_THRESHOLD - 1024);
+
+        for _ in 0..RESEED_THRESHOLD / 1024 - 1 {
+            Entropy::ThreadLocal.fill(&mut buf);
+        }
+        assert_eq!(remaining(), 0);
+
+        Entropy::ThreadLocal.fill(&mut buf);
+        assert_eq!(remaining(), RESEED_THRESHOLD - 1024);
+    }
+
+    /// Test that threads do not share a generator
+    #[test]
+    fn test_threads_differ() {
+        let sample = || {
+            let mut buf = [0u8; 32];
+            Entropy::ThreadLocal.fill(&mut buf);
+            buf
+        };
+        let other = std::thread::spawn(sample).join().unwrap();
+        assert_ne!(sample(), other);
+    }
+}
@@ -0,0 +1,283 @@
+//! HMAC-signed request IDs
+//!
+//! A signed ID has the form `<id>.<key-id>.<mac>`, where `<mac>` is a truncated
+//! HMAC-SHA256 over `<id>.<key-id>`, encoded as lowercase hex. Services that share
+//! the key can verify that an inbound ID was minted by their own edge, without
+//! trusting the network in between.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::hex::decode_hex;
+use crate::{Namespace, RequestID};
+
+/// Default number of MAC bytes appended to signed IDs (16 hex characters)
+pub const DEFAULT_MAC_LENGTH: usize = 8;
+
+/// Separator between the ID, the key ID and the MAC
+const SIGNATURE_SEPARATOR: char = '.';
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secret key used to sign request IDs
+///
+/// Each key has an ID that is embedded in signed request IDs, so verifiers can pick
+/// the right key while keys are being rotated.
+#[derive(Clone)]
+pub struct SigningKey {
+    id: String,
+    secret: Vec<u8>,
+}
+
+impl SigningKey {
+    /// Create a signing key
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Key ID embedded in signed request IDs, e.g. `"2025-06"`
+    /// * `secret` - Secret key material, ideally at least 32 random bytes
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is empty or contains characters other than ASCII letters,
+    /// digits, `-` and `_`.
+    pub fn new<I: Into<String>, S: Into<Vec<u8>>>(id: I, secret: S) -> Self {
+        let id = id.into();
+        if id.is_empty()
+            || !id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            panic!("Signing key ID must consist of ASCII letters, digits, '-' and '_'");
+        }
+
+        Self {
+            id,
+            secret: secret.into(),
+        }
+    }
+
+    /// Get the key ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Compute the HMAC over the ID and key ID
+    fn mac(&self, id: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(id.as_bytes());
+        mac.update(&[SIGNATURE_SEPARATOR as u8]);
+        mac.update(self.id.as_bytes());
+        mac
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the secret
+        f.debug_struct("SigningKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Signs generated request IDs and verifies inbound ones
+///
+/// New IDs are always signed with the current key. Additional verification keys
+/// are accepted when verifying, which allows rotating keys without rejecting IDs
+/// minted by services that have not been updated yet.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::{RequestIDMiddleware, Signer, SigningKey};
+///
+/// let signer = Signer::new(SigningKey::new("k2", "new secret"))
+///     .with_verification_key(SigningKey::new("k1", "old secret"));
+///
+/// let signed = signer.sign("550e8400-e29b-41d4-a716-446655440000");
+/// assert_eq!(signer.verify(&signed), Some("550e8400-e29b-41d4-a716-446655440000"));
+///
+/// let middleware = RequestIDMiddleware::new().with_signing(signer);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Signer {
+    current: SigningKey,
+    verification_keys: Vec<SigningKey>,
+    mac_length: usize,
+}
+
+impl Signer {
+    /// Create a signer that signs with the given key
+    pub fn new(key: SigningKey) -> Self {
+        Self {
+            current: key,
+            verification_keys: Vec::new(),
+            mac_length: DEFAULT_MAC_LENGTH,
+        }
+    }
+
+    /// Accept an additional key when verifying, e.g. the previous key during rotation
+    pub fn with_verification_key(mut self, key: SigningKey) -> Self {
+        self.verification_keys.push(key);
+        self
+    }
+
+    /// Set the number of MAC bytes appended to signed IDs
+    ///
+    /// # Arguments
+    ///
+    /// * `length` - Number of bytes, between 4 and 32
+    ///
+    /// # Panics
+    ///
+    /// Panics if `length` is outside of 4..=32.
+    pub fn with_mac_length(mut self, length: usize) -> Self {
+        if !(4..=32).contains(&length) {
+            panic!("MAC length must be between 4 and 32 bytes");
+        }
+        self.mac_length = length;
+        self
+    }
+
+    /// Get the number of characters signing adds to an ID
+    pub fn signature_length(&self) -> usize {
+        2 + self.current.id.len() + 2 * self.mac_length
+    }
+
+    /// Sign an ID with the current key
+    pub fn sign(&self, id: &str) -> String {
+        let tag = self.current.mac(id).finalize().into_bytes();
+
+        let mut signed = String::with_capacity(id.len() + self.signature_length());
+        signed.push_str(id);
+        signed.push(SIGNATURE_SEPARATOR);
+        signed.push_str(&self.current.id);
+        signed.push(SIGNATURE_SEPARATOR);
+        for byte in &tag[..self.mac_length] {
+            signed.push_str(&format!("{:02x}", byte));
+        }
+        signed
+    }
+
+    /// Verify a signed ID
+    ///
+    /// # Returns
+    ///
+    /// * `Some(&str)` - The ID without its signature, if the signature is valid
+    /// * `None` - If the ID is unsigned, signed with an unknown key or tampered with
+    pub fn verify<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let mut parts = signed.rsplitn(3, SIGNATURE_SEPARATOR);
+        let tag = parts.next()?;
+        // Tags are written in lowercase; other spellings of a valid tag are rejected
+        if tag.bytes().any(|b| b.is_ascii_uppercase()) {
+            return None;
+        }
+        let tag = decode_hex(tag)?;
+        let key_id = parts.next()?;
+        let id = parts.next()?;
+
+        if tag.len() != self.mac_length {
+            return None;
+        }
+
+        let key = std::iter::once(&self.current)
+            .chain(&self.verification_keys)
+            .find(|key| key.id == key_id)?;
+
+        // Constant-time comparison of the truncated tag
+        key.mac(id).verify_truncated_left(&tag).ok()?;
+        Some(id)
+    }
+
+    /// Sign a generated request ID, marking it as verified
+    pub(crate) fn sign_request_id(&self, mut id: RequestID) -> RequestID {
+        id.inner = self.sign(&id.inner).into();
+        id.verified = true;
+        id
+    }
+
+    /// Verify an inbound request ID, splitting it with the middleware's namespace
+    pub(crate) fn verify_request_id(
+        &self,
+        signed: &str,
+        namespace: &Namespace,
+    ) -> Option<RequestID> {
+        let unsigned_len = self.verify(signed)?.len();
+
+        let mut id = namespace.split(signed, unsigned_len);
+        id.verified = true;
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod signing_tests {
+    use super::*;
+
+    /// Test signing and verification round trip
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = Signer::new(SigningKey::new("k1", "secret"));
+        let signed = signer.sign("api_1234");
+
+        assert!(signed.starts_with("api_1234.k1."));
+        assert_eq!(signed.len(), "api_1234".len() + signer.signature_length());
+        assert_eq!(signer.verify(&signed), Some("api_1234"));
+
+        // Only the lowercase spelling of the tag verifies
+        let (id, tag) = signed.rsplit_once(SIGNATURE_SEPARATOR).unwrap();
+        let upper = format!("{}{}{}", id, SIGNATURE_SEPARATOR, tag.to_uppercase());
+        if upper != signed {
+            assert_eq!(signer.verify(&upper), None);
+        }
+    }
+
+    /// Test that tampered, unsigned and foreign IDs are rejected
+    #[test]
+    fn test_verify_rejects_forgeries() {
+        let signer = Signer::new(SigningKey::new("k1", "secret"));
+        let signed = signer.sign("1234");
+
+        assert_eq!(signer.verify("1234"), None);
+        assert_eq!(signer.verify(&signed.replacen("1234", "1235", 1)), None);
+        assert_eq!(signer.verify(&signed.replacen(".k1.", ".k2.", 1)), None);
+        assert_eq!(signer.verify(&signed[..signed.len() - 2]), None);
+
+        let other = Signer::new(SigningKey::new("k1", "other secret"));
+        assert_eq!(other.verify(&signed), None);
+    }
+
+    /// Test key rotation with verification keys
+    #[test]
+    fn test_key_rotation() {
+        let old = Signer::new(SigningKey::new("k1", "old"));
+        let new = Signer::new(SigningKey::new("k2", "new"))
+            .with_verification_key(SigningKey::new("k1", "old"));
+
+        let minted_before_rotation = old.sign("1234");
+        assert_eq!(new.verify(&minted_before_rotation), Some("1234"));
+        assert!(new.sign("1234").contains(".k2."));
+        assert_eq!(old.verify(&new.sign("1234")), None);
+    }
+
+    /// Test custom MAC lengths
+    #[test]
+    fn test_mac_length() {
+        let signer = Signer::new(SigningKey::new("k1", "secret")).with_mac_length(4);
+        let signed = signer.sign("1234");
+        assert_eq!(signed.rsplit('.').next().unwrap().len(), 8);
+        assert_eq!(signer.verify(&signed), Some("1234"));
+
+        let longer = Signer::new(SigningKey::new("k1", "secret")).with_mac_length(16);
+        assert_eq!(longer.verify(&signed), None);
+    }
+
+    /// Test that key secrets are not printed
+    #[test]
+    fn test_debug_hides_secret() {
+        let debug = format!("{:?}", SigningKey::new("k1", "top-secret"));
+        assert!(debug.contains("k1"));
+        assert!(!debug.contains("top-secret"));
+    }
+}
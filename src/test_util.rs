@@ -0,0 +1,351 @@
+//! Testing utilities for applications that use the middleware
+//!
+//! Available with the `test-util` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! actix-web-request-uuid = { version = "0.2", features = ["test-util"] }
+//! ```
+//!
+//! # Usage
+//!
+//! ```rust
+//! use actix_web::{test, web, App, HttpResponse};
+//! use actix_web_request_uuid::test_util::{assert_has_request_id, CapturedIds, IdFormat};
+//! use actix_web_request_uuid::RequestIDMiddleware;
+//!
+//! # actix_web::rt::System::new().block_on(async {
+//! let captured = CapturedIds::new();
+//! let app = test::init_service(
+//!     App::new()
+//!         .wrap(RequestIDMiddleware::new().with_ksuid().capture_ids(&captured))
+//!         .route("/", web::get().to(HttpResponse::Ok)),
+//! )
+//! .await;
+//!
+//! let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+//! let id = assert_has_request_id(&resp);
+//! assert!(IdFormat::Ksuid.matches(&id));
+//! assert_eq!(captured.last(), Some(id));
+//! # });
+//! ```
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use actix_http::Request;
+use actix_web::dev::ServiceRequest;
+use actix_web::test::TestRequest;
+use actix_web::{HttpMessage, HttpRequest};
+use uuid::Uuid;
+
+use crate::{Alphabet, Ksuid, RequestID, NANOID_ALPHABET, REQUEST_ID_HEADER};
+
+/// Extension trait for presetting the request ID of a [`TestRequest`]
+///
+/// The ID is stored in the request extensions, where the middleware picks it up
+/// instead of generating a new one.
+pub trait TestRequestExt {
+    /// Complete request creation with a preset request ID and return a `Request`
+    ///
+    /// # Usage
+    ///
+    /// ```rust
+    /// use actix_web::{test, web, App, HttpResponse};
+    /// use actix_web_request_uuid::test_util::{assert_has_request_id, TestRequestExt};
+    /// use actix_web_request_uuid::RequestIDMiddleware;
+    ///
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let app = test::init_service(
+    ///     App::new()
+    ///         .wrap(RequestIDMiddleware::new())
+    ///         .route("/", web::get().to(HttpResponse::Ok)),
+    /// )
+    /// .await;
+    ///
+    /// let req = test::TestRequest::get().uri("/").to_request_with_id("inbound-id");
+    /// let resp = test::call_service(&app, req).await;
+    /// assert_eq!(assert_has_request_id(&resp), "inbound-id");
+    /// # });
+    /// ```
+    fn to_request_with_id<T: Into<String>>(self, id: T) -> Request;
+
+    /// Complete request creation with a preset request ID and return a `ServiceRequest`
+    fn to_srv_request_with_id<T: Into<String>>(self, id: T) -> ServiceRequest;
+
+    /// Complete request creation with a preset request ID and return an `HttpRequest`
+    fn to_http_request_with_id<T: Into<String>>(self, id: T) -> HttpRequest;
+}
+
+impl TestRequestExt for TestRequest {
+    fn to_request_with_id<T: Into<String>>(self, id: T) -> Request {
+        let req = self.to_request();
+        req.extensions_mut().insert(RequestID::from_core(id.into()));
+        req
+    }
+
+    fn to_srv_request_with_id<T: Into<String>>(self, id: T) -> ServiceRequest {
+        let req = self.to_srv_request();
+        req.extensions_mut().insert(RequestID::from_core(id.into()));
+        req
+    }
+
+    fn to_http_request_with_id<T: Into<String>>(self, id: T) -> HttpRequest {
+        let req = self.to_http_request();
+        req.extensions_mut().insert(RequestID::from_core(id.into()));
+        req
+    }
+}
+
+/// Assert that a response carries a request ID in the default header and return it
+///
+/// # Panics
+///
+/// Panics if the `request-id` header is missing or is not a valid string.
+pub fn assert_has_request_id<B>(resp: &actix_web::dev::ServiceResponse<B>) -> String {
+    assert_has_request_id_header(resp, REQUEST_ID_HEADER)
+}
+
+/// Assert that a response carries a request ID in the given header and return it
+///
+/// # Panics
+///
+/// Panics if the header is missing or is not a valid string.
+pub fn assert_has_request_id_header<B>(
+    resp: &actix_web::dev::ServiceResponse<B>,
+    header_name: &str,
+) -> String {
+    let value = resp
+        .headers()
+        .get(header_name)
+        .unwrap_or_else(|| panic!("response has no `{}` header", header_name));
+    value
+        .to_str()
+        .unwrap_or_else(|_| panic!("`{}` header is not a valid string", header_name))
+        .to_string()
+}
+
+/// Expected format of a request ID, one per built-in generator
+///
+/// Matchers only check the shape of an ID, not its length, so they also accept
+/// IDs fitted with `with_id_length`, except for formats that define a length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdFormat {
+    /// Hyphenated UUID, as produced by default or with `with_full_uuid` or `with_uuid_v7`
+    Uuid,
+    /// UUID without hyphens, as produced by `with_simple_uuid`
+    SimpleUuid,
+    /// Characters of the given alphabet, as produced by `with_compact_id`
+    Compact(Alphabet),
+    /// Characters of the default nanoid alphabet, as produced by `with_nanoid`
+    Nanoid,
+    /// Characters of a custom alphabet, as produced by `with_custom_nanoid`
+    CustomAlphabet(&'static str),
+    /// KSUID, as produced by `with_ksuid`
+    Ksuid,
+    /// Decimal digits, as produced by `with_sequential_ids`
+    Sequential,
+}
+
+impl IdFormat {
+    /// Check whether a core ID matches this format
+    pub fn matches(&self, id: &str) -> bool {
+        match self {
+            IdFormat::Uuid => id.len() == 36 && Uuid::try_parse(id).is_ok(),
+            IdFormat::SimpleUuid => id.len() == 32 && Uuid::try_parse(id).is_ok(),
+            IdFormat::Compact(alphabet) => only_chars_of(id, alphabet.chars()),
+            IdFormat::Nanoid => only_chars_of(id, NANOID_ALPHABET),
+            IdFormat::CustomAlphabet(alphabet) => only_chars_of(id, alphabet),
+            IdFormat::Ksuid => id.parse::<Ksuid>().is_ok(),
+            IdFormat::Sequential => !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()),
+        }
+    }
+}
+
+/// Check that an ID is non-empty and only uses characters of `alphabet`
+fn only_chars_of(id: &str, alphabet: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| alphabet.contains(c))
+}
+
+/// Assert that an ID matches the expected format
+///
+/// # Panics
+///
+/// Panics if the ID does not match.
+pub fn assert_id_format(id: &str, format: IdFormat) {
+    assert!(
+        format.matches(id),
+        "request ID {:?} does not match format {:?}",
+        id,
+        format
+    );
+}
+
+/// Records every request ID issued by a middleware
+///
+/// Register with [`RequestIDMiddleware::capture_ids`]. Clones share the same
+/// record, so one handle can be kept by the test while the middleware holds another.
+///
+/// [`RequestIDMiddleware::capture_ids`]: crate::RequestIDMiddleware::capture_ids
+#[derive(Debug, Clone, Default)]
+pub struct CapturedIds {
+    ids: Arc<Mutex<Vec<String>>>,
+}
+
+impl CapturedIds {
+    /// Create an empty record
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get all captured IDs in the order they were issued
+    pub fn ids(&self) -> Vec<String> {
+        self.lock().clone()
+    }
+
+    /// Get the most recently issued ID
+    pub fn last(&self) -> Option<String> {
+        self.lock().last().cloned()
+    }
+
+    /// Get the number of captured IDs
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Check whether no IDs were captured
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Check whether an ID was issued, e.g. one found in log output
+    pub fn contains(&self, id: &str) -> bool {
+        self.lock().iter().any(|captured| captured == id)
+    }
+
+    /// Forget all captured IDs
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Record an issued ID
+    pub(crate) fn record(&self, id: &str) {
+        self.lock().push(id.to_string());
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<String>> {
+        // A panicking test must not hide the IDs from other assertions
+        self.ids.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test_util_tests {
+    use super::*;
+    use crate::{RequestIDMessage, RequestIDMiddleware};
+    use actix_web::{test, web, App, HttpResponse};
+
+    /// Test presetting an inbound ID on test requests
+    #[actix_rt::test]
+    async fn test_preset_request_id() {
+        let app =
+            test::init_service(App::new().wrap(RequestIDMiddleware::new()).service(
+                web::resource("/").to(|req_id: RequestID| async move {
+                    HttpResponse::Ok().body(req_id.to_string())
+                }),
+            ))
+            .await;
+
+        let req = test::TestRequest::with_uri("/").to_request_with_id("preset-id");
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(assert_has_request_id(&resp), "preset-id");
+        assert_eq!(test::read_body(resp).await, "preset-id");
+
+        let req = test::TestRequest::default().to_http_request_with_id("http-id");
+        assert_eq!(req.request_id().as_str(), "http-id");
+
+        let req = test::TestRequest::default().to_srv_request_with_id("srv-id");
+        assert_eq!(req.request_id().as_str(), "srv-id");
+    }
+
+    /// Test format matchers for every built-in generator
+    #[actix_rt::test]
+    async fn test_id_format_matchers() {
+        let cases = vec![
+            (RequestIDMiddleware::new(), IdFormat::Uuid),
+            (
+                RequestIDMiddleware::new().with_simple_uuid(),
+                IdFormat::SimpleUuid,
+            ),
+            (
+                RequestIDMiddleware::new().with_compact_id(Alphabet::Base58),
+                IdFormat::Compact(Alphabet::Base58),
+            ),
+            (RequestIDMiddleware::new().with_nanoid(), IdFormat::Nanoid),
+            (
+                RequestIDMiddleware::new().with_custom_nanoid("abc", 8),
+                IdFormat::CustomAlphabet("abc"),
+            ),
+            (RequestIDMiddleware::new().with_ksuid(), IdFormat::Ksuid),
+            (
+                RequestIDMiddleware::new().with_sequential_ids(),
+                IdFormat::Sequential,
+            ),
+        ];
+
+        for (middleware, format) in cases {
+            let app = test::init_service(
+                App::new()
+                    .wrap(middleware)
+                    .route("/", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+
+            let resp =
+                test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+            assert_id_format(&assert_has_request_id(&resp), format);
+        }
+
+        assert!(!IdFormat::Uuid.matches("550e8400e29b41d4a716446655440000"));
+        assert!(!IdFormat::Sequential.matches("00a1"));
+        assert!(!IdFormat::Ksuid.matches("not-a-ksuid"));
+    }
+
+    /// Test that captured IDs match the issued headers
+    #[actix_rt::test]
+    async fn test_captured_ids() {
+        let captured = CapturedIds::new();
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .with_prefix("api")
+                        .header_name("X-Request-ID")
+                        .capture_ids(&captured),
+                )
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let mut issued = Vec::new();
+        for _ in 0..3 {
+            let resp =
+                test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+            issued.push(assert_has_request_id_header(&resp, "X-Request-ID"));
+        }
+
+        assert_eq!(captured.ids(), issued);
+        assert!(captured.contains(&issued[1]));
+        assert_eq!(captured.len(), 3);
+
+        captured.clear();
+        assert!(captured.is_empty());
+    }
+
+    /// Test the panic message for missing request IDs
+    #[actix_rt::test]
+    #[should_panic(expected = "response has no `request-id` header")]
+    async fn test_assert_has_request_id_panics() {
+        let app = test::init_service(App::new().route("/", web::get().to(HttpResponse::Ok))).await;
+        let resp = test::call_service(&app, test::TestRequest::with_uri("/").to_request()).await;
+        assert_has_request_id(&resp);
+    }
+}
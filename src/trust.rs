@@ -0,0 +1,203 @@
+//! Networks trusted to send request IDs
+//!
+//! Propagation lets clients choose their own request ID. Behind a load balancer,
+//! restricting propagation to the balancer's addresses keeps clients from injecting
+//! IDs while still following IDs across internal hops.
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::ConfigError;
+
+/// IP network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`
+///
+/// A bare address such as `10.1.2.3` is a network of one address.
+///
+/// # Usage
+///
+/// ```rust
+/// use actix_web_request_uuid::IpCidr;
+///
+/// let network: IpCidr = "10.0.0.0/8".parse().unwrap();
+/// assert!(network.contains("10.20.30.40".parse().unwrap()));
+/// assert!(!network.contains("192.168.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpCidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    /// Create a network from an address and a prefix length
+    ///
+    /// Host bits of `address` are cleared, so `10.1.2.3/8` is the same network as
+    /// `10.0.0.0/8`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if `prefix_len` exceeds 32 for IPv4 or 128 for IPv6 addresses.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Self> {
+        let address = match address {
+            IpAddr::V4(v4) if prefix_len <= 32 => {
+                IpAddr::V4((u32::from(v4) & v4_mask(prefix_len)).into())
+            }
+            IpAddr::V6(v6) if prefix_len <= 128 => {
+                IpAddr::V6((u128::from(v6) & v6_mask(prefix_len)).into())
+            }
+            _ => return None,
+        };
+        Some(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    /// Get the network address
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// Get the prefix length
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Check whether `address` is part of this network
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) are matched against IPv4
+    /// networks.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                u32::from(address) & v4_mask(self.prefix_len) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(address) & v6_mask(self.prefix_len) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ConfigError::InvalidCidr(s.to_string());
+        let s = s.trim();
+
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => {
+                let address: IpAddr = address.parse().map_err(|_| invalid())?;
+                // Only plain decimal digits, `u8::from_str` also accepts a leading `+`
+                if prefix_len.is_empty() || !prefix_len.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                (address, prefix_len.parse().map_err(|_| invalid())?)
+            }
+            None => {
+                let address: IpAddr = s.parse().map_err(|_| invalid())?;
+                let prefix_len = if address.is_ipv4() { 32 } else { 128 };
+                (address, prefix_len)
+            }
+        };
+        Self::new(address, prefix_len).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Check whether a peer may send request IDs
+///
+/// Every peer is trusted if no networks are configured. Peers with an unknown
+/// address, e.g. on Unix sockets, are only trusted in that case.
+pub(crate) fn is_trusted(networks: &[IpCidr], peer: Option<SocketAddr>) -> bool {
+    if networks.is_empty() {
+        return true;
+    }
+    peer.is_some_and(|peer| networks.iter().any(|network| network.contains(peer.ip())))
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod trust_tests {
+    use super::*;
+
+    /// Test parsing of networks and bare addresses
+    #[test]
+    fn test_parse() {
+        let network: IpCidr = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(network.to_string(), "10.0.0.0/8");
+
+        let host: IpCidr = "192.168.1.10".parse().unwrap();
+        assert_eq!(host.prefix_len(), 32);
+
+        let v6: IpCidr = "fd00::/8".parse().unwrap();
+        assert_eq!(v6.to_string(), "fd00::/8");
+
+        let any: IpCidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("203.0.113.7".parse().unwrap()));
+
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "10.0.0.0/+8",
+        ] {
+            assert_eq!(
+                invalid.parse::<IpCidr>(),
+                Err(ConfigError::InvalidCidr(invalid.to_string())),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    /// Test network membership
+    #[test]
+    fn test_contains() {
+        let network: IpCidr = "10.0.0.0/8".parse().unwrap();
+        assert!(network.contains("10.255.0.1".parse().unwrap()));
+        assert!(network.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+        assert!(!network.contains("::1".parse().unwrap()));
+
+        let v6: IpCidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains("fd12:3456::1".parse().unwrap()));
+        assert!(!v6.contains("fe80::1".parse().unwrap()));
+    }
+
+    /// Test the trust decision for peers
+    #[test]
+    fn test_is_trusted() {
+        let peer: SocketAddr = "10.0.0.5:4000".parse().unwrap();
+        assert!(is_trusted(&[], Some(peer)));
+        assert!(is_trusted(&[], None));
+
+        let networks = ["10.0.0.0/8".parse().unwrap()];
+        assert!(is_trusted(&networks, Some(peer)));
+        assert!(!is_trusted(
+            &networks,
+            Some("192.0.2.1:4000".parse().unwrap())
+        ));
+        assert!(!is_trusted(&networks, None));
+    }
+}
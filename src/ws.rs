@@ -0,0 +1,411 @@
+//! Request IDs for `actix-ws` WebSocket sessions
+//!
+//! A WebSocket connection starts with an ordinary HTTP request, which the middleware
+//! assigns an ID. [`WsRequestID`] keeps that ID as the session ID and optionally derives a
+//! sub-ID `<session-id>:<seq>` for every message received on the connection.
+use actix_web::HttpRequest;
+use actix_ws::{
+    AggregatedMessage, AggregatedMessageStream, Item, Message, MessageStream, ProtocolError,
+};
+
+use crate::{RequestID, RequestIDMessage};
+
+/// Separator between the session ID and the message sequence number
+///
+/// Generated IDs and the `.` separated tags of signed IDs never contain `:`, so the
+/// session ID is everything before the last `:`, unless a custom alphabet or namespace
+/// includes it. A signed session ID keeps its tag intact, but the message ID as a whole
+/// does not verify.
+pub const WS_MESSAGE_ID_SEPARATOR: char = ':';
+
+/// Request IDs of a WebSocket session and its messages
+///
+/// Available with the `ws` feature. By default every frame is processed under the session
+/// ID, which is the ID of the upgrade request. With [`WsRequestID::with_message_ids`],
+/// every data message gets its own ID `<session-id>:<seq>`, numbered from 1. Fragments of
+/// one message share its ID, and control frames (ping, pong, close) use the session ID.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// use actix_web::{web, HttpRequest, HttpResponse};
+/// use actix_web_request_uuid::{spawn_with_request_id, WsRequestID};
+/// use actix_ws::Message;
+///
+/// async fn ws(req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+///     let (response, mut session, mut stream) = actix_ws::handle(&req, body)?;
+///     let mut ids = WsRequestID::from_request(&req).with_message_ids();
+///
+///     // The task keeps the session ID, e.g. for logs about the connection itself
+///     spawn_with_request_id(async move {
+///         while let Some((id, msg)) = ids.recv(&mut stream).await {
+///             let Ok(Message::Text(text)) = msg else { break };
+///             // `get_current_request_id()` returns "<session-id>:<seq>" in here
+///             id.scope(async { session.text(text).await }).await.ok();
+///         }
+///         let _ = session.close(None).await;
+///     });
+///
+///     Ok(response)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct WsRequestID {
+    session: RequestID,
+    message_ids: bool,
+    /// Sequence number of the last message
+    seq: u64,
+    /// ID of a fragmented message whose last fragment has not arrived yet
+    fragmented: Option<RequestID>,
+}
+
+impl WsRequestID {
+    /// Track the IDs of a session with the given session ID
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session ID, usually the ID of the upgrade request
+    pub fn new(session: RequestID) -> Self {
+        Self {
+            session,
+            message_ids: false,
+            seq: 0,
+            fragmented: None,
+        }
+    }
+
+    /// Track the IDs of the session opened by an upgrade request
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The upgrade request; its request ID becomes the session ID
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self::new(req.request_id())
+    }
+
+    /// Give every data message its own ID `<session-id>:<seq>`
+    pub fn with_message_ids(mut self) -> Self {
+        self.message_ids = true;
+        self
+    }
+
+    /// Get the session ID
+    pub fn session_id(&self) -> &RequestID {
+        &self.session
+    }
+
+    /// Get the number of message IDs issued so far
+    pub fn message_count(&self) -> u64 {
+        self.seq
+    }
+
+    /// Issue the ID for the next data message
+    ///
+    /// # Returns
+    ///
+    /// `<session-id>:<seq>` with per-message IDs, the session ID otherwise.
+    pub fn next_message_id(&mut self) -> RequestID {
+        if !self.message_ids {
+            return self.session.clone();
+        }
+        self.seq += 1;
+        RequestID::from_core(format!(
+            "{}{}{}",
+            self.session, WS_MESSAGE_ID_SEPARATOR, self.seq
+        ))
+    }
+
+    /// Get the ID under which a frame should be processed
+    ///
+    /// Call this once per received frame, in order, so the sequence numbers match the
+    /// messages.
+    pub fn message_id(&mut self, msg: &Message) -> RequestID {
+        match msg {
+            Message::Text(_) | Message::Binary(_) => self.next_message_id(),
+            Message::Continuation(Item::FirstText(_) | Item::FirstBinary(_)) => {
+                let id = self.next_message_id();
+                self.fragmented = Some(id.clone());
+                id
+            }
+            Message::Continuation(Item::Continue(_)) => self.fragment_id(),
+            Message::Continuation(Item::Last(_)) => {
+                let id = self.fragment_id();
+                self.fragmented = None;
+                id
+            }
+            Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Nop => {
+                self.session.clone()
+            }
+        }
+    }
+
+    /// Get the ID under which an aggregated message should be processed
+    ///
+    /// Like [`WsRequestID::message_id`], for streams created with
+    /// `MessageStream::aggregate_continuations`.
+    pub fn aggregated_message_id(&mut self, msg: &AggregatedMessage) -> RequestID {
+        match msg {
+            AggregatedMessage::Text(_) | AggregatedMessage::Binary(_) => self.next_message_id(),
+            AggregatedMessage::Ping(_)
+            | AggregatedMessage::Pong(_)
+            | AggregatedMessage::Close(_) => self.session.clone(),
+        }
+    }
+
+    /// Receive the next frame together with the ID to process it under
+    ///
+    /// # Returns
+    ///
+    /// * `Some((id, frame))` - The next frame; protocol errors are reported under the
+    ///   session ID
+    /// * `None` - If the client closed the connection
+    pub async fn recv(
+        &mut self,
+        stream: &mut MessageStream,
+    ) -> Option<(RequestID, Result<Message, ProtocolError>)> {
+        let msg = stream.recv().await?;
+        let id = match &msg {
+            Ok(msg) => self.message_id(msg),
+            Err(_) => self.session.clone(),
+        };
+        Some((id, msg))
+    }
+
+    /// Receive the next aggregated message together with the ID to process it under
+    ///
+    /// Like [`WsRequestID::recv`], for streams created with
+    /// `MessageStream::aggregate_continuations`.
+    pub async fn recv_aggregated(
+        &mut self,
+        stream: &mut AggregatedMessageStream,
+    ) -> Option<(RequestID, Result<AggregatedMessage, ProtocolError>)> {
+        let msg = stream.recv().await?;
+        let id = match &msg {
+            Ok(msg) => self.aggregated_message_id(msg),
+            Err(_) => self.session.clone(),
+        };
+        Some((id, msg))
+    }
+
+    /// ID of the fragmented message in progress
+    fn fragment_id(&mut self) -> RequestID {
+        match &self.fragmented {
+            Some(id) => id.clone(),
+            // A continuation without a first fragment; `MessageStream` reports these as errors
+            None => self.next_message_id(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod ws_tests {
+    use super::*;
+    use crate::{
+        get_current_request_id, spawn_with_request_id, RequestIDMiddleware, REQUEST_ID_HEADER,
+    };
+    use actix_web::web::Bytes;
+    use actix_web::{test, web, App, HttpResponse, HttpServer};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Encode a frame as sent by a client, masked with an all-zero key
+    fn client_frame(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() < 126);
+        let mut frame = vec![
+            opcode | if fin { 0x80 } else { 0 },
+            0x80 | payload.len() as u8,
+        ];
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Decode the text frames sent by the server
+    fn server_texts(mut body: &[u8]) -> Vec<String> {
+        let mut texts = Vec::new();
+        while body.len() >= 2 {
+            let (opcode, len) = (body[0] & 0x0f, body[1] as usize);
+            if opcode == 0x1 {
+                texts.push(String::from_utf8(body[2..2 + len].to_vec()).unwrap());
+            }
+            body = &body[2 + len..];
+        }
+        texts
+    }
+
+    async fn echo(req: HttpRequest, body: web::Payload) -> actix_web::Result<HttpResponse> {
+        let (response, mut session, mut stream) = actix_ws::handle(&req, body)?;
+        let mut ids = WsRequestID::from_request(&req);
+        if req.query_string() == "messages" {
+            ids = ids.with_message_ids();
+        }
+        spawn_with_request_id(async move {
+            let session_id = get_current_request_id().unwrap();
+            session.text(format!("open {}", session_id)).await.unwrap();
+            while let Some((id, msg)) = ids.recv(&mut stream).await {
+                let text = match msg {
+                    Ok(Message::Text(text)) => text.to_string(),
+                    Ok(Message::Continuation(Item::Last(text))) => {
+                        String::from_utf8(text.to_vec()).unwrap()
+                    }
+                    Ok(Message::Continuation(_)) => continue,
+                    Ok(Message::Ping(_)) => "ping".to_string(),
+                    _ => break,
+                };
+                let seen = id.scope(async { get_current_request_id().unwrap() });
+                session
+                    .text(format!("{} {}", seen.await, text))
+                    .await
+                    .unwrap();
+            }
+            let _ = session.close(None).await;
+        });
+        Ok(response)
+    }
+
+    async fn exchange(uri: &str, frames: &[Vec<u8>]) -> (String, Vec<String>) {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new())
+                .route("/ws", web::get().to(echo)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .set_payload(Bytes::from(frames.concat()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 101);
+        let session_id = resp.headers().get(REQUEST_ID_HEADER).unwrap();
+        let session_id = session_id.to_str().unwrap().to_string();
+        let body = test::read_body(resp).await;
+        (session_id, server_texts(&body))
+    }
+
+    /// Test that every frame is processed under the session ID by default
+    #[actix_rt::test]
+    async fn test_session_id() {
+        let frames = [
+            client_frame(0x1, true, b"hello"),
+            client_frame(0x1, true, b"world"),
+        ];
+        let (id, texts) = exchange("/ws", &frames).await;
+        assert_eq!(
+            texts,
+            vec![
+                format!("open {}", id),
+                format!("{} hello", id),
+                format!("{} world", id),
+            ]
+        );
+    }
+
+    /// Test that messages get sequential sub-IDs and fragments share one
+    #[actix_rt::test]
+    async fn test_message_ids() {
+        let frames = [
+            client_frame(0x1, true, b"hello"),
+            client_frame(0x9, true, b"ping"),
+            client_frame(0x1, false, b"frag"),
+            client_frame(0x0, true, b"mented"),
+            client_frame(0x1, true, b"world"),
+        ];
+        let (id, texts) = exchange("/ws?messages", &frames).await;
+        assert_eq!(
+            texts,
+            vec![
+                format!("open {}", id),
+                format!("{}:1 hello", id),
+                format!("{} ping", id),
+                format!("{}:2 mented", id),
+                format!("{}:3 world", id),
+            ]
+        );
+    }
+
+    /// Read the next frame sent by the server, which must be a text frame
+    async fn read_text(stream: &mut TcpStream) -> String {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[0] & 0x0f, 0x1);
+        let mut payload = vec![0; head[1] as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    /// Test a live connection, with one frame in flight at a time
+    #[actix_rt::test]
+    async fn test_live_connection() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = HttpServer::new(|| {
+            App::new()
+                .wrap(RequestIDMiddleware::new())
+                .route("/ws", web::get().to(echo))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let upgrade = format!(
+            "GET /ws?messages HTTP/1.1\r\nhost: {}\r\nupgrade: websocket\r\n\
+             connection: upgrade\r\nsec-websocket-version: 13\r\n\
+             sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            addr
+        );
+        stream.write_all(upgrade.as_bytes()).await.unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101"));
+        let id = head
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .find(|(name, _)| name.eq_ignore_ascii_case(REQUEST_ID_HEADER))
+            .map(|(_, value)| value.to_string())
+            .unwrap();
+
+        assert_eq!(read_text(&mut stream).await, format!("open {}", id));
+        for (frame, reply) in [
+            (client_frame(0x1, true, b"hello"), format!("{}:1 hello", id)),
+            (client_frame(0x9, true, b""), format!("{} ping", id)),
+            (client_frame(0x1, true, b"world"), format!("{}:2 world", id)),
+        ] {
+            stream.write_all(&frame).await.unwrap();
+            assert_eq!(read_text(&mut stream).await, reply);
+        }
+
+        // The session closes once the client did
+        let close = client_frame(0x8, true, b"");
+        stream.write_all(&close).await.unwrap();
+        let mut head = [0; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        assert_eq!(head, [0x88, 0]);
+        drop(stream);
+        handle.stop(true).await;
+    }
+
+    /// Test the IDs of aggregated messages and control frames
+    #[actix_rt::test]
+    async fn test_aggregated_message_id() {
+        let mut ids = WsRequestID::new(RequestID::from("session")).with_message_ids();
+        let text = AggregatedMessage::Text("hi".into());
+        let ping = AggregatedMessage::Ping(Bytes::new());
+        assert_eq!(ids.aggregated_message_id(&text).as_str(), "session:1");
+        assert_eq!(ids.aggregated_message_id(&ping).as_str(), "session");
+        assert_eq!(ids.aggregated_message_id(&text).as_str(), "session:2");
+        assert_eq!(ids.message_count(), 2);
+    }
+}